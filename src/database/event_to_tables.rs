@@ -5,40 +5,144 @@
 use anyhow::{anyhow, Result};
 use solabi::{
     abi::{EventDescriptor, Field},
+    value::ByteLength,
     ValueKind,
 };
+use std::{collections::HashSet, fmt::Debug};
 
 use super::{
     event_visitor::{visit_field, VisitKind},
-    keywords::KEYWORDS,
+    keywords::{KEYWORDS, POSTGRES_KEYWORDS},
 };
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct Tables<'a> {
     pub primary: Table<'a>,
     pub dynamic_arrays: Vec<Table<'a>>,
+    /// Per dynamic array the index of the dynamic array whose elements contain
+    /// it, `None` outside of dynamic arrays. Parents come before their
+    /// children.
+    pub array_parents: Vec<Option<usize>>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Table<'a> {
     /// The table name includes a sanitized version of the original event name.
     pub name: String,
     pub columns: Vec<Column<'a>>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Column<'a> {
     // leaf kind
     pub kind: &'a ValueKind,
     pub name: String,
+    /// Solidity type hint from the ABI like `contract IERC20`. Not used for the
+    /// SQL type but kept so that databases can record it.
+    pub internal_type: Option<&'a str>,
+}
+
+/// Turns event and field names into table and column names.
+pub trait NameSanitizer: Debug + Send + Sync {
+    /// Identifies the naming scheme. Databases store it so that the names of
+    /// existing tables don't change when the sanitizer does.
+    fn id(&self) -> &str;
+
+    /// Must return a valid SQL identifier: only ASCII alphanumerics and
+    /// underscore, not starting with a digit and not a keyword. Sanitizing an
+    /// already sanitized name must not change it.
+    fn sanitize(&self, name: &str) -> String;
+}
+
+/// Removes disallowed characters, prefixes an underscore if the name doesn't
+/// start with a letter and appends one if the name is a keyword.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultSanitizer;
+
+impl NameSanitizer for DefaultSanitizer {
+    fn id(&self) -> &str {
+        "default"
+    }
+
+    fn sanitize(&self, name: &str) -> String {
+        sanitize_name(name)
+    }
+}
+
+/// Like `DefaultSanitizer` but also appends an underscore to PostgreSQL's
+/// reserved words, like `user`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PostgresSanitizer;
+
+impl NameSanitizer for PostgresSanitizer {
+    fn id(&self) -> &str {
+        "postgres"
+    }
+
+    fn sanitize(&self, name: &str) -> String {
+        let mut result = sanitize_name(name);
+        let lowercase = result.to_ascii_lowercase();
+        if POSTGRES_KEYWORDS.iter().any(|word| *word == lowercase) {
+            result.push('_');
+        }
+        result
+    }
+}
+
+/// The event as it is stored: indexed fields of dynamic or composite kinds
+/// only have the keccak hash of their value in the log's topics, which is
+/// what decoding the log yields, so they are `bytes32` fields. Value kinds
+/// are decoded from their topic and stay as they are.
+pub fn stored_event(event: &EventDescriptor) -> EventDescriptor {
+    let mut event = event.clone();
+    for input in event.inputs.iter_mut().filter(|input| input.indexed) {
+        if matches!(
+            input.field.kind,
+            ValueKind::String
+                | ValueKind::Bytes
+                | ValueKind::Array(_)
+                | ValueKind::FixedArray(..)
+                | ValueKind::Tuple(_)
+        ) {
+            input.field.kind = ValueKind::FixedBytes(ByteLength::MAX);
+            input.field.components = None;
+        }
+    }
+    event
 }
 
-pub fn event_to_tables<'a>(name: &str, event: &'a EventDescriptor) -> Result<Tables<'a>> {
-    // TODO:
-    // - Handle indexed fields.
+/// Expects indexed fields to already be replaced by `stored_event`.
+///
+/// Columns are named after the path to their leaf: the names of the enclosing
+/// tuples, the leaf's name and the index of fixed array elements, followed by
+/// the column's index in the table. Values of dynamic arrays are named
+/// relative to the array's element.
+pub fn event_to_tables<'a>(
+    name: &str,
+    event: &'a EventDescriptor,
+    sanitizer: &dyn NameSanitizer,
+) -> Result<Tables<'a>> {
+    tables(name, event, sanitizer, true)
+}
 
+/// The tables of arak versions that named columns only after their leaf, for
+/// migrating them.
+pub fn legacy_event_to_tables<'a>(
+    name: &str,
+    event: &'a EventDescriptor,
+    sanitizer: &dyn NameSanitizer,
+) -> Result<Tables<'a>> {
+    tables(name, event, sanitizer, false)
+}
+
+fn tables<'a>(
+    name: &str,
+    event: &'a EventDescriptor,
+    sanitizer: &dyn NameSanitizer,
+    paths: bool,
+) -> Result<Tables<'a>> {
     // To avoid later confusion, force the user provided event name to be valid without change.
-    let sanitized = sanitize_name(name);
+    let sanitized = sanitizer.sanitize(name);
     if sanitized != name {
         return Err(anyhow!(
             "Event name '{name}' is not valid. Try '{sanitized}'."
@@ -53,13 +157,11 @@ pub fn event_to_tables<'a>(name: &str, event: &'a EventDescriptor) -> Result<Tab
         ));
     }
 
-    // Nested dynamic arrays are rare and hard to handle. The recursive visiting code and SQL schema becomes more complicated. Handle this properly later.
-    for input in &event.inputs {
-        if has_nested_dynamic_arrays(&input.field) {
-            return Err(anyhow!(
-                "Event contains a dynamic array inside of a dynamic array. This isn't supported."
-            ));
-        }
+    // SQLite refuses to create tables with this prefix.
+    if name.to_ascii_lowercase().starts_with("sqlite_") {
+        return Err(anyhow!(
+            "Event '{name}' starts with 'sqlite_', which is reserved."
+        ));
     }
 
     let mut primary = Table {
@@ -67,63 +169,149 @@ pub fn event_to_tables<'a>(name: &str, event: &'a EventDescriptor) -> Result<Tab
         columns: Default::default(),
     };
     let mut dynamic_arrays = Vec::new();
+    let mut array_parents = Vec::new();
     for input in &event.inputs {
-        handle_field_simple_names(&name, &mut primary, &mut dynamic_arrays, &input.field);
+        handle_field_simple_names(
+            &name,
+            &mut primary,
+            &mut dynamic_arrays,
+            &mut array_parents,
+            &input.field,
+            sanitizer,
+            paths,
+        );
+    }
+
+    // The names end up in SQL so custom sanitizers can't be trusted.
+    let names = std::iter::once(&primary)
+        .chain(&dynamic_arrays)
+        .flat_map(|table| {
+            std::iter::once(&table.name).chain(table.columns.iter().map(|column| &column.name))
+        });
+    for name in names {
+        if sanitize_name_(name) != *name || sanitizer.sanitize(name) != *name {
+            return Err(anyhow!(
+                "name sanitizer {} produced invalid name {name:?}",
+                sanitizer.id()
+            ));
+        }
     }
+
+    // Columns get their index as suffix so they can only collide if a custom
+    // sanitizer drops it.
+    for table in std::iter::once(&primary).chain(&dynamic_arrays) {
+        let mut names = HashSet::new();
+        for column in &table.columns {
+            if !names.insert(column.name.to_ascii_lowercase()) {
+                return Err(anyhow!(
+                    "name sanitizer {} produced duplicate column {:?} in table {:?}",
+                    sanitizer.id(),
+                    column.name,
+                    table.name
+                ));
+            }
+        }
+    }
+
     Ok(Tables {
         primary,
         dynamic_arrays,
+        array_parents,
     })
 }
 
-fn has_nested_dynamic_arrays(field: &Field) -> bool {
-    let mut level: u32 = 0;
-    let mut max_level: u32 = 0;
-    let mut visitor = |visit: VisitKind| match visit {
-        VisitKind::ArrayStart(_) => {
-            level += 1;
-            max_level = std::cmp::max(max_level, level);
+/// The names of the field's leaves in the order of `visit_field`, see
+/// `event_to_tables`. Without `paths` only the leaf's own name.
+fn leaf_names(field: &Field, paths: bool) -> Vec<String> {
+    fn visit(
+        kind: &ValueKind,
+        name: &str,
+        components: &[Field],
+        path: &str,
+        paths: bool,
+        names: &mut Vec<String>,
+    ) {
+        let join = |name: &str| match (path, name) {
+            (path, "") => path.to_string(),
+            ("", name) => name.to_string(),
+            (path, name) => format!("{path}_{name}"),
+        };
+        match kind {
+            ValueKind::Tuple(kinds) => {
+                let path = if paths { join(name) } else { String::new() };
+                for (kind, field) in kinds.iter().zip(components) {
+                    let components = field.components.as_deref().unwrap_or_default();
+                    visit(kind, &field.name, components, &path, paths, names);
+                }
+            }
+            ValueKind::FixedArray(len, kind) => {
+                for i in 0..*len {
+                    let name = match (paths, name) {
+                        (false, name) => name.to_string(),
+                        (true, "") => format!("field_{i}"),
+                        (true, name) => format!("{name}_{i}"),
+                    };
+                    visit(kind, &name, components, path, paths, names);
+                }
+            }
+            // The array's table is named after it.
+            ValueKind::Array(kind) => {
+                let name = if matches!(**kind, ValueKind::Tuple(_)) {
+                    ""
+                } else {
+                    name
+                };
+                visit(kind, name, components, "", paths, names);
+            }
+            _ => names.push(match join(name) {
+                name if name.is_empty() => "field".to_string(),
+                name => name,
+            }),
         }
-        VisitKind::ArrayEnd => level -= 1,
-        VisitKind::TupleStart(_)
-        | VisitKind::TupleEnd
-        | VisitKind::FixedArrayStart(..)
-        | VisitKind::FixedArrayEnd
-        | VisitKind::Leaf(..) => (),
-    };
-    visit_field(&mut visitor, field);
-    max_level > 1
+    }
+
+    let mut names = Vec::new();
+    let components = field.components.as_deref().unwrap_or_default();
+    visit(&field.kind, &field.name, components, "", paths, &mut names);
+    names
 }
 
 fn handle_field_simple_names<'a>(
     event_name: &str,
     primary: &mut Table<'a>,
     dynamic_arrays: &mut Vec<Table<'a>>,
+    array_parents: &mut Vec<Option<usize>>,
     field: &'a Field,
+    sanitizer: &dyn NameSanitizer,
+    paths: bool,
 ) {
-    let mut dynamic_array: Option<usize> = None;
+    let mut names = leaf_names(field, paths).into_iter();
+    // The dynamic arrays being visited, innermost last.
+    let mut dynamic_array: Vec<usize> = Vec::new();
     let mut visitor = move |value: VisitKind<'a>| match value {
         VisitKind::ArrayStart(name) => {
             let index = dynamic_arrays.len();
-            dynamic_array = Some(index);
+            array_parents.push(dynamic_array.last().copied());
+            dynamic_array.push(index);
             let name = if name.is_empty() { "array" } else { name };
             dynamic_arrays.push(Table {
-                name: sanitize_name(&format!("{event_name}_{name}_{index}")),
+                name: sanitizer.sanitize(&format!("{event_name}_{name}_{index}")),
                 columns: Default::default(),
             });
         }
         VisitKind::ArrayEnd => {
-            dynamic_array = None;
+            dynamic_array.pop();
         }
-        VisitKind::Leaf(kind, name) => {
-            let table = match dynamic_array {
-                Some(index) => &mut dynamic_arrays[index],
+        VisitKind::Leaf(kind, _, internal_type) => {
+            let table = match dynamic_array.last() {
+                Some(&index) => &mut dynamic_arrays[index],
                 None => primary,
             };
-            let name = if name.is_empty() { "field" } else { name };
+            let name = names.next().unwrap();
             table.columns.push(Column {
                 kind,
-                name: sanitize_name(&format!("{name}_{}", table.columns.len())),
+                name: sanitizer.sanitize(&format!("{name}_{}", table.columns.len())),
+                internal_type,
             });
         }
         _ => (),
@@ -140,7 +328,8 @@ fn sanitize_name(name: &str) -> String {
 fn sanitize_name_(name: &str) -> String {
     let is_allowed_character = |c: char| c.is_ascii_alphanumeric() || c == '_';
     let mut result: String = name.chars().filter(|c| is_allowed_character(*c)).collect();
-    if result.is_empty() || !is_allowed_character(result.chars().next().unwrap()) {
+    // Identifiers can't start with a digit.
+    if !result.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
         result.insert(0, '_');
     }
     let is_keyword = |s: &str| {
@@ -156,6 +345,7 @@ fn sanitize_name_(name: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use solabi::{abi::EventField, value::BitWidth};
     use ValueKind as VK;
 
     /// Helper type to make expected tables struct terser to define in tests.
@@ -168,6 +358,7 @@ mod tests {
                 .map(|(kind, name)| Column {
                     kind,
                     name: name.to_string(),
+                    internal_type: None,
                 })
                 .collect()
         }
@@ -186,13 +377,14 @@ mod tests {
                     columns: columns(columns_),
                 })
                 .collect(),
+            array_parents: vec![None; rest.len()],
         }
     }
 
     fn assert_tables(event: &str, expected: TestTables) {
         let expected = tables(expected);
         let event = EventDescriptor::parse_declaration(event).unwrap();
-        let tables = event_to_tables("event", &event).unwrap();
+        let tables = event_to_tables("event", &event, &DefaultSanitizer).unwrap();
         assert_eq!(
             tables, expected,
             "actual: {tables:#?} !=\nexpected: {expected:#?}"
@@ -261,8 +453,8 @@ event Event(
             "event",
             &[
                 (&VK::Bool, "b0_0"),
-                (&VK::Bool, "b0_1"),
-                (&VK::Bool, "b1_2"),
+                (&VK::Bool, "my_bools_b0_1"),
+                (&VK::Bool, "my_bools_b1_2"),
                 (&VK::Address, "a0_3"),
             ],
         )];
@@ -284,8 +476,8 @@ event Event(
             &[
                 (&VK::Bool, "b0_0"),
                 (&VK::Bool, "b1_1"),
-                (&VK::Bool, "foo_2"),
-                (&VK::Bool, "foo_3"),
+                (&VK::Bool, "foo_0_2"),
+                (&VK::Bool, "foo_1_3"),
                 (&VK::Address, "a0_4"),
             ],
         )];
@@ -324,6 +516,159 @@ event Event(
         assert_tables(event, expected);
     }
 
+    #[test]
+    fn tuple_with_dynamic_array() {
+        let event = r#"
+event Event(
+    (address id, bytes[] data, bool flag) t0,
+    address a0
+  )
+"#;
+        let expected: TestTables = &[
+            (
+                "event",
+                &[
+                    (&VK::Address, "t0_id_0"),
+                    (&VK::Bool, "t0_flag_1"),
+                    (&VK::Address, "a0_2"),
+                ],
+            ),
+            ("event_data_0", &[(&VK::Bytes, "data_0")]),
+        ];
+        assert_tables(event, expected);
+    }
+
+    #[derive(Debug)]
+    struct Uppercase;
+
+    impl NameSanitizer for Uppercase {
+        fn id(&self) -> &str {
+            "uppercase"
+        }
+
+        fn sanitize(&self, name: &str) -> String {
+            sanitize_name(name).to_ascii_uppercase()
+        }
+    }
+
+    #[test]
+    fn custom_sanitizer() {
+        let event = EventDescriptor::parse_declaration("event Event(bool b, bool[] c)").unwrap();
+        let tables = event_to_tables("EVENT", &event, &Uppercase).unwrap();
+        assert_eq!(tables.primary.name, "EVENT");
+        assert_eq!(tables.primary.columns[0].name, "B_0");
+        assert_eq!(tables.dynamic_arrays[0].name, "EVENT_C_0");
+        assert_eq!(tables.dynamic_arrays[0].columns[0].name, "C_0");
+
+        // The event name has to be valid as is.
+        assert!(event_to_tables("event", &event, &Uppercase).is_err());
+    }
+
+    #[test]
+    fn invalid_custom_sanitizer() {
+        #[derive(Debug)]
+        struct Quote;
+
+        impl NameSanitizer for Quote {
+            fn id(&self) -> &str {
+                "quote"
+            }
+
+            fn sanitize(&self, name: &str) -> String {
+                format!("{name}\"")
+            }
+        }
+
+        let event = EventDescriptor::parse_declaration("event Event(bool b)").unwrap();
+        assert!(event_to_tables("event\"", &event, &Quote).is_err());
+    }
+
+    #[test]
+    fn sanitize_hostile_names() {
+        for (name, expected) in [
+            ("", "_"),
+            ("1", "_1"),
+            ("1abc", "_1abc"),
+            ("a\"; DROP TABLE event; --", "aDROPTABLEevent"),
+            ("a'b", "ab"),
+            ("--", "_"),
+            ("/* comment */", "comment"),
+            ("[x]", "x"),
+            ("`x`", "x"),
+            ("é🦀x", "x"),
+            ("select", "select_"),
+            ("SELECT", "SELECT_"),
+            ("a b\nc\0d", "abcd"),
+        ] {
+            assert_eq!(sanitize_name(name), expected, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn odd_field_names() {
+        let field = |name: &str| EventField {
+            field: Field {
+                name: name.to_string(),
+                kind: VK::Bool,
+                components: None,
+                internal_type: None,
+            },
+            indexed: false,
+        };
+        let event = EventDescriptor {
+            name: "Event".to_string(),
+            inputs: vec![field("123"), field("!!!"), field(""), field("_")],
+            anonymous: false,
+        };
+        let tables = event_to_tables("event", &event, &DefaultSanitizer).unwrap();
+        let names: Vec<_> = tables
+            .primary
+            .columns
+            .iter()
+            .map(|column| column.name.as_str())
+            .collect();
+        assert_eq!(names, ["_123_0", "_1", "field_2", "__3"]);
+
+        #[derive(Debug)]
+        struct NoDigits;
+
+        impl NameSanitizer for NoDigits {
+            fn id(&self) -> &str {
+                "no_digits"
+            }
+
+            fn sanitize(&self, name: &str) -> String {
+                let name: String = name.chars().filter(|c| !c.is_ascii_digit()).collect();
+                sanitize_name(&name)
+            }
+        }
+
+        let err = event_to_tables("event", &event, &NoDigits).unwrap_err();
+        assert!(err.to_string().contains("duplicate column"), "{err}");
+    }
+
+    #[test]
+    fn hostile_event_names() {
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        for name in [
+            "",
+            "1",
+            "event; DROP TABLE blocks",
+            "event--",
+            "event\"",
+            "évent",
+            "select",
+            "_event",
+            "sqlite_master",
+            "SQLITE_event",
+        ] {
+            assert!(
+                event_to_tables(name, &event, &DefaultSanitizer).is_err(),
+                "{name:?}"
+            );
+        }
+    }
+
     #[test]
     fn nested_tuples() {
         let event = r#"
@@ -343,10 +688,10 @@ event Event(
             "event",
             &[
                 (&VK::Bool, "b0_0"),
-                (&VK::Bool, "b0_1"),
-                (&VK::Bool, "field_2"),
-                (&VK::Bool, "field_3"),
-                (&VK::Bool, "b1_4"),
+                (&VK::Bool, "outer_b0_1"),
+                (&VK::Bool, "outer_inner_2"),
+                (&VK::Bool, "outer_inner_3"),
+                (&VK::Bool, "outer_b1_4"),
             ],
         )];
         assert_tables(event, expected);
@@ -362,12 +707,12 @@ event Event(
         let expected: TestTables = &[(
             "event",
             &[
-                (&VK::Bool, "b_0"),
-                (&VK::Bool, "b_1"),
-                (&VK::Bool, "b_2"),
-                (&VK::Bool, "b_3"),
-                (&VK::Bool, "b_4"),
-                (&VK::Bool, "b_5"),
+                (&VK::Bool, "b_0_0_0"),
+                (&VK::Bool, "b_0_1_1"),
+                (&VK::Bool, "b_1_0_2"),
+                (&VK::Bool, "b_1_1_3"),
+                (&VK::Bool, "b_2_0_4"),
+                (&VK::Bool, "b_2_1_5"),
             ],
         )];
         assert_tables(event, expected);
@@ -383,14 +728,81 @@ event Event(
         let expected: TestTables = &[(
             "event",
             &[
-                (&VK::Bool, "field_0"),
-                (&VK::Bool, "b1_1"),
-                (&VK::Bool, "b2_2"),
-                (&VK::Bool, "field_3"),
-                (&VK::Bool, "b1_4"),
-                (&VK::Bool, "b2_5"),
+                (&VK::Bool, "array0_0_0_0"),
+                (&VK::Bool, "array0_0_0_b1_1"),
+                (&VK::Bool, "array0_0_0_inner_tuple_b2_2"),
+                (&VK::Bool, "array0_1_0_3"),
+                (&VK::Bool, "array0_1_0_b1_4"),
+                (&VK::Bool, "array0_1_0_inner_tuple_b2_5"),
             ],
         )];
         assert_tables(event, expected);
     }
+
+    #[test]
+    fn indexed_fields() {
+        let event = EventDescriptor::parse_declaration(
+            "event Event(address indexed from, string indexed name, uint256[] indexed ids, \
+             string note)",
+        )
+        .unwrap();
+        let event = stored_event(&event);
+        let hash = VK::FixedBytes(ByteLength::MAX);
+        let expected: TestTables = &[(
+            "event",
+            &[
+                (&VK::Address, "from_0"),
+                (&hash, "name_1"),
+                (&hash, "ids_2"),
+                (&VK::String, "note_3"),
+            ],
+        )];
+        assert_eq!(
+            event_to_tables("event", &event, &DefaultSanitizer).unwrap(),
+            tables(expected)
+        );
+    }
+
+    #[test]
+    fn nested_dynamic_arrays() {
+        let event = EventDescriptor::parse_declaration(
+            "event Event(string[][] names, (uint8 a, bool[] flags)[] xs)",
+        )
+        .unwrap();
+        let uint8 = VK::Uint(BitWidth::new(8).unwrap());
+        let expected: TestTables = &[
+            ("event", &[]),
+            ("event_names_0", &[]),
+            ("event_names_1", &[(&VK::String, "names_0")]),
+            ("event_xs_2", &[(&uint8, "a_0")]),
+            ("event_flags_3", &[(&VK::Bool, "flags_0")]),
+        ];
+        let mut expected = tables(expected);
+        expected.array_parents = vec![None, Some(0), None, Some(2)];
+        assert_eq!(
+            event_to_tables("event", &event, &DefaultSanitizer).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn postgres_sanitizer() {
+        let event = EventDescriptor::parse_declaration("event Event(uint256[] only)").unwrap();
+        // Reserved in PostgreSQL but not in SQLite.
+        assert!(event_to_tables("user", &event, &PostgresSanitizer).is_err());
+        assert_eq!(
+            event_to_tables("user", &event, &DefaultSanitizer)
+                .unwrap()
+                .primary
+                .name,
+            "user"
+        );
+        assert_eq!(PostgresSanitizer.sanitize("User"), "User_");
+        assert_eq!(PostgresSanitizer.sanitize("user_"), "user_");
+        assert_eq!(PostgresSanitizer.sanitize("select"), "select_");
+        assert_eq!(PostgresSanitizer.sanitize("Array"), "Array_");
+
+        let tables = event_to_tables("event", &event, &PostgresSanitizer).unwrap();
+        assert_eq!(tables.dynamic_arrays[0].name, "event_only_0");
+    }
 }
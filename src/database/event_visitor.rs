@@ -14,6 +14,8 @@ use solabi::{
 /// - For named values it is the name . Examples: `bool my_bool`, `bool[] my_bools`
 /// - For anonymous values it is empty. Example: `bool`
 /// - For fixed arrays and dynamic arrays, the name of the array if forwarded into the inner types. When a tuple is reached the forwarded name is dropped.
+///
+/// Leaves additionally carry the `internal_type` of their field if the ABI specified one (for example `contract IERC20` or `enum Status`). For array elements the trailing array suffix is removed.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum VisitKind<'a> {
     ArrayStart(&'a str),
@@ -22,7 +24,7 @@ pub enum VisitKind<'a> {
     TupleEnd,
     FixedArrayStart(usize, &'a str),
     FixedArrayEnd,
-    Leaf(&'a ValueKind, &'a str),
+    Leaf(&'a ValueKind, &'a str, Option<&'a str>),
 }
 
 pub fn visit_field<'a>(visitor: &mut impl FnMut(VisitKind<'a>), field: &'a Field) {
@@ -31,6 +33,7 @@ pub fn visit_field<'a>(visitor: &mut impl FnMut(VisitKind<'a>), field: &'a Field
         &field.kind,
         &field.name,
         field.components.as_deref().unwrap_or_default(),
+        field.internal_type.as_deref(),
     );
 }
 
@@ -39,6 +42,7 @@ pub fn visit_kind<'a>(
     kind: &'a ValueKind,
     name: &'a str,
     components: &'a [Field],
+    internal_type: Option<&'a str>,
 ) {
     match kind {
         ValueKind::Tuple(values) => {
@@ -50,6 +54,7 @@ pub fn visit_kind<'a>(
                     kind,
                     &field.name,
                     field.components.as_deref().unwrap_or_default(),
+                    field.internal_type.as_deref(),
                 );
             }
             visitor(VisitKind::TupleEnd);
@@ -57,22 +62,42 @@ pub fn visit_kind<'a>(
         ValueKind::FixedArray(length, kind) => {
             visitor(VisitKind::FixedArrayStart(*length, name));
             for _ in 0..*length {
-                visit_kind(visitor, kind, name, components);
+                visit_kind(
+                    visitor,
+                    kind,
+                    name,
+                    components,
+                    internal_type.map(element_type),
+                );
             }
             visitor(VisitKind::FixedArrayEnd);
         }
         ValueKind::Array(value) => {
             visitor(VisitKind::ArrayStart(name));
-            visit_kind(visitor, value, name, components);
+            visit_kind(
+                visitor,
+                value,
+                name,
+                components,
+                internal_type.map(element_type),
+            );
             visitor(VisitKind::ArrayEnd);
         }
         value => {
             assert!(components.is_empty());
-            visitor(VisitKind::Leaf(value, name));
+            visitor(VisitKind::Leaf(value, name, internal_type));
         }
     }
 }
 
+/// `uint8[2][]` -> `uint8[2]`
+fn element_type(internal_type: &str) -> &str {
+    match internal_type.rfind('[') {
+        Some(index) => &internal_type[..index],
+        None => internal_type,
+    }
+}
+
 pub enum VisitValue<'a> {
     ArrayStart(usize),
     ArrayEnd,
@@ -132,7 +157,7 @@ mod tests {
         let field = "bool b0";
         let field = dbg!(parse_field(field));
         let visits = collect_visits(&field);
-        let expected = &[VisitKind::Leaf(&ValueKind::Bool, "b0")];
+        let expected = &[VisitKind::Leaf(&ValueKind::Bool, "b0", None)];
         assert_eq!(&visits, expected);
     }
 
@@ -143,8 +168,8 @@ mod tests {
         let visits = collect_visits(&field);
         let expected = &[
             VisitKind::TupleStart(""),
-            VisitKind::Leaf(&ValueKind::Bool, "b0"),
-            VisitKind::Leaf(&ValueKind::Bool, ""),
+            VisitKind::Leaf(&ValueKind::Bool, "b0", None),
+            VisitKind::Leaf(&ValueKind::Bool, "", None),
             VisitKind::TupleEnd,
         ];
         assert_eq!(&visits, expected);
@@ -157,8 +182,8 @@ mod tests {
         let visits = collect_visits(&field);
         let expected = &[
             VisitKind::TupleStart("tuple0"),
-            VisitKind::Leaf(&ValueKind::Bool, "b0"),
-            VisitKind::Leaf(&ValueKind::Bool, ""),
+            VisitKind::Leaf(&ValueKind::Bool, "b0", None),
+            VisitKind::Leaf(&ValueKind::Bool, "", None),
             VisitKind::TupleEnd,
         ];
         assert_eq!(&visits, expected);
@@ -172,17 +197,31 @@ mod tests {
         let expected = &[
             VisitKind::FixedArrayStart(3, "a0"),
             VisitKind::FixedArrayStart(2, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
             VisitKind::FixedArrayEnd,
             VisitKind::FixedArrayStart(2, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
             VisitKind::FixedArrayEnd,
             VisitKind::FixedArrayStart(2, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "a0"),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
+            VisitKind::Leaf(&ValueKind::Bool, "a0", None),
+            VisitKind::FixedArrayEnd,
             VisitKind::FixedArrayEnd,
+        ];
+        assert_eq!(&visits, expected);
+    }
+
+    #[test]
+    fn internal_type() {
+        let mut field = parse_field("address[2] tokens");
+        field.internal_type = Some("contract IERC20[2]".to_string());
+        let visits = collect_visits(&field);
+        let expected = &[
+            VisitKind::FixedArrayStart(2, "tokens"),
+            VisitKind::Leaf(&ValueKind::Address, "tokens", Some("contract IERC20")),
+            VisitKind::Leaf(&ValueKind::Address, "tokens", Some("contract IERC20")),
             VisitKind::FixedArrayEnd,
         ];
         assert_eq!(&visits, expected);
@@ -197,9 +236,9 @@ mod tests {
             VisitKind::FixedArrayStart(1, "a0"),
             VisitKind::FixedArrayStart(1, "a0"),
             VisitKind::TupleStart("a0"),
-            VisitKind::Leaf(&ValueKind::Bool, "b0"),
+            VisitKind::Leaf(&ValueKind::Bool, "b0", None),
             VisitKind::TupleStart("t0"),
-            VisitKind::Leaf(&ValueKind::Bool, "b1"),
+            VisitKind::Leaf(&ValueKind::Bool, "b1", None),
             VisitKind::TupleEnd,
             VisitKind::TupleEnd,
             VisitKind::FixedArrayEnd,
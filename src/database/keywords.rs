@@ -148,3 +148,44 @@ pub const KEYWORDS: &[&str] = &[
     "with",
     "without",
 ];
+
+/// PostgreSQL reserved words that `KEYWORDS` doesn't contain. Only the
+/// PostgreSQL backend avoids them so that SQLite table names don't change.
+pub const POSTGRES_KEYWORDS: &[&str] = &[
+    "analyse",
+    "any",
+    "array",
+    "asymmetric",
+    "authorization",
+    "binary",
+    "both",
+    "collation",
+    "concurrently",
+    "current_catalog",
+    "current_role",
+    "current_schema",
+    "current_user",
+    "false",
+    "fetch",
+    "freeze",
+    "grant",
+    "ilike",
+    "lateral",
+    "leading",
+    "localtime",
+    "localtimestamp",
+    "only",
+    "overlaps",
+    "placing",
+    "session_user",
+    "similar",
+    "some",
+    "symmetric",
+    "system_user",
+    "tablesample",
+    "trailing",
+    "true",
+    "user",
+    "variadic",
+    "verbose",
+];
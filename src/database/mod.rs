@@ -7,14 +7,23 @@ mod sqlite;
 
 use {
     anyhow::Result,
-    futures::future::BoxFuture,
+    futures::{future::BoxFuture, FutureExt},
     solabi::{abi::EventDescriptor, ethprim::Address, value::Value},
-    std::time::SystemTime,
+    std::{borrow::Cow, fmt, time::SystemTime},
 };
 
 use solabi::Digest;
 
-pub use self::{postgres::Postgres, sqlite::Sqlite};
+pub use self::{
+    event_to_tables::{DefaultSanitizer, NameSanitizer},
+    postgres::Postgres,
+    sqlite::{
+        Clock, CursorWarning, EventOptions, HistogramMetrics, Inconsistency, IntegerEncoding,
+        Metrics, OnCommit, OnConflict, Options as SqliteOptions, Order, Profile, QueryFilter,
+        ReorgEntry, Row, SharedSqlite, SqlScript, Sqlite, TypeMapper, UnknownEvent,
+        SUSPICIOUS_CURSOR_GAP,
+    },
+};
 
 /// Block indexing information.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
@@ -48,14 +57,89 @@ pub struct Uncle<'a> {
 }
 
 /// An emitted event log.
-#[derive(Debug, Default)]
+///
+/// The event name is borrowed when inserting logs and owned when logs outlive
+/// it, like logs read back from a database.
+#[derive(Clone, Debug, Default)]
 pub struct Log<'a> {
-    pub event: &'a str,
+    pub event: Cow<'a, str>,
     pub block_number: u64,
     pub log_index: u64,
     pub transaction_index: u64,
     pub address: Address,
+    /// In the order of the event's inputs, with indexed and non-indexed
+    /// fields interleaved like in the signature. Indexed strings, bytes,
+    /// arrays and tuples are the `bytes32` hash from their topic.
     pub fields: Vec<Value>,
+    /// Only stored by databases configured to keep logs of several chains in
+    /// the same tables.
+    pub chain_id: Option<u64>,
+    /// The undecoded log data and topics. Only stored by databases
+    /// configured to keep raw logs.
+    pub data: Vec<u8>,
+    pub topics: Vec<[u8; 32]>,
+    /// Nullable columns whose values are absent from this log, see
+    /// `sqlite::EventOptions::nullable`. Their values in `fields` are read
+    /// back as zero. Only stored by SQLite.
+    pub nulls: Vec<String>,
+}
+
+impl<'a> Log<'a> {
+    /// Builds a log with fluent setters instead of a struct literal. Fields
+    /// that aren't set keep their default.
+    pub fn builder() -> LogBuilder<'a> {
+        LogBuilder(Log::default())
+    }
+}
+
+impl Log<'_> {
+    /// Takes ownership of the event name.
+    pub fn into_owned(self) -> Log<'static> {
+        Log {
+            event: Cow::Owned(self.event.into_owned()),
+            ..self
+        }
+    }
+}
+
+/// Builder of `Log`, see `Log::builder`.
+#[derive(Debug, Default)]
+pub struct LogBuilder<'a>(Log<'a>);
+
+impl<'a> LogBuilder<'a> {
+    pub fn event(mut self, event: impl Into<Cow<'a, str>>) -> Self {
+        self.0.event = event.into();
+        self
+    }
+
+    pub fn block_number(mut self, block_number: u64) -> Self {
+        self.0.block_number = block_number;
+        self
+    }
+
+    pub fn log_index(mut self, log_index: u64) -> Self {
+        self.0.log_index = log_index;
+        self
+    }
+
+    pub fn transaction_index(mut self, transaction_index: u64) -> Self {
+        self.0.transaction_index = transaction_index;
+        self
+    }
+
+    pub fn address(mut self, address: Address) -> Self {
+        self.0.address = address;
+        self
+    }
+
+    pub fn fields(mut self, fields: Vec<Value>) -> Self {
+        self.0.fields = fields;
+        self
+    }
+
+    pub fn build(self) -> Log<'a> {
+        self.0
+    }
 }
 
 /// A basic Ethereum block.
@@ -75,6 +159,60 @@ pub struct Transaction {
     pub to: Option<Address>,
 }
 
+/// Outcome of a successful `Database::update`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UpdateSummary {
+    /// Logs that were not stored because their event wasn't prepared and the
+    /// database is configured to skip such logs instead of erroring.
+    pub skipped_logs: usize,
+    /// The highest block among the `indexed` blocks of the updated events and
+    /// the block numbers of the stored logs. Everything up to it is durable
+    /// once `update` returns, so callers can advance their cursor from it.
+    /// `None` if the update contained neither.
+    pub indexed_block: Option<u64>,
+}
+
+impl UpdateSummary {
+    /// Raises `indexed_block` to `block` if it is higher.
+    pub fn record_block(&mut self, block: u64) {
+        self.indexed_block = self.indexed_block.max(Some(block));
+    }
+}
+
+/// Errors of reading stored logs that callers may want to handle, returned as
+/// the root cause of the `anyhow::Error`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DatabaseError {
+    /// A stored string isn't valid UTF-8, for example because the row was
+    /// modified outside of arak. `column` is the name of the column, or of the
+    /// array table for strings in dynamic arrays.
+    InvalidUtf8 {
+        event: String,
+        block_number: u64,
+        log_index: u64,
+        column: String,
+    },
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUtf8 {
+                event,
+                block_number,
+                log_index,
+                column,
+            } => write!(
+                f,
+                "invalid utf-8 in column {column} of event {event} at block {block_number} log \
+                 {log_index}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
 /// Abstraction over specific SQL like backends.
 ///
 /// All methods either succeed in full or error without having applied any
@@ -87,7 +225,10 @@ pub trait Database {
     /// an array index for values in dynamic arrays.
     ///
     /// `name` identifies this event. Database tables for this event are
-    /// prefixed with the name.
+    /// prefixed with the name. The same descriptor can be prepared under
+    /// several names, for example to index it once raw and once filtered by
+    /// address. The names get separate tables and blocks, and `update` stores
+    /// each log only under the name in `Log::event`.
     ///
     /// If this is the first time the event has been prepared on this database
     /// (the persistent database file, not this instance of the Database trait),
@@ -107,6 +248,17 @@ pub trait Database {
     /// Retrieves the block information for the specified event.
     fn event_block<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, Result<Block>>;
 
+    /// Whether `prepare_event` has been successfully called for `name`.
+    ///
+    /// The default implementation probes `event_block`, which backends can
+    /// avoid.
+    fn contains_event<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, bool>
+    where
+        Self: Send,
+    {
+        async move { self.event_block(name).await.is_ok() }.boxed()
+    }
+
     /// It updates two things:
     /// - `blocks` specifies updates to the block information for events; this
     ///   will change the value that is read from `event_block`.
@@ -115,7 +267,8 @@ pub trait Database {
     /// Errors:
     ///
     /// - `prepare_event` has not been successfully called with `event` field
-    ///   from one or more of the specified `blocks` or `logs`.
+    ///   from one or more of the specified `blocks` or `logs`. Backends can be
+    ///   configured to skip such logs, which are then counted in the summary.
     /// - `fields` do not match the event signature specified in the successful
     ///   call to `prepare_event` with this `event` name for one or more `logs`.
     fn update<'a>(
@@ -124,7 +277,7 @@ pub trait Database {
         logs: &'a [Log],
         block_times: &'a [BlockTime],
         transactions: &'a [Transaction],
-    ) -> BoxFuture<'a, Result<()>>;
+    ) -> BoxFuture<'a, Result<UpdateSummary>>;
 
     /// Removes logs from the specified event's uncled blocks.
     ///
@@ -143,13 +143,12 @@ impl Database for Postgres {
         async move {
             let transaction = self.client.transaction().await.context("transaction")?;
             // TODO:
-            // - Check that either no table exists or all tables exist and with the right
-            //   types.
+            // - Verify existing tables and store the event signature like the SQLite
+            //   backend does.
             // - Maybe have `CHECK` clauses to enforce things like address and integers
             //   having expected length.
-            // - Maybe store serialized event descriptor in the database so we can load and
-            //   check it.
 
+            let event = &database::event_to_tables::stored_event(event);
             if let Some(existing) = self.events.get(name) {
                 if event != &existing.descriptor {
                     return Err(anyhow!(
@@ -160,8 +159,15 @@ impl Database for Postgres {
                 return Ok(());
             }
 
-            let tables = database::event_to_tables::event_to_tables(name, event)
-                .context("unsupported event")?;
+            let tables = database::event_to_tables::event_to_tables(
+                name,
+                event,
+                &database::event_to_tables::PostgresSanitizer,
+            )
+            .context("unsupported event")?;
+            if tables.array_parents.iter().any(Option::is_some) {
+                return Err(anyhow!("nested dynamic arrays aren't supported"));
+            }
             let name = &tables.primary.name;
             Self::create_table(&transaction, false, &tables.primary).await?;
             for table in &tables.dynamic_arrays {
@@ -183,7 +189,9 @@ impl Database for Postgres {
                     write!(&mut sql, "${},", i + 1).unwrap();
                 }
                 assert_eq!(sql.pop(), Some(','));
-                write!(&mut sql, ");").unwrap();
+                // Logs that are stored already, for example because indexing
+                // restarted before the block was recorded, are skipped.
+                write!(&mut sql, ") ON CONFLICT DO NOTHING;").unwrap();
                 tracing::debug!("creating insert statement:\n{}", sql);
                 insert_statements.push(InsertStatement {
                     sql: transaction
@@ -241,7 +249,7 @@ impl Database for Postgres {
         logs: &'a [database::Log],
         block_times: &'a [database::BlockTime],
         transactions: &'a [database::Transaction],
-    ) -> BoxFuture<'a, Result<()>> {
+    ) -> BoxFuture<'a, Result<database::UpdateSummary>> {
         async move {
             let mut transaction = self.client.transaction().await.context("transaction")?;
 
@@ -279,10 +287,15 @@ impl Database for Postgres {
                 validate_rows(rows)?;
             }
 
+            let mut summary = database::UpdateSummary::default();
+            for block in blocks {
+                summary.record_block(block.block.indexed);
+            }
             for log in logs {
                 Self::store_event(&mut transaction, &self.events, log)
                     .await
                     .context(format!("store_event {:?}", log))?;
+                summary.record_block(log.block_number);
             }
             // Store blocks
             for block_time in block_times {
@@ -296,7 +309,8 @@ impl Database for Postgres {
                     .await
                     .context(format!("store_transaction {:?}", tx))?;
             }
-            transaction.commit().await.context("commit")
+            transaction.commit().await.context("commit")?;
+            Ok(summary)
         }
         .boxed()
     }
@@ -360,9 +374,10 @@ impl Postgres {
             transaction_index,
             address,
             fields,
+            ..
         }: &'a Log<'a>,
     ) -> Result<()> {
-        let event = events.get(*event).context("unknown event")?;
+        let event = events.get(event.as_ref()).context("unknown event")?;
 
         let len = fields.len();
         let expected_len = event.descriptor.inputs.len();
@@ -381,6 +396,9 @@ impl Postgres {
         type ToSqlBox = Box<dyn tokio_postgres::types::ToSql + Send + Sync>;
         let mut sql_values: Vec<(Option<usize>, Vec<ToSqlBox>)> = vec![(None, vec![])];
         let mut in_array: bool = false;
+        // `visit_value` doesn't yield containers but a leaf value we can't store
+        // should be an error instead of a panic.
+        let mut unsupported = None;
         let mut visitor = |value: VisitValue<'a>| {
             let sql_value: Box<dyn tokio_postgres::types::ToSql + Send + Sync> = match value {
                 VisitValue::ArrayStart(len) => {
@@ -413,7 +431,10 @@ impl Postgres {
                 ),
                 VisitValue::Value(AbiValue::Bytes(v)) => Box::new(v.to_owned()),
                 VisitValue::Value(AbiValue::String(v)) => Box::new(v.replace('\0', "")),
-                _ => unreachable!(),
+                VisitValue::Value(value) => {
+                    unsupported.get_or_insert(value);
+                    return;
+                }
             };
             (if in_array {
                 <[_]>::last_mut
@@ -427,6 +448,9 @@ impl Postgres {
         for value in fields {
             event_visitor::visit_value(value, &mut visitor)
         }
+        if let Some(value) = unsupported {
+            return Err(anyhow!("unsupported value of kind {:?}", value.kind()));
+        }
 
         let block_number = i64::try_from(*block_number).unwrap();
         let log_index = i64::try_from(*log_index).unwrap();
@@ -684,7 +708,7 @@ mod tests {
         let event = EventDescriptor::parse_declaration(event).unwrap();
         db.prepare_event("event", &event).await.unwrap();
         let log = Log {
-            event: "event",
+            event: "event".into(),
             block_number: 0,
             fields: vec![
                 AbiValue::Uint(Uint::new(256, U256::MAX).unwrap()),
@@ -709,7 +733,7 @@ mod tests {
         let event = EventDescriptor::parse_declaration(event).unwrap();
         db.prepare_event("event", &event).await.unwrap();
         let log = Log {
-            event: "event",
+            event: "event".into(),
             block_number: 0,
             fields: vec![
                 AbiValue::Bool(true),
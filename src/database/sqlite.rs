@@ -2,645 +2,6428 @@ use {
     crate::database::{
         self,
         date_util::systemtime_to_string,
-        event_to_tables::Table,
+        event_to_tables::{
+            event_to_tables, stored_event, Column, DefaultSanitizer, NameSanitizer, Table, Tables,
+        },
         event_visitor::{self, VisitValue},
         BlockTime, Database, Log,
     },
     anyhow::{anyhow, Context, Result},
     futures::{future::BoxFuture, FutureExt},
     rusqlite::{
+        backup::Progress,
+        functions::FunctionFlags,
         types::{ToSqlOutput, Type as SqlType, Value as SqlValue, ValueRef as SqlValueRef},
-        Connection, Transaction,
+        Connection, DatabaseName, OpenFlags, OptionalExtension,
     },
+    serde_json::json,
     solabi::{
-        abi::EventDescriptor,
-        value::{Value as AbiValue, ValueKind as AbiKind},
+        abi::{EventDescriptor, Field},
+        ethprim::Address,
+        function::{ExternalFunction, Selector},
+        value::{
+            Array, FixedArray, FixedBytes, Int, Uint, Value as AbiValue, ValueKind as AbiKind,
+        },
+        I256, U256,
     },
-    std::{collections::HashMap, fmt::Write},
+    std::{
+        borrow::{Borrow, Cow},
+        cell::Cell,
+        cmp::Ordering,
+        collections::{HashMap, HashSet},
+        fmt::{Debug, Write},
+        panic::AssertUnwindSafe,
+        path::Path,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+#[cfg(feature = "arrow")]
+mod arrow;
+mod metrics;
+mod migration;
+mod partition;
+mod read;
+mod script;
+mod shared;
+mod union;
+
+pub use self::{
+    metrics::{HistogramMetrics, Metrics},
+    read::{Order, QueryFilter, Row},
+    script::SqlScript,
+    shared::SharedSqlite,
+};
+use self::{
+    migration::{set_schema_version, MIGRATIONS, SCHEMA_VERSION},
+    partition::{create_table_sql, create_view, partitions, Storage},
+    read::{read_sql, READ_LOGS_FILTER},
 };
 
+/// The SQLite backend.
+///
+/// Every read like `read_logs` or `read_page` runs in a read transaction, so
+/// it sees the database as of a single commit even if another connection to
+/// the same file commits an update meanwhile: a log is read with all of its
+/// array rows or not at all. Inside a batch (see `begin_batch`) reads see the
+/// uncommitted state of the batch instead.
+///
+/// In WAL mode (see `enable_wal`) readers see the state of when their read
+/// started and readers and the writer never block each other. In the other
+/// journal modes a read waits for a commit in progress and a commit waits for
+/// running reads, each up to the busy timeout. That is why the first read of
+/// a database file that isn't in WAL mode logs a warning, which
+/// `Options::require_wal` turns into an error.
 pub struct Sqlite {
     connection: Connection,
     inner: SqliteInner,
+    on_commit: Option<OnCommit>,
+    /// Blocks and logs of the updates of the current batch, which are only
+    /// passed to `on_commit` once the batch is committed.
+    pending_commits: Vec<PendingCommit>,
+    /// Whether the journal mode was checked for the first read.
+    journal_checked: Cell<bool>,
 }
 
-impl Sqlite {
-    pub fn new(connection: Connection) -> Result<Self> {
-        let inner = SqliteInner::new(&connection)?;
-        Ok(Self { connection, inner })
-    }
+/// Callback of `Sqlite::set_on_commit`.
+pub type OnCommit = Box<dyn Fn(&[database::EventBlock], &[Log]) + Send>;
 
-    /// Opens a new SQLite database backend for the specified connection string.
-    /// The connection string can either be a file path or a `file://` URL (see
-    /// <https://www.sqlite.org/uri.html> for more information).
-    pub fn open(connection: &str) -> Result<Self> {
-        let connection = Connection::open(connection)?;
-        Self::new(connection)
-    }
+/// Owned blocks and logs of an update waiting for its batch to commit.
+type PendingCommit = (Vec<(String, database::Block)>, Vec<Log<'static>>);
 
-    #[cfg(test)]
-    /// Create a temporary in memory database for tests.
-    pub fn new_for_test() -> Self {
-        Self::new(Connection::open_in_memory().unwrap()).unwrap()
-    }
-}
+/// Source of the timestamps arak writes, see `Sqlite::set_clock`.
+pub type Clock = fn() -> SystemTime;
 
-impl Database for Sqlite {
-    fn prepare_event<'a>(
-        &'a mut self,
-        name: &'a str,
-        event: &'a EventDescriptor,
-    ) -> BoxFuture<'a, Result<()>> {
-        async move {
-            let transaction = self.connection.transaction().context("transaction")?;
-            self.inner.prepare_event(&transaction, name, event)?;
-            transaction.commit().context("commit")
-        }
-        .boxed()
-    }
+/// Configuration of the SQLite backend. The default matches `Sqlite::new`.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Prepended to the name of every table arak creates, for example `arak_`.
+    /// Useful when the database is shared with tables of other applications.
+    pub table_prefix: String,
+    /// Adds a `chain_id` column to all event tables and makes it part of the
+    /// primary key so that logs of several chains can share the tables. Logs
+    /// must then have `Log::chain_id` set.
+    ///
+    /// Note that the indexed blocks and reorg handling are still tracked per
+    /// event name and not per chain.
+    pub chain_id: bool,
+    /// What `update` does with logs of events that weren't prepared.
+    pub on_unknown_event: UnknownEvent,
+    /// Maximum number of prepared statements kept cached on the connection.
+    /// Every event needs a few statements so with many events a small cache
+    /// trades memory for re-preparing statements. Defaults to rusqlite's
+    /// capacity of 16 when `None`.
+    pub statement_cache_capacity: Option<usize>,
+    /// Stores empty `bytes` and `string` values as NULL instead of empty blobs or text.
+    /// Their columns are then nullable. Reading maps NULL back to empty values.
+    pub empty_as_null: bool,
+    /// Adds `transaction_index` to the primary key of event tables so that
+    /// logs with the same block number and log index but different
+    /// transaction indices are not duplicates. Existing tables keep the key
+    /// they were created with; preparing an event whose tables have a
+    /// different key fails.
+    pub transaction_index_key: bool,
+    /// Runs `EXPLAIN QUERY PLAN` for every read query and logs the plan at
+    /// debug level. Helps finding reads that scan whole tables because of a
+    /// missing index.
+    pub debug_query_plan: bool,
+    /// What storing a log does when the event's tables already have a log
+    /// with the same primary key.
+    pub on_conflict: OnConflict,
+    /// Stores `function` values in two columns `{column}_address` and
+    /// `{column}_selector` instead of one 24 byte blob so that they can be
+    /// queried by address.
+    pub split_functions: bool,
+    /// Stores the logs of an update in chunks of at most this many logs, each
+    /// in its own transaction, to bound how long the write lock is held and
+    /// how much the WAL grows during backfills.
+    ///
+    /// This weakens the all-or-nothing guarantee of `update`: if a later chunk
+    /// fails the earlier chunks stay stored. Indexed blocks, block times and
+    /// transactions are only written with the last chunk so indexed blocks
+    /// never cover logs that are missing. The stored logs of a failed update
+    /// are delivered again when indexing resumes, so `on_conflict` must not
+    /// be `Error`, which `Sqlite::with_options` checks.
+    pub max_batch_size: Option<usize>,
+    /// Refuses updates that move an event's indexed or finalized block back,
+    /// which would make the indexer skip or process blocks again. Only
+    /// `Database::remove` moves them back then, for reorgs. The `blocks` and
+    /// `transactions` pseudo-events are exempt because they follow the
+    /// earliest event, which can be a newly added one.
+    /// `Sqlite::set_event_blocks_monotonic` always refuses it.
+    pub monotonic_blocks: bool,
+    /// Turns event and field names into table and column names. Defaults to
+    /// `DefaultSanitizer` when `None`. The sanitizer's id is stored per event
+    /// and preparing an event that was created with a different one fails.
+    pub name_sanitizer: Option<Arc<dyn NameSanitizer>>,
+    /// How `int` and `uint` values are stored. The encoding is stored per
+    /// event and preparing an event that was created with a different one
+    /// fails. When `None`, new events use `IntegerEncoding::Biased` and events
+    /// keep the encoding they were created with; events whose tables were
+    /// created before encodings were stored use `TwosComplement`.
+    ///
+    /// So new databases store signed integers biased, which older versions
+    /// and SQL that reads the blobs as two's complement decode wrongly.
+    /// External readers have to look up the encoding of each event with
+    /// `Sqlite::integer_encoding`.
+    pub integer_encoding: Option<IntegerEncoding>,
+    /// Enables `PRAGMA foreign_keys` and declares foreign keys with
+    /// `ON DELETE CASCADE` from the array tables to the primary table, so
+    /// that array rows can't exist without their log and deleting a log
+    /// deletes its array rows. Like the primary key this only applies to
+    /// tables created with it.
+    pub foreign_keys: bool,
+    /// Maximum number of columns of a table including the fixed columns.
+    /// Events whose flattened fields exceed it, for example through large
+    /// fixed arrays of tuples, are refused by `prepare_event` with an error
+    /// naming the table. Defaults to SQLite's default limit of 2000 when
+    /// `None`.
+    pub max_columns: Option<usize>,
+    /// Keys the primary tables of events by a single integer
+    /// `block_number << 32 | log_index` stored as the SQLite rowid instead of
+    /// the composite primary key, which saves the separate key index and
+    /// makes storing, removing and reading block ranges of logs cheaper on
+    /// very large tables. Array tables keep the composite key.
+    ///
+    /// Log indices must be below 2^32 and block numbers below 2^31, which a
+    /// `CHECK` enforces; real chains are far from either. When the key
+    /// doesn't fit in 64 bits because `chain_id` or `transaction_index_key`
+    /// is set, or `foreign_keys` needs a composite key to reference, the
+    /// composite key is used. Existing tables keep the key they were created
+    /// with; preparing an event whose tables have a different key fails.
+    pub packed_key: bool,
+    /// Stores the leaf kinds it handles its own way. `integer_encoding`,
+    /// `split_functions` and `empty_as_null` only apply to the other kinds.
+    /// Changing it for an existing database makes stored values unreadable.
+    pub type_mapper: Option<Arc<dyn TypeMapper>>,
+    /// Stores `Log::data` and `Log::topics` in the columns `raw_data` and
+    /// `topic0` to `topic3` at the end of the primary tables, so that logs
+    /// can be decoded again without fetching them from a node. Missing topics
+    /// are NULL. Like the primary key this only applies to tables created
+    /// with it.
+    pub raw_logs: bool,
+    /// Stores the elements of all dynamic arrays of an event in a single
+    /// table `{event}_arrays` instead of one table per array. Its
+    /// `array_id` column tells which array a row belongs to, and every array
+    /// has its own columns prefixed with `a{array_id}_` that are NULL in the
+    /// rows of the other arrays. Fewer tables for wider rows. Like the
+    /// primary key this only applies to tables created with it.
+    pub coalesce_arrays: bool,
+    /// Splits the tables of events into partitions of this many blocks, so
+    /// that no table grows without bound and old history can be dropped
+    /// cheaply. The rows of blocks `n * size` to `(n + 1) * size - 1` are
+    /// stored in tables suffixed with `_p{n}` that are created when the
+    /// first log of their range is stored. The unsuffixed table names are
+    /// views that union the partitions for reads. Not supported together with
+    /// `foreign_keys`. Like the primary key this only applies to tables
+    /// created with it.
+    pub partition_size: Option<u64>,
+    /// Receives how long storing each log took.
+    pub metrics: Option<Arc<dyn Metrics>>,
+    /// Makes reads of a database file fail unless it is in WAL mode instead
+    /// of logging a warning, see `Sqlite`.
+    pub require_wal: bool,
+    /// Sets `PRAGMA case_sensitive_like` on the connection so that
+    /// `Sqlite::search_text` and other `LIKE`s match ASCII letters case
+    /// sensitively.
+    pub case_sensitive_like: bool,
+    /// Reads strings that aren't valid UTF-8 with replacement characters
+    /// instead of failing with `DatabaseError::InvalidUtf8`.
+    pub lossy_utf8: bool,
+}
 
-    fn event_block<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, Result<database::Block>> {
-        async move { self.inner.event_block(&self.connection, name) }.boxed()
+/// Custom storage of leaf kinds, see `Options::type_mapper`. The defaults
+/// handle no kind, which keeps arak's built-in storage.
+pub trait TypeMapper: Debug + Send + Sync {
+    /// The column type of `kind`, or `None` if the mapper doesn't handle it.
+    fn column_type(&self, kind: &AbiKind) -> Option<SqlType> {
+        let _ = kind;
+        None
     }
 
-    fn update<'a>(
-        &'a mut self,
-        blocks: &'a [database::EventBlock],
-        logs: &'a [database::Log],
-        block_times: &'a [database::BlockTime],
-        transactions: &'a [database::Transaction],
-    ) -> BoxFuture<'a, Result<()>> {
-        async move {
-            let transaction = self.connection.transaction().context("transaction")?;
-            self.inner
-                .update(&transaction, blocks, logs, block_times, transactions)?;
-            transaction.commit().context("commit")
-        }
-        .boxed()
+    /// Encodes a value of a handled kind. `None` fails storing the log.
+    fn encode<'a>(&self, value: &'a AbiValue) -> Option<ToSqlOutput<'a>> {
+        let _ = value;
+        None
     }
 
-    fn remove<'a>(&'a mut self, uncles: &'a [database::Uncle]) -> BoxFuture<'a, Result<()>> {
-        async move {
-            let transaction = self.connection.transaction().context("transaction")?;
-            self.inner.remove(&transaction, uncles)?;
-            transaction.commit().context("commit")
-        }
-        .boxed()
+    /// Inverse of `encode`.
+    fn decode(&self, kind: &AbiKind, value: SqlValueRef) -> Result<AbiValue> {
+        let _ = value;
+        Err(anyhow!("{kind:?} isn't mapped"))
     }
 }
 
-/// Columns that every event table has.
-const FIXED_COLUMNS: &str = "block_number INTEGER NOT NULL, log_index INTEGER NOT NULL, \
-                             transaction_index INTEGER NOT NULL, address BLOB NOT NULL";
-const FIXED_COLUMNS_COUNT: usize = 4;
-const PRIMARY_KEY: &str = "block_number ASC, log_index ASC";
-
-/// Column for array tables.
-const ARRAY_COLUMN: &str = "array_index INTEGER NOT NULL";
-const PRIMARY_KEY_ARRAY: &str = "block_number ASC, log_index ASC, array_index ASC";
+/// The type mapper of `options` if it handles `kind`.
+fn type_mapper<'a>(options: &'a Options, kind: &AbiKind) -> Option<&'a dyn TypeMapper> {
+    options
+        .type_mapper
+        .as_deref()
+        .filter(|mapper| mapper.column_type(kind).is_some())
+}
 
-const CREATE_BLOCKS_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS blocks
-(
-    number INTEGER PRIMARY KEY,
-    time   TEXT    NOT NULL
-);"#;
+/// Storage of `int` and `uint` values. In the blob encodings unsigned values
+/// are stored as 32 byte big endian blobs, which sort like the numbers they
+/// encode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IntegerEncoding {
+    /// Signed values are stored as 32 byte big endian two's complement blobs.
+    /// Negative values sort after positive ones so ordering needs
+    /// `arak_int_cmp`.
+    TwosComplement,
+    /// `int<M>` values are offset by `2^(M-1)` before being stored as 32 byte
+    /// big endian blobs, so that `ORDER BY` and comparisons of the blobs
+    /// match the numbers.
+    Biased,
+    /// Values are stored as decimal TEXT like `-1000`. The columns use the
+    /// `ARAK_NUMERIC` collation so that `ORDER BY amount` and
+    /// `WHERE amount > '1000'` compare numerically. The collation only
+    /// applies to integer columns in this encoding.
+    DecimalText,
+}
 
-const INSERT_BLOCK: &str = "INSERT OR IGNORE INTO blocks (number, time) VALUES (?1, ?2);";
+impl IntegerEncoding {
+    /// Name of the encoding in `{prefix}_event_naming`.
+    fn id(self) -> &'static str {
+        match self {
+            Self::TwosComplement => "twos_complement",
+            Self::Biased => "biased",
+            Self::DecimalText => "decimal_text",
+        }
+    }
 
-const REMOVE_BLOCKS_FROM: &str = "DELETE FROM blocks WHERE number >=?1;";
+    fn from_id(id: &str) -> Result<Self> {
+        [Self::TwosComplement, Self::Biased, Self::DecimalText]
+            .into_iter()
+            .find(|encoding| encoding.id() == id)
+            .with_context(|| format!("unknown integer encoding {id}"))
+    }
+}
 
-const CREATE_TRANSACTIONS_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS transactions
-(
-    block_number INTEGER NOT NULL,
-    "index"      INTEGER NOT NULL,
-    hash         BLOB    NOT NULL,
-    "from"       BLOB    NOT NULL,
-    "to"         BLOB,
-    PRIMARY KEY (block_number, "index")
-);"#; // Can not add a new line here or will get Error:
-      // https://docs.rs/rusqlite/0.30.0/rusqlite/enum.Error.html#variant.MultipleStatement
+/// Settings of a single event, see `Sqlite::prepare_event_with`.
+#[derive(Clone, Debug, Default)]
+pub struct EventOptions<'a> {
+    /// Columns created without `NOT NULL`. The columns that a log lists in
+    /// `Log::nulls` are stored as NULL, which is read back as the zero value
+    /// with the column listed again. This lets tables that several event
+    /// variants map into mark the fields a variant doesn't have as absent.
+    /// With `Options::empty_as_null` empty `bytes` and `string` values of
+    /// these columns read back as absent too.
+    ///
+    /// These are column names like `amount_0`, which can be in the primary or
+    /// the array tables. Existing tables must already have these columns
+    /// nullable.
+    pub nullable: &'a [&'a str],
+    /// The only contract that emits the event. Its `address` column is then a
+    /// virtual column of this constant instead of being stored in every row;
+    /// reads and queries see it like a stored column. Logs from other
+    /// addresses are refused. Existing tables must have been created with the
+    /// same setting.
+    pub address: Option<Address>,
+    /// Maximum lengths in bytes of `bytes` and `string` columns, enforced by
+    /// `CHECK` constraints so that values from a buggy decoder are refused
+    /// instead of stored. The columns are named like in the created tables.
+    /// Only applies to tables created with it.
+    pub max_lengths: &'a [(&'a str, usize)],
+}
 
-const INSERT_TRANSACTION: &str = r#"INSERT INTO transactions (block_number, "index", hash, "from", "to")
-                                    VALUES (?1, ?2, ?3, ?4, ?5)
-                                    ON CONFLICT DO NOTHING;"#;
+/// Connection tuning for a workload, see `Sqlite::tune`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Profile {
+    /// For the initial sync: `synchronous=OFF`, a 256 MiB page cache and
+    /// temporary tables in memory. Writes are much faster but a power loss or
+    /// OS crash can corrupt the database, so only use it for databases that
+    /// can be rebuilt from the chain. A crash of the process alone is safe.
+    Backfill,
+    /// `synchronous=FULL` and SQLite's default cache and temporary storage.
+    /// Committed updates survive power loss.
+    Durable,
+}
 
-const REMOVE_TRANSACTIONS_FROM: &str = "DELETE FROM transactions WHERE block_number >=?1;";
+/// A problem found by `Sqlite::check_integrity`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Inconsistency {
+    /// A row in a dynamic array table without a row for the same log in the
+    /// primary table.
+    OrphanedArrayRow {
+        table: String,
+        block_number: u64,
+        log_index: u64,
+    },
+}
 
-const CREATE_EVENT_BLOCK_TABLE: &str = "CREATE TABLE IF NOT EXISTS _event_block(event TEXT \
-                                        PRIMARY KEY NOT NULL, indexed INTEGER NOT NULL, finalized \
-                                        INTEGER NOT NULL) STRICT;";
-const GET_EVENT_BLOCK: &str = "SELECT indexed, finalized FROM _event_block WHERE event = ?1;";
-const NEW_EVENT_BLOCK: &str = "INSERT INTO _event_block (event, indexed, finalized) VALUES(?1, 0, \
-                               0) ON CONFLICT(event) DO NOTHING;";
-const SET_EVENT_BLOCK: &str =
-    "UPDATE _event_block SET indexed = ?2, finalized = ?3 WHERE event = ?1;";
-const SET_INDEXED_BLOCK: &str = "UPDATE _event_block SET indexed = ?2 WHERE event = ?1";
+/// A suspicious event block found by `Sqlite::verify_cursors`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CursorWarning {
+    /// Logs are stored past the indexed block, which an interrupted write
+    /// leaves behind. `Sqlite::resync_indexed` fixes it.
+    LogsPastIndexed {
+        event: String,
+        indexed: u64,
+        max_block: u64,
+    },
+    /// The indexed block is more than `SUSPICIOUS_CURSOR_GAP` blocks past the
+    /// last stored log. Normal for events that are rarely emitted, otherwise
+    /// a sign of logs that were skipped.
+    LargeGap {
+        event: String,
+        indexed: u64,
+        max_block: u64,
+    },
+    /// The finalized block is past the indexed block.
+    FinalizedPastIndexed {
+        event: String,
+        indexed: u64,
+        finalized: u64,
+    },
+}
 
-const TABLE_EXISTS: &str =
-    "SELECT COUNT(*) > 0 FROM sqlite_schema WHERE type = 'table' AND name = ?1";
+/// Gap between the indexed block and the last log of an event from which on
+/// `Sqlite::verify_cursors` warns, about half a year of mainnet blocks.
+pub const SUSPICIOUS_CURSOR_GAP: u64 = 1_000_000;
 
-// Separate type because of lifetime issues when creating transactions. Outer
-// struct only stores the connection itself.
-struct SqliteInner {
-    /// Invariant: Events in the map have corresponding tables in the database.
-    ///
-    /// The key is the `name` argument when the event was passed into
-    /// `prepare_event`.
-    events: HashMap<String, PreparedEvent>,
+/// A `remove` of an event's logs, see `Sqlite::reorg_history`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReorgEntry {
+    /// Logs in this block and later blocks were removed.
+    pub from_block: u64,
+    /// Number of rows removed from the event's tables, including array rows.
+    pub removed_rows: u64,
+    pub at: SystemTime,
 }
 
-/// An event is represented in the database in several tables.
-///
-/// All tables have some columns that are unrelated to the event's fields. See
-/// `FIXED_COLUMNS`. The first table contains all fields that exist once per
-/// event which means they do not show up in arrays. The other tables contain
-/// fields that are part of arrays. Those tables additionally have the column
-/// `ARRAY_COLUMN`.
-///
-/// The order of tables and fields is given by the `event_visitor` module.
-struct PreparedEvent {
-    descriptor: EventDescriptor,
-    insert_statements: Vec<InsertStatement>,
-    /// Prepared statements for removing rows starting at some block number.
-    /// Every statement takes a block number as parameter.
-    remove_statements: Vec<String>,
+/// Policy for logs whose event wasn't prepared.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum UnknownEvent {
+    /// Fail the whole update.
+    #[default]
+    Error,
+    /// Log a warning and store the rest of the update.
+    Skip,
 }
 
-/// Parameters:
-/// - 1: block number
-/// - 2: log index
-/// - 3: array index if this is an array table (all tables after the first)
-/// - 3 + n: n-th event field/column
-#[derive(Debug)]
-struct InsertStatement {
-    sql: String,
-    /// Number of event fields that map to SQL columns. Does not count
-    /// FIXED_COLUMNS and array index.
-    fields: usize,
+/// Policy for storing a log that was already stored, for example because it
+/// is delivered again.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnConflict {
+    /// Fail the whole update.
+    #[default]
+    Error,
+    /// Keep the stored log including its array rows.
+    Ignore,
+    /// Replace the stored log. Its array rows are removed first so that a
+    /// shorter array leaves no stale elements behind.
+    Replace,
 }
 
-impl SqliteInner {
-    fn new(connection: &Connection) -> Result<Self> {
-        connection
-            .execute(CREATE_EVENT_BLOCK_TABLE, ())
-            .context("create event_block table")?;
-
-        connection
-            .prepare_cached(GET_EVENT_BLOCK)
-            .context("prepare get_event_block")?;
-        connection
-            .prepare_cached(SET_EVENT_BLOCK)
-            .context("prepare set_event_block")?;
-        connection
-            .prepare_cached(SET_INDEXED_BLOCK)
-            .context("prepare set_indexed_block")?;
-        connection
-            .prepare_cached(TABLE_EXISTS)
-            .context("prepare table_exists")?;
-
-        connection
-            .execute(CREATE_BLOCKS_TABLE, [])
-            .context("create blocks table")?;
-        connection
-            .execute(CREATE_TRANSACTIONS_TABLE, [])
-            .context("create transactions table")?;
-
-        let mut new_event_block = connection
-            .prepare_cached(NEW_EVENT_BLOCK)
-            .context("prepare new_event_block")?;
-        new_event_block
-            .execute((&"blocks",))
-            .context("add blocks to _event_blocks")?;
-        new_event_block
-            .execute((&"transactions",))
-            .context("add transactions to _event_blocks")?;
+impl Sqlite {
+    pub fn new(connection: Connection) -> Result<Self> {
+        Self::with_options(connection, Options::default())
+    }
 
+    pub fn with_options(connection: Connection, options: Options) -> Result<Self> {
+        // The library might be a system SQLite instead of the bundled one.
+        let version: String = connection
+            .query_row("SELECT sqlite_version();", (), |row| row.get(0))
+            .context("query sqlite_version")?;
+        check_sqlite_version(&version)?;
+        register_functions(&connection)?;
+        if let Some(capacity) = options.statement_cache_capacity {
+            connection.set_prepared_statement_cache_capacity(capacity);
+        }
+        if options.case_sensitive_like {
+            connection
+                .pragma_update(None, "case_sensitive_like", true)
+                .context("set case_sensitive_like")?;
+        }
+        let inner = SqliteInner::new(&connection, options)?;
         Ok(Self {
-            events: Default::default(),
+            connection,
+            inner,
+            on_commit: None,
+            pending_commits: Vec::new(),
+            journal_checked: Cell::new(false),
         })
     }
 
-    /*
-        fn read_event(
-            &self,
-            c: &Connection,
-            name: &str,
-            block_number: u64,
-            log_index: u64,
-        ) -> Result<Vec<AbiValue>> {
-            let name = Self::internal_event_name(name);
-            let event = self.events.get(&name).context("unknown event")?;
-
-            todo!()
-        }
-    */
+    /// Opens a new SQLite database backend for the specified connection string.
+    /// The connection string can either be a file path or a `file://` URL (see
+    /// <https://www.sqlite.org/uri.html> for more information).
+    ///
+    /// `sqlite://` URLs are accepted too. `sqlite://` alone opens a private
+    /// in-memory database and `sqlite://:memory:?cache=shared` an in-memory
+    /// database that is shared by all connections of the process and lives as
+    /// long as one of them is open.
+    pub fn open(connection: &str) -> Result<Self> {
+        Self::open_with_flags(connection, OpenFlags::default())
+    }
 
-    fn event_block(&self, con: &Connection, name: &str) -> Result<database::Block> {
-        let mut statement = con
-            .prepare_cached(GET_EVENT_BLOCK)
-            .context("prepare_cached")?;
-        let block: (i64, i64) = statement
-            .query_row((name,), |row| Ok((row.get(0)?, row.get(1)?)))
-            .context("query_row")?;
-        Ok(database::Block {
-            indexed: block.0.try_into().context("indexed out of bounds")?,
-            finalized: block.1.try_into().context("finalized out of bounds")?,
-        })
+    /// Like `open` with explicit flags, for example without
+    /// `SQLITE_OPEN_CREATE` to fail instead of creating a missing database.
+    /// `SQLITE_OPEN_URI` is always added so that `file://` URLs keep working.
+    pub fn open_with_flags(connection: &str, flags: OpenFlags) -> Result<Self> {
+        let path = connection_path(connection);
+        let connection =
+            Connection::open_with_flags(path.as_ref(), flags | OpenFlags::SQLITE_OPEN_URI)
+                .with_context(|| format!("open {path}"))?;
+        Self::new(connection)
     }
 
-    fn set_event_blocks(&self, con: &Transaction, blocks: &[database::EventBlock]) -> Result<()> {
-        let mut statement = con
-            .prepare_cached(SET_EVENT_BLOCK)
-            .context("prepare_cached")?;
-        for block in blocks {
-            if block.is_event() && !self.events.contains_key(block.event) {
-                return Err(anyhow!("event {} wasn't prepared", block.event));
-            }
-            let indexed: i64 = block
-                .block
-                .indexed
-                .try_into()
-                .context("indexed out of bounds")?;
-            let finalized: i64 = block
-                .block
-                .finalized
-                .try_into()
-                .context("finalized out of bounds")?;
-            let rows = statement
-                .execute((block.event, indexed, finalized))
-                .context("execute")?;
-            if rows != 1 {
-                return Err(anyhow!(
-                    "query unexpectedly changed {rows} rows instead of 1"
-                ));
-            }
-        }
-        Ok(())
+    /// Opens a private in-memory database. Unlike `open` there is no path or
+    /// URI to resolve so nothing touches the filesystem, which makes this
+    /// usable on targets without one like `wasm32` with a bundled SQLite.
+    pub fn in_memory(options: Options) -> Result<Self> {
+        let connection = Connection::open_in_memory().context("open_in_memory")?;
+        Self::with_options(connection, options)
     }
 
-    fn prepare_event(
+    /// Like `Database::prepare_event` with per event settings. Preparing an
+    /// event again with different settings fails.
+    pub fn prepare_event_with(
         &mut self,
-        con: &Transaction,
         name: &str,
         event: &EventDescriptor,
+        options: &EventOptions,
     ) -> Result<()> {
-        // TODO:
-        // - Check that either no table exists or all tables exist and with the right
-        //   types.
-        // - Maybe have `CHECK` clauses to enforce things like address and integers
-        //   having expected length.
-        // - Maybe store serialized event descriptor in the database so we can load and
-        //   check it.
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        self.inner
+            .prepare_event(&transaction, name, event, options, None)?;
+        transaction.commit().context("commit")
+    }
 
-        if let Some(existing) = self.events.get(name) {
-            if event != &existing.descriptor {
-                return Err(anyhow!(
-                    "event {} (database name {name}) already exists with different signature",
-                    event.name
-                ));
+    /// Like `Database::prepare_event` but for several events at once. Either
+    /// all events are prepared or, if one of them fails, none are.
+    pub fn prepare_events(&mut self, events: &[(&str, &EventDescriptor)]) -> Result<()> {
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        let mut prepared = Vec::new();
+        let result = events
+            .iter()
+            .try_for_each(|(name, event)| {
+                let new = !self.inner.events.contains_key(*name);
+                self.inner
+                    .prepare_event(&transaction, name, event, &Default::default(), None)
+                    .with_context(|| format!("prepare event {name}"))?;
+                if new {
+                    prepared.push(*name);
+                }
+                Ok(())
+            })
+            .and_then(|()| transaction.commit().context("commit"));
+        if result.is_err() {
+            for name in prepared {
+                self.inner.events.remove(name);
             }
-            return Ok(());
         }
+        result
+    }
 
-        let tables =
-            database::event_to_tables::event_to_tables(name, event).context("unsupported event")?;
-        let name = &tables.primary.name;
-
-        let create_table = |is_array: bool, table: &Table| {
-            let mut sql = String::new();
-            write!(&mut sql, "CREATE TABLE IF NOT EXISTS {} (", table.name).unwrap();
-            write!(&mut sql, "{FIXED_COLUMNS}, ").unwrap();
-            if is_array {
-                write!(&mut sql, "{ARRAY_COLUMN}, ").unwrap();
-            }
-            for column in table.columns.iter() {
-                write!(&mut sql, "{}", column.name).unwrap();
-                let type_ = match abi_kind_to_sql_type(column.kind).unwrap() {
-                    SqlType::Null => unreachable!(),
-                    SqlType::Integer => "INTEGER",
-                    SqlType::Real => "REAL",
-                    SqlType::Text => "TEXT",
-                    SqlType::Blob => "BLOB",
-                };
-                write!(&mut sql, " {type_} NOT NULL, ").unwrap();
-            }
-            let primary_key = if is_array {
-                PRIMARY_KEY_ARRAY
-            } else {
-                PRIMARY_KEY
-            };
-            write!(&mut sql, "PRIMARY KEY({primary_key})) STRICT;").unwrap();
-            tracing::debug!("creating table:\n{}", sql);
-            con.execute(&sql, ()).context("execute create_table")
-        };
-        create_table(false, &tables.primary)?;
-        for table in &tables.dynamic_arrays {
-            create_table(true, table)?;
+    /// Starts a batch: until `commit_batch`, all changes made through this
+    /// instance are kept in one long-lived transaction instead of being
+    /// committed one call at a time, which makes backfilling many small
+    /// updates a lot faster. Calls within the batch still either apply fully
+    /// or not at all.
+    ///
+    /// The batch is rolled back if this instance is dropped before it is
+    /// committed.
+    pub fn begin_batch(&mut self) -> Result<()> {
+        if !self.connection.is_autocommit() {
+            return Err(anyhow!("batch already started"));
         }
+        // Left from a batch that SQLite rolled back on its own.
+        self.pending_commits.clear();
+        self.connection.execute_batch("BEGIN").context("begin")
+    }
 
-        let mut new_event_block = con
-            .prepare_cached(NEW_EVENT_BLOCK)
-            .context("prepare new_event_block")?;
-        new_event_block
-            .execute((&name,))
-            .context("execute new_event_block")?;
+    /// Sets a callback that `update_iter` and `Database::update` call with
+    /// their blocks and logs after they have been committed, for example to
+    /// invalidate caches. It isn't called for failed updates. With
+    /// `Options::max_batch_size` every committed chunk is reported on its own,
+    /// so the chunks stored before a failing one are reported as well.
+    ///
+    /// Inside a batch (see `begin_batch`) the updates are reported once
+    /// `commit_batch` succeeds, in their order, and never if the batch is
+    /// rolled back. A panic in the callback is caught and logged because the
+    /// update itself has already succeeded.
+    pub fn set_on_commit(&mut self, on_commit: Option<OnCommit>) {
+        self.on_commit = on_commit;
+    }
 
-        let insert_statements: Vec<InsertStatement> = std::iter::once((false, &tables.primary))
-            .chain(std::iter::repeat(true).zip(&tables.dynamic_arrays))
-            .clone()
-            .map(|(is_array, table)| {
-                let mut sql = String::new();
-                write!(&mut sql, "INSERT INTO {} VALUES(", table.name).unwrap();
-                for i in 0..table.columns.len() + FIXED_COLUMNS_COUNT + is_array as usize {
-                    write!(&mut sql, "?{},", i + 1).unwrap();
-                }
-                assert_eq!(sql.pop(), Some(','));
-                write!(&mut sql, ");").unwrap();
-                tracing::debug!("creating insert statement:\n{}", sql);
-                InsertStatement {
-                    sql,
-                    fields: table.columns.len(),
-                }
-            })
-            .collect();
+    /// Replaces `SystemTime::now` as the source of written timestamps like
+    /// `ReorgEntry::at`, for example with a fixed time in tests.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.inner.clock = clock;
+    }
 
-        let remove_statements: Vec<String> = std::iter::once(&tables.primary)
-            .chain(&tables.dynamic_arrays)
-            .map(|table| format!("DELETE FROM {} WHERE block_number >= ?1;", table.name))
-            .collect();
+    /// Commits the changes of the current batch.
+    pub fn commit_batch(&mut self) -> Result<()> {
+        if self.connection.is_autocommit() {
+            return Err(anyhow!("no batch started"));
+        }
+        self.connection.execute_batch("COMMIT").context("commit")?;
+        for (blocks, logs) in std::mem::take(&mut self.pending_commits) {
+            let blocks = blocks
+                .iter()
+                .map(|(event, block)| database::EventBlock {
+                    event,
+                    block: *block,
+                })
+                .collect::<Vec<_>>();
+            self.notify_commit(&blocks, &logs);
+        }
+        Ok(())
+    }
 
-        // Check that prepared statements are valid. Unfortunately we can't distinguish
-        // the statement being wrong from other Sqlite errors like being unable to
-        // access the database file on disk.
-        for statement in &insert_statements {
-            con.prepare_cached(&statement.sql)
-                .context("invalid prepared insert statement")?;
+    /// Reports an update committed by `update_iter` to `on_commit`, or keeps
+    /// it until the batch is committed.
+    fn committed(&mut self, blocks: &[database::EventBlock], logs: &[Log]) {
+        if self.on_commit.is_none() {
+            return;
         }
-        for statement in &remove_statements {
-            con.prepare_cached(statement)
-                .context("invalid prepared remove statement")?;
+        if self.connection.is_autocommit() {
+            self.notify_commit(blocks, logs);
+        } else {
+            // Dropped with the batch if it is rolled back.
+            let blocks = blocks
+                .iter()
+                .map(|block| (block.event.to_string(), block.block))
+                .collect();
+            let logs = logs.iter().map(|log| log.clone().into_owned()).collect();
+            self.pending_commits.push((blocks, logs));
         }
+    }
 
-        self.events.insert(
-            name.clone(),
-            PreparedEvent {
-                descriptor: event.clone(),
-                insert_statements,
-                remove_statements,
-            },
-        );
+    /// Calls `on_commit` with a committed update.
+    fn notify_commit(&self, blocks: &[database::EventBlock], logs: &[Log]) {
+        if let Some(on_commit) = &self.on_commit {
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| on_commit(blocks, logs)));
+            if result.is_err() {
+                tracing::error!("on_commit callback panicked");
+            }
+        }
+    }
 
-        Ok(())
+    /// Opens an existing database like `open` but without write access. Reads
+    /// work as usual while writes like `update` fail. Events can be prepared
+    /// for reading as long as their tables exist.
+    ///
+    /// The database must already have been created by this version of arak
+    /// because nothing can be created or migrated.
+    pub fn open_readonly(connection: &str) -> Result<Self> {
+        Self::open_with_flags(
+            connection,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
     }
 
-    fn store_event<'a>(
-        &self,
-        conn: &Transaction,
-        Log {
-            event,
-            block_number,
-            log_index,
-            transaction_index,
-            address,
-            fields,
-        }: &'a Log,
-    ) -> Result<()> {
-        let event = self.events.get(*event).context("unknown event")?;
+    /// Like `Database::update` but takes the logs as an iterator so that they
+    /// don't have to be collected into memory first. The logs are stored one
+    /// by one in the same transaction as the other updates, unless
+    /// `Options::max_batch_size` splits them.
+    ///
+    /// With `Options::monotonic_blocks` moving the indexed and finalized
+    /// blocks of events back is an error, see also
+    /// `Sqlite::set_event_blocks_monotonic`.
+    pub fn update_iter<'l>(
+        &mut self,
+        blocks: &[database::EventBlock],
+        logs: impl IntoIterator<Item = impl Borrow<Log<'l>>>,
+        block_times: &[database::BlockTime],
+        transactions: &[database::Transaction],
+    ) -> Result<database::UpdateSummary> {
+        let Some(max_batch_size) = self.inner.options.max_batch_size else {
+            if self.on_commit.is_some() {
+                // Kept for `on_commit`.
+                let logs = logs
+                    .into_iter()
+                    .map(|log| log.borrow().clone())
+                    .collect::<Vec<_>>();
+                let transaction = self.connection.savepoint().context("savepoint")?;
+                let summary =
+                    self.inner
+                        .update(&transaction, blocks, &logs, block_times, transactions)?;
+                transaction.commit().context("commit")?;
+                self.inner.cache_event_blocks(&self.connection, blocks);
+                self.committed(blocks, &logs);
+                return Ok(summary);
+            }
+            let transaction = self.connection.savepoint().context("savepoint")?;
+            let summary =
+                self.inner
+                    .update(&transaction, blocks, logs, block_times, transactions)?;
+            transaction.commit().context("commit")?;
+            self.inner.cache_event_blocks(&self.connection, blocks);
+            return Ok(summary);
+        };
 
-        let len = fields.len();
-        let expected_len = event.descriptor.inputs.len();
-        if fields.len() != expected_len {
-            return Err(anyhow!(
-                "event value has {len} fields but should have {expected_len}"
-            ));
+        let mut logs = logs.into_iter().peekable();
+        let mut summary = database::UpdateSummary::default();
+        loop {
+            let chunk = logs.by_ref().take(max_batch_size).collect::<Vec<_>>();
+            let last = logs.peek().is_none();
+            let transaction = self.connection.savepoint().context("savepoint")?;
+            let chunk_blocks = if last { blocks } else { &[] };
+            let chunk_logs = chunk.iter().map(Borrow::<Log<'l>>::borrow);
+            let chunk_summary = if last {
+                self.inner
+                    .update(&transaction, blocks, chunk_logs, block_times, transactions)?
+            } else {
+                self.inner.update(&transaction, &[], chunk_logs, &[], &[])?
+            };
+            transaction.commit().context("commit")?;
+            self.inner
+                .cache_event_blocks(&self.connection, chunk_blocks);
+            if self.on_commit.is_some() {
+                let logs = chunk
+                    .iter()
+                    .map(|log| log.borrow().clone())
+                    .collect::<Vec<_>>();
+                self.committed(chunk_blocks, &logs);
+            }
+            summary.skipped_logs += chunk_summary.skipped_logs;
+            summary.indexed_block = summary.indexed_block.max(chunk_summary.indexed_block);
+            if last {
+                return Ok(summary);
+            }
         }
-        for (i, (value, kind)) in fields.iter().zip(&event.descriptor.inputs).enumerate() {
-            if value.kind() != kind.field.kind {
-                return Err(anyhow!("event field {i} doesn't match event descriptor"));
+    }
+
+    /// Sets the indexed and finalized blocks of events like an `update` without
+    /// logs, but fails instead of moving them back whether or not
+    /// `Options::monotonic_blocks` is set. `Database::remove` still moves them
+    /// back for reorgs, and the `blocks` and `transactions` pseudo-events are
+    /// set as they are.
+    pub fn set_event_blocks_monotonic(&mut self, blocks: &[database::EventBlock]) -> Result<()> {
+        self.inner.check_writable()?;
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        self.inner
+            .set_event_blocks(&transaction, blocks, true)
+            .context("set_event_blocks")?;
+        transaction.commit().context("commit")?;
+        self.inner.cache_event_blocks(&self.connection, blocks);
+        self.committed(blocks, &[]);
+        Ok(())
+    }
+
+    /// Sets the pragmas of `profile` on the connection. They only last as long
+    /// as the connection, so switching between profiles is fine, for example
+    /// from `Profile::Backfill` to `Profile::Durable` once the indexer caught
+    /// up.
+    pub fn tune(&self, profile: Profile) -> Result<()> {
+        let (synchronous, cache_size, temp_store) = match profile {
+            Profile::Backfill => ("OFF", -256 * 1024, "MEMORY"),
+            Profile::Durable => ("FULL", -2000, "DEFAULT"),
+        };
+        self.connection
+            .pragma_update(None, "synchronous", synchronous)
+            .context("set synchronous")?;
+        // Negative sizes are in KiB.
+        self.connection
+            .pragma_update(None, "cache_size", cache_size)
+            .context("set cache_size")?;
+        self.connection
+            .pragma_update(None, "temp_store", temp_store)
+            .context("set temp_store")
+    }
+
+    /// Writes a consistent snapshot of the database to `path` using SQLite's
+    /// online backup API, so it is safe to use while the database is being
+    /// written to. The snapshot is written to a temporary file next to `path`
+    /// first and then renamed so that `path` never contains a partial backup.
+    ///
+    /// `progress` is called after every step of the backup.
+    pub fn backup_to(&self, path: &Path, progress: Option<fn(Progress)>) -> Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let result = self
+            .connection
+            .backup(DatabaseName::Main, &temporary, progress)
+            .context("backup")
+            .and_then(|()| std::fs::rename(&temporary, path).context("rename backup"));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temporary);
+        }
+        result
+    }
+
+    /// Decodes a value of an event field column, for example from
+    /// `read_fields`, back into an ABI value of the column's kind.
+    ///
+    /// NULL is decoded as an empty `bytes` or `string` (see
+    /// `Options::empty_as_null`). Integers are expected in `encoding`, see
+    /// `Sqlite::integer_encoding`.
+    pub fn decode_value(
+        kind: &AbiKind,
+        value: SqlValueRef,
+        encoding: IntegerEncoding,
+    ) -> Result<AbiValue> {
+        let blob = || value.as_blob().context("expected blob");
+        let fixed = |len: usize| {
+            let blob = blob()?;
+            if blob.len() != len {
+                return Err(anyhow!("expected {len} bytes but got {}", blob.len()));
+            }
+            Ok(blob)
+        };
+        let decimal = || value.as_str().context("expected decimal text");
+        Ok(match kind {
+            AbiKind::Int(bits) if encoding == IntegerEncoding::DecimalText => {
+                let value = decimal()?.parse::<I256>().context("invalid decimal int")?;
+                AbiValue::Int(Int::new(bits.get(), value).context("int out of range")?)
+            }
+            AbiKind::Uint(bits) if encoding == IntegerEncoding::DecimalText => {
+                let value = decimal()?.parse::<U256>().context("invalid decimal uint")?;
+                AbiValue::Uint(Uint::new(bits.get(), value).context("uint out of range")?)
+            }
+            AbiKind::Int(bits) if encoding == IntegerEncoding::Biased => {
+                AbiValue::Int(decode_biased_int(bits.get(), blob()?)?)
+            }
+            AbiKind::Int(bits) => AbiValue::Int(decode_int(bits.get(), blob()?)?),
+            AbiKind::Uint(bits) => AbiValue::Uint(decode_uint(bits.get(), blob()?)?),
+            AbiKind::Address => AbiValue::Address(Address(fixed(20)?.try_into().unwrap())),
+            AbiKind::Bool => match value.as_i64().context("expected integer")? {
+                0 => AbiValue::Bool(false),
+                1 => AbiValue::Bool(true),
+                other => return Err(anyhow!("invalid bool {other}")),
+            },
+            AbiKind::FixedBytes(len) => {
+                AbiValue::FixedBytes(FixedBytes::new(fixed(len.get())?).unwrap())
+            }
+            AbiKind::Function => {
+                let blob = fixed(24)?;
+                AbiValue::Function(ExternalFunction {
+                    address: Address(blob[..20].try_into().unwrap()),
+                    selector: Selector(blob[20..].try_into().unwrap()),
+                })
+            }
+            AbiKind::Bytes if value == SqlValueRef::Null => AbiValue::Bytes(Vec::new()),
+            AbiKind::String if value == SqlValueRef::Null => AbiValue::String(String::new()),
+            AbiKind::Bytes => AbiValue::Bytes(blob()?.to_vec()),
+            AbiKind::String => {
+                // Tables created before strings were stored as text have blobs.
+                let bytes = match value {
+                    SqlValueRef::Text(text) => text,
+                    _ => blob()?,
+                };
+                AbiValue::String(String::from_utf8(bytes.to_vec()).context("invalid utf-8")?)
             }
+            AbiKind::FixedArray(..) | AbiKind::Tuple(_) | AbiKind::Array(_) => {
+                return Err(anyhow!("{kind:?} is not stored in a single column"))
+            }
+        })
+    }
+
+    /// The `IntegerEncoding` of a prepared event's columns.
+    pub fn integer_encoding(&self, event: &str) -> Option<IntegerEncoding> {
+        self.inner
+            .events
+            .get(event)
+            .map(|event| event.integer_encoding)
+    }
+
+    /// Describes the tables and columns of all prepared events for generating
+    /// client code. The object is keyed by event name:
+    ///
+    /// ```json
+    /// {"event": {"tables": [{
+    ///   "name": "event",
+    ///   "fixed_columns": [{"name": "block_number", "sql_type": "INTEGER"}, ...],
+    ///   "columns": [{"name": "value_0", "sql_type": "BLOB", "kind": "uint256"}]
+    /// }]}}
+    /// ```
+    ///
+    /// Array tables additionally have `array_index` as last fixed column,
+    /// preceded by `array_id` with `Options::coalesce_arrays` and followed by
+    /// `array_index_1` and so on for nested arrays.
+    pub fn schema_json(&self) -> Result<serde_json::Value> {
+        let column = |name: &str, type_: &str| json!({"name": name, "sql_type": type_});
+        let mut events = serde_json::Map::new();
+        for (name, event) in &self.inner.events {
+            let tables = event_to_tables(name, &event.descriptor, self.inner.sanitizer())
+                .context("event_to_tables")?;
+            let arrays = self.inner.physical_arrays(&tables);
+            let depths = array_depths(&event.array_parents);
+            let tables = std::iter::once((0, &tables.primary))
+                .chain(depths.into_iter().zip(&arrays))
+                .map(|(depth, table)| {
+                    let is_array = depth > 0;
+                    let fixed_columns = self
+                        .inner
+                        .options
+                        .chain_id
+                        .then_some(("chain_id", SqlType::Integer))
+                        .into_iter()
+                        .chain([
+                            ("block_number", SqlType::Integer),
+                            ("log_index", SqlType::Integer),
+                            ("transaction_index", SqlType::Integer),
+                            ("address", SqlType::Blob),
+                        ])
+                        .chain(
+                            (is_array && self.inner.options.coalesce_arrays)
+                                .then_some(("array_id", SqlType::Integer)),
+                        )
+                        .map(|(name, type_)| column(name, sql_type_name(type_)))
+                        .chain(
+                            array_index_columns(depth)
+                                .map(|name| column(&name, sql_type_name(SqlType::Integer))),
+                        )
+                        .collect::<Vec<_>>();
+                    let columns = table
+                        .columns
+                        .iter()
+                        .flat_map(|column_| {
+                            let type_ = sql_type_name(self.inner.leaf_sql_type(
+                                column_.kind,
+                                event.blob_strings,
+                                event.integer_encoding,
+                            ));
+                            let kind = kind_name(column_.kind);
+                            self.inner
+                                .column_names(column_)
+                                .into_iter()
+                                .map(move |name| {
+                                    let mut column = column(&name, type_);
+                                    column["kind"] = kind.clone().into();
+                                    column
+                                })
+                        })
+                        .collect::<Vec<_>>();
+                    json!({
+                        "name": format!("{}{}", self.inner.options.table_prefix, table.name),
+                        "fixed_columns": fixed_columns,
+                        "columns": columns,
+                    })
+                })
+                .collect::<Vec<_>>();
+            events.insert(name.clone(), json!({ "tables": tables }));
         }
+        Ok(events.into())
+    }
 
-        // Outer vec maps to tables. Inner vec maps to (array element count, columns).
-        let mut sql_values: Vec<(Option<usize>, Vec<ToSqlOutput<'a>>)> = vec![(None, vec![])];
-        let mut in_array: bool = false;
-        let mut visitor = |value: VisitValue<'a>| {
-            let sql_value = match value {
-                VisitValue::ArrayStart(len) => {
-                    sql_values.push((Some(len), Vec::new()));
-                    in_array = true;
-                    return;
-                }
-                VisitValue::ArrayEnd => {
-                    in_array = false;
-                    return;
-                }
-                VisitValue::Value(AbiValue::Int(v)) => {
-                    ToSqlOutput::Owned(SqlValue::Blob(v.get().to_be_bytes().to_vec()))
-                }
-                VisitValue::Value(AbiValue::Uint(v)) => {
-                    ToSqlOutput::Owned(SqlValue::Blob(v.get().to_be_bytes().to_vec()))
+    /// Human readable description of an event's tables for debugging: their
+    /// columns with SQL types, primary keys and current row counts. For a
+    /// machine readable schema see `schema_json`.
+    pub fn describe(&self, name: &str) -> Result<String> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let mut out = String::new();
+        let inputs = event
+            .descriptor
+            .inputs
+            .iter()
+            .map(|input| {
+                let mut input_ = kind_name(&input.field.kind);
+                if input.indexed {
+                    input_.push_str(" indexed");
                 }
-                VisitValue::Value(AbiValue::Address(v)) => {
-                    ToSqlOutput::Borrowed(SqlValueRef::Blob(&v.0))
+                if !input.field.name.is_empty() {
+                    write!(&mut input_, " {}", input.field.name).unwrap();
                 }
-                VisitValue::Value(AbiValue::Bool(v)) => {
-                    ToSqlOutput::Owned(SqlValue::Integer(*v as i64))
+                input_
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            &mut out,
+            "event {name}: {}({})",
+            event.descriptor.name,
+            inputs.join(", ")
+        )
+        .unwrap();
+        for table in std::iter::once(&event.table).chain(&event.array_tables) {
+            let rows: i64 = self
+                .connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table};"), (), |row| {
+                    row.get(0)
+                })
+                .context("count rows")?;
+            writeln!(&mut out, "table {table} ({rows} rows)").unwrap();
+            let mut statement = self
+                .connection
+                .prepare(
+                    "SELECT name, type, \"notnull\", pk, hidden FROM pragma_table_xinfo(?1) \
+                     ORDER BY cid;",
+                )
+                .context("prepare table_xinfo")?;
+            let columns = statement
+                .query_map((table,), |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                })
+                .context("query table_xinfo")?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .context("read table_xinfo")?;
+            for (column, type_, not_null, _, hidden) in &columns {
+                write!(&mut out, "  {column} {type_}").unwrap();
+                if *not_null {
+                    write!(&mut out, " NOT NULL").unwrap();
                 }
-                VisitValue::Value(AbiValue::FixedBytes(v)) => {
-                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v.as_bytes()))
+                if *hidden != 0 {
+                    write!(&mut out, " GENERATED").unwrap();
                 }
-                VisitValue::Value(AbiValue::Function(v)) => ToSqlOutput::Owned(SqlValue::Blob(
-                    v.address
-                        .0
-                        .iter()
-                        .copied()
-                        .chain(v.selector.0.iter().copied())
-                        .collect(),
-                )),
-                VisitValue::Value(AbiValue::Bytes(v)) => {
-                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v))
+                writeln!(&mut out).unwrap();
+            }
+            let mut key = columns
+                .iter()
+                .filter(|column| column.3 > 0)
+                .collect::<Vec<_>>();
+            key.sort_by_key(|column| column.3);
+            let key = key
+                .iter()
+                .map(|column| column.0.as_str())
+                .collect::<Vec<_>>();
+            writeln!(&mut out, "  PRIMARY KEY({})", key.join(", ")).unwrap();
+        }
+        Ok(out)
+    }
+
+    /// The tables that arak created for the prepared events and for itself,
+    /// sorted by name, for tooling that backs up or drops them. Partitioned
+    /// tables are listed as their partitions; their views aren't tables.
+    /// Tables of events that aren't prepared on this instance are missing.
+    ///
+    /// Errors if one of the tables doesn't exist in the database.
+    pub fn managed_tables(&self) -> Result<Vec<String>> {
+        let mut tables = INTERNAL_TABLES
+            .iter()
+            .map(|table| format!("{}{table}", self.inner.options.table_prefix))
+            .collect::<Vec<_>>();
+        for event in self.inner.events.values() {
+            for table in std::iter::once(&event.table).chain(&event.array_tables) {
+                if self.inner.options.partition_size.is_some() {
+                    tables.extend(
+                        partitions(&self.connection, table)?
+                            .into_iter()
+                            .map(|partition| format!("{table}_p{partition}")),
+                    );
+                } else if let Some((union_table, _)) = self.inner.unions.get(table) {
+                    tables.push(union_table.clone());
+                } else {
+                    tables.push(table.clone());
                 }
-                VisitValue::Value(AbiValue::String(v)) => {
-                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v.as_bytes()))
+            }
+        }
+        tables.sort();
+        tables.dedup();
+        for table in &tables {
+            let exists: bool = self
+                .connection
+                .prepare_cached(TABLE_EXISTS)
+                .context("prepare_cached table_exists")?
+                .query_row((table,), |row| row.get(0))
+                .context("query table_exists")?;
+            if !exists {
+                return Err(anyhow!("managed table {table} doesn't exist"));
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Compares the block information of every prepared event to its stored
+    /// logs, ordered by event name. Only reads, so that the warnings can be
+    /// reviewed before fixing anything.
+    pub fn verify_cursors(&self) -> Result<Vec<CursorWarning>> {
+        let mut names = self.inner.events.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut warnings = Vec::new();
+        for name in names {
+            let event = &self.inner.events[name];
+            let block = self.inner.event_block(&self.connection, name)?;
+            let max_block: Option<i64> = self
+                .connection
+                .query_row(
+                    &format!("SELECT MAX(block_number) FROM {};", event.table),
+                    (),
+                    |row| row.get(0),
+                )
+                .context("query max block_number")?;
+            let event = name.clone();
+            if let Some(max_block) = max_block {
+                let max_block = u64::try_from(max_block).context("block out of bounds")?;
+                if max_block > block.indexed {
+                    warnings.push(CursorWarning::LogsPastIndexed {
+                        event: event.clone(),
+                        indexed: block.indexed,
+                        max_block,
+                    });
+                } else if block.indexed - max_block > SUSPICIOUS_CURSOR_GAP {
+                    warnings.push(CursorWarning::LargeGap {
+                        event: event.clone(),
+                        indexed: block.indexed,
+                        max_block,
+                    });
                 }
-                _ => unreachable!(),
-            };
-            (if in_array {
-                <[_]>::last_mut
-            } else {
-                <[_]>::first_mut
-            })(&mut sql_values)
-            .unwrap()
-            .1
-            .push(sql_value);
+            }
+            if block.finalized > block.indexed {
+                warnings.push(CursorWarning::FinalizedPastIndexed {
+                    event,
+                    indexed: block.indexed,
+                    finalized: block.finalized,
+                });
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Checks that every row in the event's dynamic array tables belongs to a
+    /// log in the primary table.
+    ///
+    /// The opposite direction, logs that are missing their array rows, can't
+    /// be detected because empty arrays don't have any rows.
+    pub fn check_integrity(&self, name: &str) -> Result<Vec<Inconsistency>> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let chain_id = if self.inner.options.chain_id {
+            " AND p.chain_id = a.chain_id"
+        } else {
+            ""
         };
-        for value in fields {
-            event_visitor::visit_value(value, &mut visitor)
+        let mut inconsistencies = Vec::new();
+        for table in &event.array_tables {
+            let sql = format!(
+                "SELECT DISTINCT a.block_number, a.log_index FROM {table} a WHERE NOT EXISTS \
+                 (SELECT 1 FROM {} p WHERE p.block_number = a.block_number AND p.log_index = \
+                 a.log_index{chain_id}) ORDER BY a.block_number, a.log_index;",
+                event.table
+            );
+            let mut statement = self
+                .connection
+                .prepare(&sql)
+                .context("prepare check_integrity")?;
+            let mut rows = statement.query(()).context("query check_integrity")?;
+            while let Some(row) = rows.next().context("next row")? {
+                let block_number: i64 = row.get(0)?;
+                let log_index: i64 = row.get(1)?;
+                inconsistencies.push(Inconsistency::OrphanedArrayRow {
+                    table: table.clone(),
+                    block_number: block_number.try_into().context("block out of bounds")?,
+                    log_index: log_index.try_into().context("log index out of bounds")?,
+                });
+            }
         }
+        Ok(inconsistencies)
+    }
 
-        let block_number =
-            ToSqlOutput::Owned(SqlValue::Integer((*block_number).try_into().unwrap()));
-        let log_index = ToSqlOutput::Owned(SqlValue::Integer((*log_index).try_into().unwrap()));
-        let transaction_index =
-            ToSqlOutput::Owned(SqlValue::Integer((*transaction_index).try_into().unwrap()));
-        let address = ToSqlOutput::Borrowed(SqlValueRef::Blob(&address.0));
-        for (statement, (array_element_count, values)) in
-            event.insert_statements.iter().zip(sql_values)
+    /// Returns the removals of the event's logs because of reorgs, oldest
+    /// first.
+    pub fn reorg_history(&self, name: &str) -> Result<Vec<ReorgEntry>> {
+        let mut statement = self
+            .connection
+            .prepare_cached(&self.inner.sql(GET_REORG_LOG))
+            .context("prepare_cached get_reorg_log")?;
+        let rows = statement
+            .query_map((name,), |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })
+            .context("query get_reorg_log")?;
+        rows.map(|row| {
+            let (from_block, removed_rows, at_time) = row.context("read row")?;
+            Ok(ReorgEntry {
+                from_block: from_block.try_into().context("block out of bounds")?,
+                removed_rows: removed_rows.try_into().context("rows out of bounds")?,
+                at: UNIX_EPOCH
+                    + Duration::from_secs(at_time.try_into().context("time out of bounds")?),
+            })
+        })
+        .collect()
+    }
+
+    /// Whether the event has been prepared on this instance. Names that aren't
+    /// valid table names as they are, even if they sanitize to the name of a
+    /// prepared event, are never contained, just like `prepare_event` rejects
+    /// them.
+    pub fn contains_event(&self, name: &str) -> bool {
+        self.inner.sanitizer().sanitize(name) == name && self.inner.events.contains_key(name)
+    }
+
+    /// Removes the logs of an event in the inclusive block range, for example
+    /// to index a window again that was indexed incorrectly. Returns the
+    /// number of removed rows including array rows.
+    ///
+    /// Unlike `Database::remove` this doesn't change the event's indexed
+    /// block because the tip of the chain is unchanged. The removed logs have
+    /// to be stored again explicitly.
+    pub fn remove_range(&mut self, name: &str, from: u64, to: u64) -> Result<u64> {
+        self.inner.check_writable()?;
+        self.inner.event_blocks.clear();
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let first = from;
+        let from = i64::try_from(from).context("from out of bounds")?;
+        let to = i64::try_from(to).context("to out of bounds")?;
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        let mut removed = 0;
+        for table in event
+            .array_tables
+            .iter()
+            .chain(std::iter::once(&event.table))
         {
-            let mut statement_ = conn
-                .prepare_cached(&statement.sql)
-                .context("prepare_cached event")?;
-            let is_array = array_element_count.is_some();
-            let array_element_count = array_element_count.unwrap_or(1);
-            assert_eq!(statement.fields * array_element_count, values.len());
-            for i in 0..array_element_count {
-                let row = &values[i * statement.fields..][..statement.fields];
-                let array_index = if is_array {
-                    Some(ToSqlOutput::Owned(SqlValue::Integer(i.try_into().unwrap())))
-                } else {
-                    None
-                };
-                let params = rusqlite::params_from_iter(
-                    [&block_number, &log_index, &transaction_index, &address]
-                        .into_iter()
-                        .chain(array_index.as_ref())
-                        .chain(row),
-                );
-                statement_.insert(params).context("insert event")?;
+            for storage in self.inner.storages(&transaction, table, first)? {
+                removed += transaction
+                    .prepare_cached(&storage.delete("block_number BETWEEN ?1 AND ?2"))
+                    .context("prepare_cached remove_range")?
+                    .execute((from, to))
+                    .context("execute remove_range")?;
             }
         }
+        transaction.commit().context("commit")?;
+        Ok(removed as u64)
+    }
 
-        Ok(())
+    /// Recovery tool that repairs the indexed block of an event from the rows
+    /// that are actually stored and returns the new indexed block.
+    ///
+    /// The indexed block is raised to the highest block with a stored log. It
+    /// is never lowered because blocks after the last stored log might have
+    /// been indexed without containing any logs for this event; the rows don't
+    /// tell these two cases apart.
+    pub fn resync_indexed(&mut self, name: &str) -> Result<u64> {
+        self.inner.check_writable()?;
+        self.inner.event_blocks.clear();
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        let indexed = self.inner.event_block(&transaction, name)?.indexed;
+        let stored: Option<i64> = transaction
+            .query_row(
+                &format!("SELECT MAX(block_number) FROM {};", event.table),
+                (),
+                |row| row.get(0),
+            )
+            .context("query max block_number")?;
+        let stored = u64::try_from(stored.unwrap_or_default()).context("block out of bounds")?;
+        if stored > indexed {
+            transaction
+                .prepare_cached(&self.inner.sql(SET_INDEXED_BLOCK))
+                .context("prepare_cached set_indexed_block")?
+                .execute((name, i64::try_from(stored)?))
+                .context("execute set_indexed_block")?;
+        }
+        transaction.commit().context("commit")?;
+        Ok(stored.max(indexed))
     }
 
-    fn store_block(&self, conn: &Transaction, block_time: &BlockTime) -> Result<()> {
-        let number = ToSqlOutput::Owned(SqlValue::Integer((block_time.number).try_into().unwrap()));
-        let time = ToSqlOutput::Owned(SqlValue::Text(systemtime_to_string(
-            block_time.timestamp,
-            None,
-        )));
-        conn.prepare_cached(INSERT_BLOCK)
-            .context("prepare_cached block insert")?
-            .execute([number, time])
-            .context("insert block")?;
-        Ok(())
+    /// Like `Database::remove` but returns how many rows were deleted from
+    /// each table of the uncled events, including tables without deleted
+    /// rows, in the order the tables were first touched. Rows of all
+    /// partitions of a table count towards the table. A reorg that removes
+    /// nothing is deeper than the indexed data or hit an event without logs
+    /// at the uncled blocks.
+    pub fn remove_reporting(&mut self, uncles: &[database::Uncle]) -> Result<Vec<(String, usize)>> {
+        self.inner.event_blocks.clear();
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        let report = self.inner.remove(&transaction, uncles)?;
+        transaction.commit().context("commit")?;
+        Ok(report)
     }
 
-    fn store_transaction(&self, conn: &Transaction, tx: &database::Transaction) -> Result<()> {
-        let block_number =
-            ToSqlOutput::Owned(SqlValue::Integer((tx.block_number).try_into().unwrap()));
-        let index = ToSqlOutput::Owned(SqlValue::Integer((tx.index).try_into().unwrap()));
-        let hash = ToSqlOutput::Owned(SqlValue::Blob(tx.hash.to_vec()));
-        let from = ToSqlOutput::Owned(SqlValue::Blob(tx.from.to_vec()));
-        let to = ToSqlOutput::Owned(match tx.to {
-            Some(val) => SqlValue::Blob(val.to_vec()),
-            None => SqlValue::Null,
-        });
+    /// Like `Database::remove` with an uncle at `block` for every prepared
+    /// event, rewinding the whole database to before `block` in one
+    /// transaction.
+    pub fn remove_all_from(&mut self, block: u64) -> Result<()> {
+        let names = self.inner.events.keys().cloned().collect::<Vec<_>>();
+        let uncles = names
+            .iter()
+            .map(|event| database::Uncle {
+                event,
+                number: block,
+            })
+            .collect::<Vec<_>>();
+        self.inner.event_blocks.clear();
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        self.inner.remove(&transaction, &uncles)?;
+        transaction.commit().context("commit")
+    }
 
-        conn.prepare_cached(INSERT_TRANSACTION)
-            .context("prepare_cached transaction insert")?
-            .execute([block_number, index, hash, from, to])
-            .context("insert transaction")?;
-        Ok(())
+    /// Deletes all logs of an event and resets its indexed and finalized
+    /// blocks to 0 in one transaction, so that the indexer fills it again from
+    /// the start, for example after fixing a decoding bug. The tables and the
+    /// prepared event are kept.
+    pub fn reindex(&mut self, name: &str) -> Result<()> {
+        self.inner.check_writable()?;
+        self.inner.event_blocks.clear();
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        self.inner.reset_event(&transaction, name)?;
+        transaction.commit().context("commit")
     }
 
-    fn update(
-        &self,
-        con: &Transaction,
-        blocks: &[database::EventBlock],
-        logs: &[database::Log],
-        block_times: &[database::BlockTime],
-        transactions: &[database::Transaction],
-    ) -> Result<()> {
-        self.set_event_blocks(con, blocks)
-            .context("set_event_blocks")?;
-        for log in logs {
-            self.store_event(con, log).context("store_event")?;
+    /// Rewinds the whole database to genesis in one transaction: deletes all
+    /// logs, blocks and transactions and resets the indexed and finalized
+    /// blocks of every event to 0. `Database::remove` refuses to uncle block
+    /// 0 because it can't happen during a reorg, so this is the way to
+    /// deliberately start over.
+    pub fn remove_from_genesis(&mut self) -> Result<()> {
+        self.inner.check_writable()?;
+        self.inner.event_blocks.clear();
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        for name in self.inner.events.keys() {
+            self.inner.reset_event(&transaction, name)?;
         }
-        for block_time in block_times {
-            self.store_block(con, block_time).context("store_block")?;
+        for (remove, name) in [
+            (REMOVE_BLOCKS_FROM, "blocks"),
+            (REMOVE_TRANSACTIONS_FROM, "transactions"),
+        ] {
+            transaction
+                .prepare_cached(&self.inner.sql(remove))
+                .context("prepare_cached remove")?
+                .execute((0,))
+                .with_context(|| format!("execute remove {name}"))?;
+            transaction
+                .prepare_cached(&self.inner.sql(SET_EVENT_BLOCK))
+                .context("prepare_cached set_event_block")?
+                .execute((name, 0, 0))
+                .context("execute set_event_block")?;
         }
-        for tx in transactions {
-            self.store_transaction(con, tx)
-                .context("store_transaction")?;
+        transaction.commit().context("commit")
+    }
+
+    /// Prepares the statements of all prepared events for storing, removing
+    /// and `read_logs` as well as the shared statements, so that the first
+    /// operations after opening don't pay for preparing them. Only up to
+    /// `Options::statement_cache_capacity` statements stay cached so with many
+    /// events this only warms the most recently prepared ones.
+    pub fn warm_cache(&self) -> Result<()> {
+        let inner = &self.inner;
+        let order = inner.primary_order();
+        for event in inner.events.values() {
+            // The write statements of partitioned tables depend on the
+            // partition and the deletes of union members are restricted to
+            // the member's rows.
+            let writes =
+                inner.options.partition_size.is_none() && !inner.unions.contains_key(&event.table);
+            let tables = event.array_tables.iter().chain([&event.table]);
+            let statements = event
+                .insert_statements
+                .iter()
+                .map(|statement| statement.sql(&statement.table))
+                .chain(
+                    tables
+                        .zip(&event.remove_conditions)
+                        .map(|(table, condition)| Storage::plain(table.clone()).delete(condition)),
+                )
+                .chain(event.array_tables.iter().map(|table| {
+                    Storage::plain(table.clone()).delete(&event.clear_array_condition)
+                }))
+                .filter(|_| writes)
+                .chain(std::iter::once(read_sql(
+                    &event.table,
+                    inner.primary_filter(READ_LOGS_FILTER),
+                    &order,
+                )))
+                .chain(
+                    event
+                        .array_sources
+                        .iter()
+                        .zip(inner.array_orders(event))
+                        .map(|(table, order)| read_sql(table, READ_LOGS_FILTER, &order)),
+                );
+            for sql in statements {
+                self.connection
+                    .prepare_cached(&sql)
+                    .with_context(|| format!("prepare_cached {sql}"))?;
+            }
+        }
+        for sql in [
+            GET_EVENT_BLOCK,
+            SET_EVENT_BLOCK,
+            SET_INDEXED_BLOCK,
+            INSERT_BLOCK,
+            INSERT_TRANSACTION,
+        ] {
+            self.connection
+                .prepare_cached(&inner.sql(sql))
+                .with_context(|| format!("prepare_cached {sql}"))?;
         }
         Ok(())
     }
 
-    fn remove(&self, connection: &Connection, uncles: &[database::Uncle]) -> Result<()> {
-        let mut set_indexed_block: rusqlite::CachedStatement<'_> = connection
-            .prepare_cached(SET_INDEXED_BLOCK)
-            .context("prepare_cached set_indexed_block")?;
-        for uncle in uncles {
-            if uncle.number == 0 {
-                return Err(anyhow!("block 0 got uncled"));
-            }
-            let block = i64::try_from(uncle.number).context("block out of bounds")?;
-            let parent_block = block - 1;
-            let prepared = self.events.get(uncle.event).context("unprepared event")?;
-            for remove_statement in &prepared.remove_statements {
-                let mut remove_statement = connection
-                    .prepare_cached(remove_statement)
-                    .context("prepare_cached remove_statement")?;
-                remove_statement
-                    .execute((block,))
-                    .context("execute remove_statement")?;
-                set_indexed_block
-                    .execute((uncle.event, parent_block))
-                    .context("execute set_indexed_block")?;
-            }
+    /// Switches the database file to WAL mode so that readers on other
+    /// connections and the writer don't block each other, see `Sqlite`. The
+    /// mode is stored in the file so this only needs to happen once.
+    pub fn enable_wal(&self) -> Result<()> {
+        let mode: String = self
+            .connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))
+            .context("set journal_mode")?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            return Err(anyhow!("database can't use WAL, journal mode is {mode}"));
+        }
+        Ok(())
+    }
 
-            // Remove blocks and transactions as well.
-            let mut remove_statement = connection.prepare_cached(REMOVE_BLOCKS_FROM)?;
-            remove_statement
-                .execute((block,))
-                .context("execute remove_statement (blocks)")?;
-            set_indexed_block.execute(("blocks", parent_block))?;
+    /// Runs the reads of `f` in one read transaction, see `Sqlite`. Inside a
+    /// batch they already are.
+    fn snapshot<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if !self.connection.is_autocommit() {
+            return f();
+        }
+        let transaction = self
+            .connection
+            .unchecked_transaction()
+            .context("begin read")?;
+        let result = f()?;
+        // Only a read picks up a journal mode that another connection set.
+        self.check_journal_mode()?;
+        transaction.commit().context("commit read")?;
+        Ok(result)
+    }
 
-            let mut remove_statement = connection.prepare_cached(REMOVE_TRANSACTIONS_FROM)?;
-            remove_statement
-                .execute((block,))
-                .context("execute remove_statement (transactions)")?;
-            set_indexed_block.execute(("transactions", parent_block))?;
+    /// Warns once, or errors with `Options::require_wal`, if reads can block
+    /// the writer of the database file, see `Sqlite`. In-memory databases
+    /// can't use WAL.
+    fn check_journal_mode(&self) -> Result<()> {
+        if self.journal_checked.get() {
+            return Ok(());
+        }
+        if self.connection.path().is_some_and(|path| !path.is_empty()) {
+            let mode: String = self
+                .connection
+                .query_row("PRAGMA journal_mode;", (), |row| row.get(0))
+                .context("query journal_mode")?;
+            if !mode.eq_ignore_ascii_case("wal") {
+                if self.inner.options.require_wal {
+                    return Err(anyhow!("database is in journal mode {mode} instead of WAL"));
+                }
+                tracing::warn!(
+                    %mode,
+                    "database isn't in WAL mode, reads and updates from other connections block \
+                     each other"
+                );
+            }
         }
+        self.journal_checked.set(true);
         Ok(())
     }
-}
 
-fn abi_kind_to_sql_type(value: &AbiKind) -> Option<SqlType> {
-    match value {
-        AbiKind::Int(_) => Some(SqlType::Blob),
-        AbiKind::Uint(_) => Some(SqlType::Blob),
-        AbiKind::Address => Some(SqlType::Blob),
-        AbiKind::Bool => Some(SqlType::Integer),
-        AbiKind::FixedBytes(_) => Some(SqlType::Blob),
-        AbiKind::Function => Some(SqlType::Blob),
-        AbiKind::Bytes => Some(SqlType::Blob),
-        AbiKind::String => Some(SqlType::Blob),
-        AbiKind::FixedArray(_, _) | AbiKind::Tuple(_) | AbiKind::Array(_) => None,
+    /// The underlying connection for queries that this API doesn't cover,
+    /// like custom `SELECT`s over the event tables.
+    ///
+    /// Only read through it. Writes bypass the invariants arak keeps between
+    /// the event tables, the prepared events and the event blocks and are not
+    /// supported.
+    pub fn raw_connection(&self) -> &Connection {
+        &self.connection
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use {
-        super::*,
-        solabi::{
-            digest,
-            ethprim::Address,
-            function::{ExternalFunction, Selector},
-            value::{Array, FixedBytes, Int, Uint},
-        },
-        std::time::SystemTime,
-    };
 
-    #[test]
-    fn new_for_test() {
-        Sqlite::new_for_test();
+    /// Calls `f` with `raw_connection`.
+    pub fn with_connection<T>(&self, f: impl FnOnce(&Connection) -> T) -> T {
+        f(&self.connection)
     }
 
-    fn print_table(con: &Connection, table: &str) {
-        let mut statement = con.prepare(&format!("SELECT * FROM {table}")).unwrap();
+    #[cfg(test)]
+    /// Create a temporary in memory database for tests.
+    pub fn new_for_test() -> Self {
+        Self::in_memory(Default::default()).unwrap()
+    }
+}
+
+impl Database for Sqlite {
+    fn prepare_event<'a>(
+        &'a mut self,
+        name: &'a str,
+        event: &'a EventDescriptor,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let transaction = self.connection.savepoint().context("savepoint")?;
+            self.inner
+                .prepare_event(&transaction, name, event, &Default::default(), None)?;
+            transaction.commit().context("commit")
+        }
+        .boxed()
+    }
+
+    fn event_block<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, Result<database::Block>> {
+        async move { self.inner.event_block(&self.connection, name) }.boxed()
+    }
+
+    fn contains_event<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, bool> {
+        async move { Sqlite::contains_event(self, name) }.boxed()
+    }
+
+    fn update<'a>(
+        &'a mut self,
+        blocks: &'a [database::EventBlock],
+        logs: &'a [database::Log],
+        block_times: &'a [database::BlockTime],
+        transactions: &'a [database::Transaction],
+    ) -> BoxFuture<'a, Result<database::UpdateSummary>> {
+        async move { self.update_iter(blocks, logs, block_times, transactions) }.boxed()
+    }
+
+    fn remove<'a>(&'a mut self, uncles: &'a [database::Uncle]) -> BoxFuture<'a, Result<()>> {
+        async move { self.remove_reporting(uncles).map(|_| ()) }.boxed()
+    }
+}
+
+/// Columns that every event table has.
+const FIXED_COLUMNS: &str = "block_number INTEGER NOT NULL, log_index INTEGER NOT NULL, \
+                             transaction_index INTEGER NOT NULL, address BLOB NOT NULL";
+const FIXED_COLUMNS_COUNT: usize = 4;
+/// Last columns of primary tables, see `Options::raw_logs`.
+const RAW_COLUMNS: &str =
+    "raw_data BLOB NOT NULL, topic0 BLOB, topic1 BLOB, topic2 BLOB, topic3 BLOB";
+const RAW_COLUMN_NAMES: [&str; 5] = ["raw_data", "topic0", "topic1", "topic2", "topic3"];
+/// `SQLITE_MAX_COLUMN` of default SQLite builds.
+const DEFAULT_MAX_COLUMNS: usize = 2000;
+const PRIMARY_KEY: &str = "block_number ASC, log_index ASC";
+/// Replaces the primary key of primary tables, see `Options::packed_key`.
+const PACKED_KEY_CHECK: &str = "CHECK(block_number < 2147483648 AND log_index < 4294967296)";
+const PACKED_KEY: &str = "(?1 << 32) | ?2";
+/// Appended to the primary key, see `Options::transaction_index_key`.
+const PRIMARY_KEY_TRANSACTION_INDEX: &str = "transaction_index ASC";
+
+/// Optional first column of every event table. See `Options::chain_id`.
+const CHAIN_ID_COLUMN: &str = "chain_id INTEGER NOT NULL";
+const PRIMARY_KEY_CHAIN_ID: &str = "chain_id ASC";
+
+/// Column for array tables.
+const ARRAY_COLUMN: &str = "array_index INTEGER NOT NULL";
+/// Column of coalesced array tables, see `Options::coalesce_arrays`.
+const ARRAY_ID_COLUMN: &str = "array_id INTEGER NOT NULL";
+/// Column of union tables, see `Sqlite::prepare_union_event`.
+const SOURCE_EVENT_COLUMN: &str = "source_event TEXT NOT NULL";
+const PRIMARY_KEY_SOURCE_EVENT: &str = "source_event ASC";
+const PRIMARY_KEY_ARRAY_ID: &str = "array_id ASC";
+const PRIMARY_KEY_ARRAY: &str = "array_index ASC";
+
+/// The array index columns of an array table nested `depth` dynamic arrays
+/// deep: `array_index` for the outermost array, then `array_index_1` and so
+/// on for the elements of the enclosing arrays' elements.
+fn array_index_columns(depth: usize) -> impl Iterator<Item = String> {
+    (0..depth).map(|level| match level {
+        0 => "array_index".to_string(),
+        _ => format!("array_index_{level}"),
+    })
+}
+
+/// Per dynamic array its nesting depth, 1 for arrays directly in the event.
+fn array_depths(parents: &[Option<usize>]) -> Vec<usize> {
+    let mut depths = Vec::<usize>::with_capacity(parents.len());
+    for parent in parents {
+        depths.push(parent.map_or(1, |parent| depths[parent] + 1));
+    }
+    depths
+}
+
+const CREATE_BLOCKS_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS {prefix}blocks
+(
+    number INTEGER PRIMARY KEY,
+    time   TEXT    NOT NULL
+);"#;
+
+const INSERT_BLOCK: &str = "INSERT OR IGNORE INTO {prefix}blocks (number, time) VALUES (?1, ?2);";
+
+const REMOVE_BLOCKS_FROM: &str = "DELETE FROM {prefix}blocks WHERE number >=?1;";
+
+const CREATE_TRANSACTIONS_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS {prefix}transactions
+(
+    block_number INTEGER NOT NULL,
+    "index"      INTEGER NOT NULL,
+    hash         BLOB    NOT NULL,
+    "from"       BLOB    NOT NULL,
+    "to"         BLOB,
+    PRIMARY KEY (block_number, "index")
+);"#; // Can not add a new line here or will get Error:
+      // https://docs.rs/rusqlite/0.30.0/rusqlite/enum.Error.html#variant.MultipleStatement
+
+const INSERT_TRANSACTION: &str = r#"INSERT INTO {prefix}transactions (block_number, "index", hash, "from", "to")
+                                    VALUES (?1, ?2, ?3, ?4, ?5)
+                                    ON CONFLICT DO NOTHING;"#;
+
+const REMOVE_TRANSACTIONS_FROM: &str = "DELETE FROM {prefix}transactions WHERE block_number >=?1;";
+
+const CREATE_EVENT_BLOCK_TABLE: &str = "CREATE TABLE IF NOT EXISTS {prefix}_event_block(event \
+                                        TEXT PRIMARY KEY NOT NULL, indexed INTEGER NOT NULL, \
+                                        finalized INTEGER NOT NULL) STRICT;";
+const GET_EVENT_BLOCK: &str =
+    "SELECT indexed, finalized FROM {prefix}_event_block WHERE event = ?1;";
+const NEW_EVENT_BLOCK: &str = "INSERT INTO {prefix}_event_block (event, indexed, finalized) \
+                               VALUES(?1, 0, 0) ON CONFLICT(event) DO NOTHING;";
+const SET_EVENT_BLOCK: &str =
+    "UPDATE {prefix}_event_block SET indexed = ?2, finalized = ?3 WHERE event = ?1;";
+const SET_INDEXED_BLOCK: &str = "UPDATE {prefix}_event_block SET indexed = ?2 WHERE event = ?1";
+
+/// arak's own tables without the table prefix, see `Sqlite::managed_tables`.
+const INTERNAL_TABLES: [&str; 8] = [
+    "blocks",
+    "transactions",
+    "_event_block",
+    "_column_metadata",
+    "_reorg_log",
+    "_event_naming",
+    "_event_tables",
+    "_event_signature",
+];
+
+/// SQLite has no column comments so extra information about columns, like the
+/// Solidity `internal_type`, is stored here.
+const CREATE_COLUMN_METADATA_TABLE: &str = "CREATE TABLE IF NOT EXISTS \
+                                            {prefix}_column_metadata(table_name TEXT NOT NULL, \
+                                            column_name TEXT NOT NULL, internal_type TEXT NOT \
+                                            NULL, PRIMARY KEY(table_name, column_name)) STRICT;";
+const SET_COLUMN_METADATA: &str = "INSERT INTO {prefix}_column_metadata (table_name, column_name, \
+                                   internal_type) VALUES(?1, ?2, ?3) ON CONFLICT(table_name, \
+                                   column_name) DO UPDATE SET internal_type = ?3;";
+
+/// Audit trail of `remove`. `at_time` is in seconds since the Unix epoch.
+const CREATE_REORG_LOG_TABLE: &str = "CREATE TABLE IF NOT EXISTS {prefix}_reorg_log(event TEXT \
+                                      NOT NULL, from_block INTEGER NOT NULL, removed_rows \
+                                      INTEGER NOT NULL, at_time INTEGER NOT NULL) STRICT;";
+const INSERT_REORG_LOG: &str = "INSERT INTO {prefix}_reorg_log (event, from_block, removed_rows, \
+                                at_time) VALUES(?1, ?2, ?3, ?4);";
+const GET_REORG_LOG: &str = "SELECT from_block, removed_rows, at_time FROM {prefix}_reorg_log \
+                             WHERE event = ?1 ORDER BY rowid;";
+
+const CREATE_EVENT_NAMING_TABLE: &str = "CREATE TABLE IF NOT EXISTS {prefix}_event_naming(event \
+                                         TEXT PRIMARY KEY NOT NULL, sanitizer TEXT NOT NULL, \
+                                         integer_encoding TEXT) STRICT;";
+const SET_EVENT_NAMING: &str = "INSERT INTO {prefix}_event_naming (event, sanitizer) VALUES(?1, \
+                                ?2) ON CONFLICT(event) DO NOTHING;";
+const GET_EVENT_NAMING: &str = "SELECT sanitizer, integer_encoding FROM {prefix}_event_naming \
+                                WHERE event = ?1;";
+/// Records the encoding of an event the first time it is prepared. Rows from
+/// before encodings were stored have NULL.
+const SET_EVENT_INTEGER_ENCODING: &str = "UPDATE {prefix}_event_naming SET integer_encoding = ?2 \
+                                          WHERE event = ?1 AND integer_encoding IS NULL;";
+
+/// The event that created each event table, so that an event whose tables'
+/// names collide with another event's tables, like an event `foo_array_0` with
+/// the array table of event `foo`, is refused instead of sharing them.
+const CREATE_EVENT_TABLES_TABLE: &str = "CREATE TABLE IF NOT EXISTS {prefix}_event_tables(\
+                                         table_name TEXT PRIMARY KEY NOT NULL, event TEXT NOT \
+                                         NULL) STRICT;";
+const SET_EVENT_TABLE: &str = "INSERT INTO {prefix}_event_tables (table_name, event) VALUES(?1, \
+                               ?2) ON CONFLICT(table_name) DO NOTHING;";
+const GET_EVENT_TABLE: &str = "SELECT event FROM {prefix}_event_tables WHERE table_name = ?1;";
+const GET_EVENT_TABLES: &str =
+    "SELECT table_name FROM {prefix}_event_tables WHERE event = ?1 ORDER BY table_name;";
+
+/// The signature each event was first prepared with, see `event_signature`.
+const CREATE_EVENT_SIGNATURE_TABLE: &str = "CREATE TABLE IF NOT EXISTS \
+                                            {prefix}_event_signature(event TEXT PRIMARY KEY NOT \
+                                            NULL, hash INTEGER NOT NULL, signature TEXT NOT \
+                                            NULL) STRICT;";
+const SET_EVENT_SIGNATURE: &str = "INSERT INTO {prefix}_event_signature (event, hash, signature) \
+                                   VALUES(?1, ?2, ?3) ON CONFLICT(event) DO NOTHING;";
+const GET_EVENT_SIGNATURE: &str =
+    "SELECT hash, signature FROM {prefix}_event_signature WHERE event = ?1;";
+
+/// The column of a coalesced array table that stores `column` of the
+/// `array`-th dynamic array.
+fn coalesced_column<'a>(array: usize, column: &Column<'a>) -> Column<'a> {
+    Column {
+        name: format!("a{array}_{}", column.name),
+        ..column.clone()
+    }
+}
+
+/// `value` as an SQL string literal.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// `STRICT` tables need SQLite 3.37.
+const MIN_SQLITE_VERSION: (u32, u32) = (3, 37);
+
+fn check_sqlite_version(version: &str) -> Result<()> {
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    let (Some(Ok(major)), Some(Ok(minor))) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("unexpected SQLite version {version:?}"));
+    };
+    if (major, minor) < MIN_SQLITE_VERSION {
+        let (major, minor) = MIN_SQLITE_VERSION;
+        return Err(anyhow!(
+            "arak requires SQLite >= {major}.{minor} for STRICT tables but the linked SQLite \
+             is {version}"
+        ));
+    }
+    Ok(())
+}
+
+const TABLE_EXISTS: &str =
+    "SELECT COUNT(*) > 0 FROM sqlite_schema WHERE type = 'table' AND name = ?1";
+const TABLE_SQL: &str = "SELECT sql FROM sqlite_schema WHERE type = 'table' AND name = ?1";
+const VIEW_EXISTS: &str =
+    "SELECT COUNT(*) > 0 FROM sqlite_schema WHERE type = 'view' AND name = ?1";
+
+// Separate type because of lifetime issues when creating transactions. Outer
+// struct only stores the connection itself.
+struct SqliteInner {
+    /// Invariant: Events in the map have corresponding tables in the database.
+    ///
+    /// The key is the `name` argument when the event was passed into
+    /// `prepare_event`.
+    events: HashMap<String, PreparedEvent>,
+    options: Options,
+    /// Opened with `Sqlite::open_readonly`. Writes fail early with a clear
+    /// error instead of SQLite's.
+    read_only: bool,
+    clock: Clock,
+    /// The union tables of events prepared with `Sqlite::prepare_union_event`
+    /// by the names of their views, along with the event name in the
+    /// `source_event` column.
+    unions: HashMap<String, (String, String)>,
+    /// The committed indexed and finalized blocks of events as of their last
+    /// update, so that `set_event_blocks` neither reads nor writes blocks that
+    /// didn't change. Changes by other connections aren't seen.
+    event_blocks: HashMap<String, database::Block>,
+}
+
+/// An event is represented in the database in several tables.
+///
+/// All tables have some columns that are unrelated to the event's fields. See
+/// `FIXED_COLUMNS`. The first table contains all fields that exist once per
+/// event which means they do not show up in arrays. The other tables contain
+/// fields that are part of arrays. Those tables additionally have the column
+/// `ARRAY_COLUMN`.
+///
+/// The order of tables and fields is given by the `event_visitor` module.
+struct PreparedEvent {
+    descriptor: EventDescriptor,
+    /// Name of the primary table.
+    table: String,
+    /// Event field columns of the primary table.
+    columns: Vec<String>,
+    /// Names of the dynamic array tables.
+    array_tables: Vec<String>,
+    /// Per dynamic array the array it is nested in, see
+    /// `Tables::array_parents`.
+    array_parents: Vec<Option<usize>>,
+    /// Per table, primary first, its columns and constraints. Creates the
+    /// table or its partitions with `create_table_sql`.
+    table_definitions: Vec<String>,
+    /// Per dynamic array what to select its rows from: the array table, or a
+    /// subquery of the coalesced table with the same columns.
+    array_sources: Vec<String>,
+    insert_statements: Vec<InsertStatement>,
+    /// Per table, arrays first, the condition of the rows starting at some
+    /// block number, which is the parameter.
+    remove_conditions: Vec<String>,
+    /// The condition of the array rows of one log. Takes the primary key
+    /// columns without array index as parameters.
+    clear_array_condition: String,
+    /// Per table, primary first, per SQL column after the fixed ones the name
+    /// of its event column if that is nullable.
+    nullable: Vec<Vec<Option<String>>>,
+    /// Sorted names of the nullable columns.
+    nullable_columns: Vec<String>,
+    /// See `EventOptions::address`.
+    address: Option<Address>,
+    /// `signature_hash` of the descriptor.
+    signature_hash: i64,
+    /// Whether the string columns are BLOBs because the tables were created
+    /// before strings were stored as text.
+    blob_strings: bool,
+    /// The encoding stored for the event, see `Options::integer_encoding`.
+    integer_encoding: IntegerEncoding,
+}
+
+/// The tables of an event while `SqliteInner::prepare_event` prepares it.
+struct EventLayout<'a> {
+    name: &'a str,
+    /// The event as it is stored, see `stored_event`.
+    descriptor: &'a EventDescriptor,
+    options: &'a EventOptions<'a>,
+    tables: Tables<'a>,
+    /// The tables that store the arrays, which `tables.dynamic_arrays` still
+    /// describes separately.
+    physical_arrays: Vec<Table<'a>>,
+    /// See `array_depths`.
+    depths: Vec<usize>,
+    /// See `Sqlite::prepare_union_event`.
+    union_table: Option<String>,
+    /// Sorted names of the nullable columns.
+    nullable_columns: Vec<String>,
+}
+
+impl<'a> EventLayout<'a> {
+    /// The depth and the `index`-th table, primary first.
+    fn table(&self, index: usize) -> (usize, &Table<'a>) {
+        match index {
+            0 => (0, &self.tables.primary),
+            index => (self.depths[index - 1], &self.physical_arrays[index - 1]),
+        }
+    }
+}
+
+/// How the tables of an `EventLayout` are created or, if they exist, checked.
+struct TableSetup<'a> {
+    /// Whether the tables exist already.
+    existing: bool,
+    /// See `PreparedEvent::blob_strings`.
+    blob_strings: bool,
+    integer_encoding: IntegerEncoding,
+    /// See `EventOptions::max_lengths`.
+    max_lengths: HashMap<&'a str, usize>,
+    /// Per table, primary first, the number of columns.
+    column_counts: Vec<usize>,
+}
+
+/// The values of one occurrence of a dynamic array in a log, see
+/// `store_event`.
+type ArrayValues<'a> = (Vec<usize>, Option<usize>, Vec<ToSqlOutput<'a>>);
+
+/// A row of an array table with the rows of the arrays nested in it, see
+/// `decode_logs`.
+#[derive(Clone, Debug, Default)]
+struct ArrayRow {
+    values: Vec<SqlValue>,
+    /// Per array nested in the element, its rows ordered by array index.
+    arrays: Vec<Vec<ArrayRow>>,
+}
+
+/// Parameters:
+/// - chain id if `Options::chain_id` is set, shifting the other parameters
+/// - 1: block number
+/// - 2: log index
+/// - 3: transaction index
+/// - 4: address
+/// - 5: array index if this is an array table (all tables after the first)
+/// - 5 + n: n-th event field/column
+///
+/// The array id of coalesced array tables is part of the statement.
+#[derive(Debug)]
+struct InsertStatement {
+    /// The table the statement inserts into.
+    table: String,
+    /// `INSERT` with the conflict clause.
+    insert: &'static str,
+    /// The statement after the table name.
+    values: String,
+    /// Number of event fields that map to SQL columns. Does not count
+    /// FIXED_COLUMNS and array index.
+    fields: usize,
+}
+
+impl InsertStatement {
+    /// The statement inserting into `storage`, which is `table` or one of its
+    /// partitions.
+    fn sql(&self, storage: &str) -> String {
+        format!("{} INTO {storage} {}", self.insert, self.values)
+    }
+}
+
+impl SqliteInner {
+    fn new(connection: &Connection, options: Options) -> Result<Self> {
+        let prefix = &options.table_prefix;
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            || prefix.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Err(anyhow!("invalid table prefix {prefix:?}"));
+        }
+        if options.max_batch_size == Some(0) {
+            return Err(anyhow!("max_batch_size must not be 0"));
+        }
+        if options.max_batch_size.is_some() && options.on_conflict == OnConflict::Error {
+            return Err(anyhow!(
+                "max_batch_size needs an on_conflict other than Error to store the logs of a \
+                 failed update again"
+            ));
+        }
+        if options.partition_size == Some(0) {
+            return Err(anyhow!("partition_size must not be 0"));
+        }
+        if options.partition_size.is_some() && options.foreign_keys {
+            return Err(anyhow!(
+                "foreign keys can't reference the views of partitioned tables"
+            ));
+        }
+        if options.foreign_keys {
+            connection
+                .pragma_update(None, "foreign_keys", true)
+                .context("enable foreign_keys")?;
+        }
+        let this = Self {
+            events: Default::default(),
+            options,
+            read_only: connection
+                .is_readonly(DatabaseName::Main)
+                .context("is_readonly")?,
+            clock: SystemTime::now,
+            unions: Default::default(),
+            event_blocks: Default::default(),
+        };
+
+        let exists: bool = connection
+            .query_row(TABLE_EXISTS, (this.sql("{prefix}_event_block"),), |row| {
+                row.get(0)
+            })
+            .context("query table_exists")?;
+        if this.read_only {
+            // Nothing can be created or migrated so the database has to be
+            // complete already.
+            if !exists {
+                return Err(anyhow!("read-only database was not created by arak"));
+            }
+            let version: u32 = connection
+                .pragma_query_value(None, "user_version", |row| row.get(0))
+                .context("query user_version")?;
+            if version != SCHEMA_VERSION {
+                return Err(anyhow!(
+                    "read-only database has schema version {version} instead of \
+                     {SCHEMA_VERSION}"
+                ));
+            }
+            return Ok(this);
+        }
+        if exists {
+            this.migrate(connection, MIGRATIONS)?;
+        } else {
+            set_schema_version(connection, SCHEMA_VERSION)?;
+        }
+
+        connection
+            .execute(&this.sql(CREATE_EVENT_BLOCK_TABLE), ())
+            .context("create event_block table")?;
+        connection
+            .execute(&this.sql(CREATE_COLUMN_METADATA_TABLE), ())
+            .context("create column_metadata table")?;
+        connection
+            .execute(&this.sql(CREATE_REORG_LOG_TABLE), ())
+            .context("create reorg_log table")?;
+        connection
+            .execute(&this.sql(CREATE_EVENT_NAMING_TABLE), ())
+            .context("create event_naming table")?;
+        connection
+            .execute(&this.sql(CREATE_EVENT_TABLES_TABLE), ())
+            .context("create event_tables table")?;
+        connection
+            .execute(&this.sql(CREATE_EVENT_SIGNATURE_TABLE), ())
+            .context("create event_signature table")?;
+
+        connection
+            .prepare_cached(&this.sql(GET_EVENT_BLOCK))
+            .context("prepare get_event_block")?;
+        connection
+            .prepare_cached(&this.sql(SET_EVENT_BLOCK))
+            .context("prepare set_event_block")?;
+        connection
+            .prepare_cached(&this.sql(SET_INDEXED_BLOCK))
+            .context("prepare set_indexed_block")?;
+        connection
+            .prepare_cached(TABLE_EXISTS)
+            .context("prepare table_exists")?;
+
+        connection
+            .execute(&this.sql(CREATE_BLOCKS_TABLE), [])
+            .context("create blocks table")?;
+        connection
+            .execute(&this.sql(CREATE_TRANSACTIONS_TABLE), [])
+            .context("create transactions table")?;
+
+        let mut new_event_block = connection
+            .prepare_cached(&this.sql(NEW_EVENT_BLOCK))
+            .context("prepare new_event_block")?;
+        new_event_block
+            .execute((&"blocks",))
+            .context("add blocks to _event_blocks")?;
+        new_event_block
+            .execute((&"transactions",))
+            .context("add transactions to _event_blocks")?;
+
+        Ok(this)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("database is opened read-only"));
+        }
+        Ok(())
+    }
+
+    /// Fills in the table prefix of one of the SQL constants.
+    fn sql(&self, template: &str) -> String {
+        template.replace("{prefix}", &self.options.table_prefix)
+    }
+
+    fn event_block(&self, con: &Connection, name: &str) -> Result<database::Block> {
+        let mut statement = con
+            .prepare_cached(&self.sql(GET_EVENT_BLOCK))
+            .context("prepare_cached")?;
+        let block: (i64, i64) = statement
+            .query_row((name,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("query_row")?;
+        Ok(database::Block {
+            indexed: block.0.try_into().context("indexed out of bounds")?,
+            finalized: block.1.try_into().context("finalized out of bounds")?,
+        })
+    }
+
+    /// Remembers the blocks that `set_event_blocks` set once they are
+    /// committed. Inside a batch they are forgotten instead because the batch
+    /// can still be rolled back.
+    fn cache_event_blocks(&mut self, con: &Connection, blocks: &[database::EventBlock]) {
+        for block in blocks {
+            if con.is_autocommit() {
+                self.event_blocks
+                    .insert(block.event.to_string(), block.block);
+            } else {
+                self.event_blocks.remove(block.event);
+            }
+        }
+    }
+
+    /// With `monotonic` moving the blocks of an event back is an error.
+    fn set_event_blocks(
+        &self,
+        con: &Connection,
+        blocks: &[database::EventBlock],
+        monotonic: bool,
+    ) -> Result<()> {
+        let mut statement = con
+            .prepare_cached(&self.sql(SET_EVENT_BLOCK))
+            .context("prepare_cached")?;
+        for block in blocks {
+            if block.is_event() && !self.events.contains_key(block.event) {
+                return Err(anyhow!("event {} wasn't prepared", block.event));
+            }
+            let indexed: i64 = block
+                .block
+                .indexed
+                .try_into()
+                .context("indexed out of bounds")?;
+            let finalized: i64 = block
+                .block
+                .finalized
+                .try_into()
+                .context("finalized out of bounds")?;
+            let current = match self.event_blocks.get(block.event) {
+                Some(current) => *current,
+                None => self.event_block(con, block.event)?,
+            };
+            let regresses =
+                block.block.indexed < current.indexed || block.block.finalized < current.finalized;
+            if monotonic && block.is_event() && regresses {
+                return Err(anyhow!(
+                    "event {} blocks would regress from {current:?} to {:?}",
+                    block.event,
+                    block.block
+                ));
+            }
+            // Polling indexers update blocks that didn't change on every poll.
+            if current == block.block {
+                continue;
+            }
+            let rows = statement
+                .execute((block.event, indexed, finalized))
+                .context("execute")?;
+            if rows != 1 {
+                return Err(anyhow!(
+                    "query unexpectedly changed {rows} rows instead of 1"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// With `union` the event is a member of that union table, see
+    /// `Sqlite::prepare_union_event`.
+    fn prepare_event(
+        &mut self,
+        con: &Connection,
+        name: &str,
+        event: &EventDescriptor,
+        options: &EventOptions,
+        union: Option<&str>,
+    ) -> Result<()> {
+        // TODO: Maybe have `CHECK` clauses to enforce things like address and
+        // integers having expected length. Fixed bytes already do.
+
+        let signature = event_signature(event);
+        let event = &stored_event(event);
+        let union_table = union.map(|union| {
+            format!(
+                "{}{}",
+                self.options.table_prefix,
+                self.sanitizer().sanitize(union)
+            )
+        });
+        if let Some(existing) = self.events.get(name) {
+            return self.check_prepared(
+                existing,
+                name,
+                event,
+                &signature,
+                union_table.as_ref(),
+                options,
+            );
+        }
+
+        let layout = self.event_layout(name, event, options, union_table)?;
+        let insert_statements = self.insert_statements(&layout);
+        let remove_conditions = self.remove_conditions(&layout);
+        let clear_array_condition = self.clear_array_condition();
+        // Every restart prepares all events again. Events whose tables were
+        // created exactly like they would be now skip storing and checking.
+        let (setup, table_definitions) = match self.stored_tables(con, &layout, &signature)? {
+            Some(stored) => stored,
+            None => {
+                // Read-only databases are checked like writable ones except
+                // that nothing is stored.
+                if self.read_only && !self.tables_exist(con, &layout)? {
+                    return Err(anyhow!(
+                        "database is opened read-only but the tables of event {name} don't \
+                         exist"
+                    ));
+                }
+                let stored_encoding = self.store_signature(con, name, &signature)?;
+                self.claim_tables(con, &layout, union)?;
+                let setup = self.table_setup(con, &layout, stored_encoding)?;
+                let table_definitions = self.create_tables(con, &layout, &setup)?;
+
+                if !self.read_only {
+                    let mut new_event_block = con
+                        .prepare_cached(&self.sql(NEW_EVENT_BLOCK))
+                        .context("prepare new_event_block")?;
+                    new_event_block
+                        .execute((&name,))
+                        .context("execute new_event_block")?;
+                }
+
+                self.check_statements(
+                    con,
+                    &layout,
+                    &insert_statements,
+                    &remove_conditions,
+                    &clear_array_condition,
+                )?;
+                (setup, table_definitions)
+            }
+        };
+        let array_sources = self.array_sources(&layout);
+
+        let EventLayout {
+            tables,
+            physical_arrays,
+            union_table,
+            nullable_columns,
+            ..
+        } = layout;
+        let tables_nullable = std::iter::once(&tables.primary)
+            .chain(&tables.dynamic_arrays)
+            .map(|table| {
+                table
+                    .columns
+                    .iter()
+                    .flat_map(|column| {
+                        let nullable = nullable_columns
+                            .contains(&column.name)
+                            .then(|| column.name.clone());
+                        std::iter::repeat_n(nullable, self.column_names(column).len())
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(union_table) = union_table {
+            self.unions
+                .insert(tables.primary.name.clone(), (union_table, name.to_string()));
+        }
+        self.events.insert(
+            name.to_string(),
+            PreparedEvent {
+                descriptor: event.clone(),
+                table: tables.primary.name.clone(),
+                columns: tables
+                    .primary
+                    .columns
+                    .iter()
+                    .flat_map(|column| self.column_names(column))
+                    .collect(),
+                array_tables: physical_arrays
+                    .iter()
+                    .map(|table| table.name.clone())
+                    .collect(),
+                array_parents: tables.array_parents.clone(),
+                array_sources,
+                table_definitions,
+                insert_statements,
+                remove_conditions,
+                clear_array_condition,
+                nullable: tables_nullable,
+                nullable_columns,
+                address: options.address,
+                signature_hash: signature_hash(&signature),
+                blob_strings: setup.blob_strings,
+                integer_encoding: setup.integer_encoding,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Checks that an event that is prepared already is prepared the same way
+    /// again.
+    fn check_prepared(
+        &self,
+        existing: &PreparedEvent,
+        name: &str,
+        event: &EventDescriptor,
+        signature: &str,
+        union_table: Option<&String>,
+        options: &EventOptions,
+    ) -> Result<()> {
+        if self.unions.get(&existing.table).map(|(table, _)| table) != union_table {
+            return Err(anyhow!(
+                "event {name} already exists with a different union table"
+            ));
+        }
+        if signature_hash(signature) != existing.signature_hash {
+            return Err(anyhow!(
+                "event {name} already exists with different signature {}, not {signature}",
+                event_signature(&existing.descriptor)
+            ));
+        }
+        if event != &existing.descriptor {
+            return Err(anyhow!(
+                "event {} (database name {name}) already exists with different signature",
+                event.name
+            ));
+        }
+        if nullable_columns(options) != existing.nullable_columns
+            || options.address != existing.address
+        {
+            return Err(anyhow!(
+                "event {name} already exists with different event options"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Stores the event's name sanitizer and signature or, if they are stored
+    /// already or the database is read-only, checks that they match. Returns the stored integer encoding.
+    fn store_signature(
+        &self,
+        con: &Connection,
+        name: &str,
+        signature: &str,
+    ) -> Result<Option<String>> {
+        let sanitizer = self.sanitizer();
+        if !self.read_only {
+            con.prepare_cached(&self.sql(SET_EVENT_NAMING))
+                .context("prepare_cached set_event_naming")?
+                .execute((name, sanitizer.id()))
+                .context("execute set_event_naming")?;
+        }
+        let (stored, stored_encoding): (String, Option<String>) = con
+            .prepare_cached(&self.sql(GET_EVENT_NAMING))
+            .context("prepare_cached get_event_naming")?
+            .query_row((name,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("query get_event_naming")?;
+        if stored != sanitizer.id() {
+            return Err(anyhow!(
+                "event {name} was created with name sanitizer {stored} instead of {}",
+                sanitizer.id()
+            ));
+        }
+
+        // Catches a changed event across restarts, which the tables alone
+        // don't when only names or `indexed` changed.
+        let hash = signature_hash(signature);
+        if !self.read_only {
+            con.prepare_cached(&self.sql(SET_EVENT_SIGNATURE))
+                .context("prepare_cached set_event_signature")?
+                .execute((name, hash, signature))
+                .context("execute set_event_signature")?;
+        }
+        let (stored_hash, stored): (i64, String) = con
+            .prepare_cached(&self.sql(GET_EVENT_SIGNATURE))
+            .context("prepare_cached get_event_signature")?
+            .query_row((name,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("query get_event_signature")?;
+        if stored_hash != hash {
+            return Err(anyhow!(
+                "event {name} was created with signature {stored} instead of {signature}"
+            ));
+        }
+
+        Ok(stored_encoding)
+    }
+
+    /// The tables of an event, after checking that the event can be stored
+    /// in them.
+    fn event_layout<'a>(
+        &self,
+        name: &'a str,
+        event: &'a EventDescriptor,
+        options: &'a EventOptions<'a>,
+        union_table: Option<String>,
+    ) -> Result<EventLayout<'a>> {
+        let mut tables =
+            event_to_tables(name, event, self.sanitizer()).context("unsupported event")?;
+        for table in std::iter::once(&mut tables.primary).chain(&mut tables.dynamic_arrays) {
+            table.name.insert_str(0, &self.options.table_prefix);
+        }
+        let physical_arrays = self.physical_arrays(&tables);
+        let depths = array_depths(&tables.array_parents);
+        let nested = depths.iter().any(|&depth| depth > 1);
+        if nested && self.options.coalesce_arrays {
+            return Err(anyhow!(
+                "event {name} has nested dynamic arrays, which coalesced arrays don't support"
+            ));
+        }
+        if union_table.is_some() {
+            if !tables.dynamic_arrays.is_empty() {
+                return Err(anyhow!(
+                    "event {name} has dynamic arrays, which union tables don't support"
+                ));
+            }
+            if self.options.partition_size.is_some()
+                || self.packed_key()
+                || options.address.is_some()
+            {
+                return Err(anyhow!(
+                    "union tables don't support partitions, packed keys or event addresses"
+                ));
+            }
+        }
+        let nullable_columns = nullable_columns(options);
+        for column in &nullable_columns {
+            let exists = std::iter::once(&tables.primary)
+                .chain(&tables.dynamic_arrays)
+                .any(|table| table.columns.iter().any(|c| &c.name == column));
+            if !exists {
+                return Err(anyhow!("event {name} has no column {column:?}"));
+            }
+        }
+
+        Ok(EventLayout {
+            name,
+            descriptor: event,
+            options,
+            tables,
+            physical_arrays,
+            depths,
+            union_table,
+            nullable_columns,
+        })
+    }
+
+    /// Stores that the tables of an event belong to it or, if they belong to
+    /// something already, checks that it is the event.
+    fn claim_tables(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        union: Option<&str>,
+    ) -> Result<()> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref union_table,
+            ..
+        } = *layout;
+        // Union tables are owned by the union so that no event takes their
+        // name.
+        let union_owner = union.map(|union| format!("union {union}"));
+        let owned_tables = std::iter::once(&tables.primary)
+            .chain(physical_arrays)
+            .map(|table| (table.name.as_str(), name))
+            .chain(union_table.as_deref().zip(union_owner.as_deref()));
+        for (table, event) in owned_tables {
+            if !self.read_only {
+                con.prepare_cached(&self.sql(SET_EVENT_TABLE))
+                    .context("prepare_cached set_event_table")?
+                    .execute((table, event))
+                    .context("execute set_event_table")?;
+            }
+            let owner: String = con
+                .prepare_cached(&self.sql(GET_EVENT_TABLE))
+                .context("prepare_cached get_event_table")?
+                .query_row((table,), |row| row.get(0))
+                .context("query get_event_table")?;
+            if owner != event {
+                let owner = match owner.starts_with("union ") {
+                    true => owner,
+                    false => format!("event {owner}"),
+                };
+                return Err(anyhow!(
+                    "table {table} of event {name} already belongs to {owner}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The setup and definitions of the tables of an event if an earlier
+    /// preparation created them exactly like this one would, in which case
+    /// nothing has to be stored or checked. Looked up without writing.
+    fn stored_tables<'a>(
+        &self,
+        con: &Connection,
+        layout: &EventLayout<'a>,
+        signature: &str,
+    ) -> Result<Option<(TableSetup<'a>, Vec<String>)>> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref union_table,
+            ..
+        } = *layout;
+        // The view of a union member doesn't show how it was created.
+        if union_table.is_some() {
+            return Ok(None);
+        }
+        let naming: Option<(String, Option<String>)> = con
+            .prepare_cached(&self.sql(GET_EVENT_NAMING))
+            .context("prepare_cached get_event_naming")?
+            .query_row((name,), |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()
+            .context("query get_event_naming")?;
+        let Some((sanitizer, Some(stored_encoding))) = naming else {
+            return Ok(None);
+        };
+        let integer_encoding = IntegerEncoding::from_id(&stored_encoding)?;
+        if sanitizer != self.sanitizer().id()
+            || self
+                .options
+                .integer_encoding
+                .is_some_and(|configured| configured != integer_encoding)
+        {
+            return Ok(None);
+        }
+        let stored_hash: Option<i64> = con
+            .prepare_cached(&self.sql(GET_EVENT_SIGNATURE))
+            .context("prepare_cached get_event_signature")?
+            .query_row((name,), |row| row.get(0))
+            .optional()
+            .context("query get_event_signature")?;
+        if stored_hash != Some(signature_hash(signature)) {
+            return Ok(None);
+        }
+
+        let mut table_names = std::iter::once(&tables.primary)
+            .chain(physical_arrays)
+            .map(|table| table.name.as_str())
+            .collect::<Vec<_>>();
+        table_names.sort_unstable();
+        let owned_tables = con
+            .prepare_cached(&self.sql(GET_EVENT_TABLES))
+            .context("prepare_cached get_event_tables")?
+            .query_map((name,), |row| row.get::<_, String>(0))
+            .context("query get_event_tables")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read get_event_tables")?;
+        if owned_tables != table_names {
+            return Ok(None);
+        }
+        let mut stored_sql = Vec::new();
+        for index in 0..table_names.len() {
+            let storage = self.storage_table(&layout.table(index).1.name);
+            let sql: Option<String> = con
+                .prepare_cached(TABLE_SQL)
+                .context("prepare_cached table_sql")?
+                .query_row((&storage,), |row| row.get(0))
+                .optional()
+                .context("query table_sql")?;
+            let Some(sql) = sql else {
+                return Ok(None);
+            };
+            stored_sql.push((storage, sql));
+        }
+
+        // SQLite stores the statement that created a table without
+        // `IF NOT EXISTS` and the final semicolon.
+        let created = |storage: &str, definition: &str| {
+            format!(
+                "CREATE TABLE {storage} {}",
+                definition.trim_end_matches(';')
+            )
+        };
+        for blob_strings in [false, true] {
+            let setup = TableSetup {
+                existing: true,
+                blob_strings,
+                integer_encoding,
+                max_lengths: self.max_lengths(layout)?,
+                column_counts: self.column_counts(layout)?,
+            };
+            let table_definitions = (0..stored_sql.len())
+                .map(|index| self.table_definition(layout, &setup, index))
+                .collect::<Vec<_>>();
+            let unchanged = stored_sql
+                .iter()
+                .zip(&table_definitions)
+                .all(|((storage, sql), definition)| *sql == created(storage, definition));
+            if unchanged {
+                return Ok(Some((setup, table_definitions)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// How the tables of an event are created or, if they exist, checked.
+    fn table_setup<'a>(
+        &self,
+        con: &Connection,
+        layout: &EventLayout<'a>,
+        stored_encoding: Option<String>,
+    ) -> Result<TableSetup<'a>> {
+        let max_lengths = self.max_lengths(layout)?;
+        let column_counts = self.column_counts(layout)?;
+        let existing = self.tables_exist(con, layout)?;
+        // Tables from before columns were named after their path still have
+        // the leaf names.
+        if existing && layout.union_table.is_none() && !self.read_only {
+            self.rename_legacy_columns(con, layout.name, layout.descriptor)?;
+        }
+        let integer_encoding = self.integer_encoding(con, layout, existing, stored_encoding)?;
+        // Tables created before strings were stored as text have BLOB string
+        // columns, which keep storing blobs.
+        let blob_strings = existing && self.blob_strings(con, layout)?;
+        Ok(TableSetup {
+            existing,
+            blob_strings,
+            integer_encoding,
+            max_lengths,
+            column_counts,
+        })
+    }
+
+    /// See `EventOptions::max_lengths`.
+    fn max_lengths<'a>(&self, layout: &EventLayout<'a>) -> Result<HashMap<&'a str, usize>> {
+        let EventLayout {
+            name,
+            options,
+            ref tables,
+            ref physical_arrays,
+            ..
+        } = *layout;
+        options
+            .max_lengths
+            .iter()
+            .map(|&(column, length)| {
+                let kind = std::iter::once(&tables.primary)
+                    .chain(physical_arrays)
+                    .flat_map(|table| &table.columns)
+                    .find(|c| c.name == column)
+                    .map(|c| c.kind)
+                    .with_context(|| format!("event {name} has no column {column:?}"))?;
+                if !matches!(kind, AbiKind::Bytes | AbiKind::String)
+                    || type_mapper(&self.options, kind).is_some()
+                {
+                    return Err(anyhow!(
+                        "column {column:?} of event {name} is not a bytes or string column"
+                    ));
+                }
+                Ok((column, length))
+            })
+            .collect()
+    }
+
+    /// Per table, primary first, the number of columns.
+    fn column_counts(&self, layout: &EventLayout) -> Result<Vec<usize>> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref depths,
+            ..
+        } = *layout;
+        let max_columns = self.options.max_columns.unwrap_or(DEFAULT_MAX_COLUMNS);
+        let mut column_counts = Vec::new();
+        for (depth, table) in
+            std::iter::once((0, &tables.primary)).chain(depths.iter().copied().zip(physical_arrays))
+        {
+            let is_array = depth > 0;
+            let count = FIXED_COLUMNS_COUNT
+                + usize::from(self.options.chain_id)
+                + depth
+                + usize::from(is_array && self.options.coalesce_arrays)
+                + if !is_array && self.options.raw_logs {
+                    RAW_COLUMN_NAMES.len()
+                } else {
+                    0
+                }
+                + table
+                    .columns
+                    .iter()
+                    .map(|column| self.column_names(column).len())
+                    .sum::<usize>();
+            if count > max_columns {
+                return Err(anyhow!(
+                    "table {} of event {name} would have {count} columns but at most \
+                     {max_columns} are allowed; split the event's fields across events or \
+                     raise Options::max_columns up to SQLite's compile time limit",
+                    table.name
+                ));
+            }
+            column_counts.push(count);
+        }
+        Ok(column_counts)
+    }
+
+    /// Whether the tables of an event exist. Fails if only some of them do.
+    fn tables_exist(&self, con: &Connection, layout: &EventLayout) -> Result<bool> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref union_table,
+            ..
+        } = *layout;
+        // `CREATE TABLE IF NOT EXISTS` skips existing tables, so tables left
+        // from an interrupted or different preparation of the event have to be
+        // found here.
+        let table_names = std::iter::once(&tables.primary)
+            .chain(physical_arrays)
+            .map(|table| table.name.as_str())
+            .collect::<Vec<_>>();
+        let mut missing = Vec::new();
+        for table in &table_names {
+            let exists: bool = con
+                .prepare_cached(TABLE_EXISTS)
+                .context("prepare_cached table_exists")?
+                .query_row((self.storage_table(table),), |row| row.get(0))
+                .context("query table_exists")?;
+            if !exists {
+                missing.push(*table);
+            }
+        }
+        let extra = con
+            .prepare_cached(&self.sql(GET_EVENT_TABLES))
+            .context("prepare_cached get_event_tables")?
+            .query_map((name,), |row| row.get::<_, String>(0))
+            .context("query get_event_tables")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read get_event_tables")?
+            .into_iter()
+            .filter(|table| !table_names.contains(&table.as_str()))
+            .collect::<Vec<_>>();
+        if (!missing.is_empty() && missing.len() < table_names.len()) || !extra.is_empty() {
+            return Err(anyhow!(
+                "event {name} has a partial set of tables: missing {missing:?}, unexpected \
+                 {extra:?}"
+            ));
+        }
+
+        // Every restart prepares all events again. The stored signature matched
+        // so when all tables exist only the checks of the existing tables run.
+        Ok(match union_table {
+            // Members have a view of the union table instead of their own table.
+            Some(_) if missing.is_empty() => {
+                return Err(anyhow!(
+                    "event {name} has a table {} of its own instead of a view of the union table",
+                    tables.primary.name
+                ));
+            }
+            Some(_) => con
+                .query_row(VIEW_EXISTS, (&tables.primary.name,), |row| row.get(0))
+                .context("query view_exists")?,
+            None => missing.is_empty(),
+        })
+    }
+
+    /// The integer encoding of an event, which is stored unless it is stored
+    /// already.
+    fn integer_encoding(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        existing: bool,
+        stored_encoding: Option<String>,
+    ) -> Result<IntegerEncoding> {
+        let EventLayout {
+            name,
+            ref union_table,
+            ..
+        } = *layout;
+        // Members of a union share its table so they have to encode integers
+        // the same way.
+        let union_encoding = union_table.as_ref().and_then(|union_table| {
+            self.unions
+                .values()
+                .filter(|(table, _)| table == union_table)
+                .find_map(|(_, member)| self.events.get(member))
+                .map(|member| member.integer_encoding)
+        });
+        let tables_existed = existing
+            || match &union_table {
+                Some(union_table) => con
+                    .query_row(TABLE_EXISTS, (union_table,), |row| row.get(0))
+                    .context("query table_exists")?,
+                None => false,
+            };
+        let integer_encoding = match (stored_encoding, union_encoding) {
+            (Some(stored), _) => IntegerEncoding::from_id(&stored)?,
+            (None, Some(encoding)) => encoding,
+            (None, None) => self
+                .options
+                .integer_encoding
+                .unwrap_or(match tables_existed {
+                    true => IntegerEncoding::TwosComplement,
+                    false => IntegerEncoding::Biased,
+                }),
+        };
+        if let Some(configured) = self
+            .options
+            .integer_encoding
+            .filter(|&configured| configured != integer_encoding)
+        {
+            return Err(anyhow!(
+                "event {name} was created with integer encoding {integer_encoding:?} instead of \
+                 {configured:?}"
+            ));
+        }
+        if union_encoding.is_some_and(|encoding| encoding != integer_encoding) {
+            return Err(anyhow!(
+                "event {name} has integer encoding {integer_encoding:?} unlike the other members \
+                 of its union"
+            ));
+        }
+        if !self.read_only {
+            con.prepare_cached(&self.sql(SET_EVENT_INTEGER_ENCODING))
+                .context("prepare_cached set_event_integer_encoding")?
+                .execute((name, integer_encoding.id()))
+                .context("execute set_event_integer_encoding")?;
+        }
+        Ok(integer_encoding)
+    }
+
+    /// Whether the string columns of existing tables are BLOBs.
+    fn blob_strings(&self, con: &Connection, layout: &EventLayout) -> Result<bool> {
+        let EventLayout {
+            ref tables,
+            ref physical_arrays,
+            ..
+        } = *layout;
+        let string_column = std::iter::once(&tables.primary)
+            .chain(physical_arrays)
+            .flat_map(|table| table.columns.iter().map(move |column| (table, column)))
+            .find(|(_, column)| {
+                matches!(column.kind, AbiKind::String)
+                    && type_mapper(&self.options, column.kind).is_none()
+            });
+        let Some((table, column)) = string_column else {
+            return Ok(false);
+        };
+        let type_: String = con
+            .query_row(
+                "SELECT type FROM pragma_table_info(?1) WHERE name = ?2;",
+                (self.storage_table(&table.name), &column.name),
+                |row| row.get(0),
+            )
+            .context("query string column type")?;
+        Ok(type_ == "BLOB")
+    }
+
+    /// Creates the tables of an event unless they exist and checks them.
+    /// Returns their definitions, see `PreparedEvent::table_definitions`.
+    fn create_tables(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        setup: &TableSetup,
+    ) -> Result<Vec<String>> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref union_table,
+            ..
+        } = *layout;
+        let table_definitions = (0..setup.column_counts.len())
+            .map(|index| self.create_table(con, layout, setup, index))
+            .collect::<Result<Vec<_>>>()?;
+        if let (Some(union_table), false) = (union_table, setup.existing) {
+            self.create_union_view(
+                con,
+                name,
+                &tables.primary,
+                union_table,
+                setup.integer_encoding,
+            )?;
+        }
+
+        if !setup.existing {
+            let mut set_column_metadata = con
+                .prepare_cached(&self.sql(SET_COLUMN_METADATA))
+                .context("prepare set_column_metadata")?;
+            for table in std::iter::once(&tables.primary).chain(physical_arrays) {
+                for column in &table.columns {
+                    if let Some(internal_type) = column.internal_type {
+                        for name in self.column_names(column) {
+                            set_column_metadata
+                                .execute((&table.name, &name, internal_type))
+                                .context("execute set_column_metadata")?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(table_definitions)
+    }
+
+    /// Creates the `index`-th table of an event, primary first, unless it
+    /// exists and checks it. Returns its definition.
+    fn create_table(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        setup: &TableSetup,
+        index: usize,
+    ) -> Result<String> {
+        let sql = self.table_definition(layout, setup, index);
+        let (_, table) = layout.table(index);
+        let storage = match layout.union_table.as_ref().filter(|_| index == 0) {
+            Some(union_table) => union_table.clone(),
+            None => self.storage_table(&table.name),
+        };
+        tracing::debug!("creating table:\n{}", create_table_sql(&storage, &sql));
+        if !setup.existing {
+            con.execute(&create_table_sql(&storage, &sql), ())
+                .context("execute create_table")?;
+            if self.options.partition_size.is_some() {
+                create_view(con, &table.name)?;
+            }
+        }
+        self.check_table(con, layout, index, &storage, setup.column_counts[index])?;
+        Ok(sql)
+    }
+
+    /// The definition of the `index`-th table of an event, see
+    /// `PreparedEvent::table_definitions`.
+    fn table_definition(&self, layout: &EventLayout, setup: &TableSetup, index: usize) -> String {
+        let EventLayout {
+            options,
+            ref tables,
+            ref union_table,
+            ref nullable_columns,
+            ..
+        } = *layout;
+        let TableSetup {
+            blob_strings,
+            integer_encoding,
+            ref max_lengths,
+            ..
+        } = *setup;
+        let (depth, table) = layout.table(index);
+        let is_array = depth > 0;
+        let union_table = union_table.as_ref().filter(|_| !is_array);
+        let mut sql = String::new();
+        write!(&mut sql, "(").unwrap();
+        if self.options.chain_id {
+            write!(&mut sql, "{CHAIN_ID_COLUMN}, ").unwrap();
+        }
+        write!(&mut sql, "{FIXED_COLUMNS}").unwrap();
+        // `address` is the last fixed column.
+        if let Some(address) = &options.address {
+            let hex = address
+                .0
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>();
+            write!(&mut sql, " GENERATED ALWAYS AS (x'{hex}') VIRTUAL").unwrap();
+        }
+        write!(&mut sql, ", ").unwrap();
+        if union_table.is_some() {
+            write!(&mut sql, "{SOURCE_EVENT_COLUMN}, ").unwrap();
+        }
+        let coalesced = is_array && self.options.coalesce_arrays;
+        if coalesced {
+            write!(&mut sql, "{ARRAY_ID_COLUMN}, ").unwrap();
+        }
+        if is_array {
+            write!(&mut sql, "{ARRAY_COLUMN}, ").unwrap();
+        }
+        for column in array_index_columns(depth).skip(1) {
+            write!(&mut sql, "{column} INTEGER NOT NULL, ").unwrap();
+        }
+        for column in table.columns.iter() {
+            let mapped = type_mapper(&self.options, column.kind).is_some();
+            // Rows of the other arrays leave coalesced columns NULL.
+            let nullable = coalesced
+                || nullable_columns.contains(&column.name)
+                || (self.options.empty_as_null
+                    && !mapped
+                    && matches!(column.kind, AbiKind::Bytes | AbiKind::String));
+            if self.split_function(column.kind) {
+                let not_null = if nullable { "" } else { " NOT NULL" };
+                for (name, length) in self.column_names(column).iter().zip([20, 4]) {
+                    write!(
+                        &mut sql,
+                        "{name} BLOB{not_null} CHECK(length({name}) = {length}), "
+                    )
+                    .unwrap();
+                }
+                continue;
+            }
+            write!(&mut sql, "{}", column.name).unwrap();
+            let type_ =
+                sql_type_name(self.leaf_sql_type(column.kind, blob_strings, integer_encoding));
+            write!(&mut sql, " {type_}").unwrap();
+            if !nullable {
+                write!(&mut sql, " NOT NULL").unwrap();
+            }
+            if mapped {
+                write!(&mut sql, ", ").unwrap();
+                continue;
+            }
+            if type_ == "TEXT" && matches!(column.kind, AbiKind::Int(_) | AbiKind::Uint(_)) {
+                write!(&mut sql, " COLLATE {NUMERIC_COLLATION}").unwrap();
+            }
+            if let AbiKind::FixedBytes(length) = column.kind {
+                write!(
+                    &mut sql,
+                    " CHECK(length({}) = {})",
+                    column.name,
+                    length.get()
+                )
+                .unwrap();
+            }
+            if let Some(length) = max_lengths.get(column.name.as_str()) {
+                // `length` counts characters of text.
+                let value = match type_ {
+                    "TEXT" => format!("CAST({} AS BLOB)", column.name),
+                    _ => column.name.clone(),
+                };
+                write!(&mut sql, " CHECK(length({value}) <= {length})").unwrap();
+            }
+            write!(&mut sql, ", ").unwrap();
+        }
+        if !is_array && self.options.raw_logs {
+            write!(&mut sql, "{RAW_COLUMNS}, ").unwrap();
+        }
+        if is_array && self.options.foreign_keys {
+            let key = self
+                .options
+                .chain_id
+                .then_some("chain_id")
+                .into_iter()
+                .chain(["block_number", "log_index"])
+                .chain(
+                    self.options
+                        .transaction_index_key
+                        .then_some("transaction_index"),
+                )
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(
+                &mut sql,
+                "FOREIGN KEY({key}) REFERENCES {}({key}) ON DELETE CASCADE, ",
+                tables.primary.name
+            )
+            .unwrap();
+        }
+        let packed_key = !is_array && self.packed_key();
+        if packed_key {
+            write!(&mut sql, "{PACKED_KEY_CHECK}").unwrap();
+        } else {
+            write!(&mut sql, "PRIMARY KEY(").unwrap();
+            if union_table.is_some() {
+                write!(&mut sql, "{PRIMARY_KEY_SOURCE_EVENT}, ").unwrap();
+            }
+            if self.options.chain_id {
+                write!(&mut sql, "{PRIMARY_KEY_CHAIN_ID}, ").unwrap();
+            }
+            write!(&mut sql, "{PRIMARY_KEY}").unwrap();
+            if self.options.transaction_index_key {
+                write!(&mut sql, ", {PRIMARY_KEY_TRANSACTION_INDEX}").unwrap();
+            }
+            if coalesced {
+                write!(&mut sql, ", {PRIMARY_KEY_ARRAY_ID}").unwrap();
+            }
+            if is_array {
+                write!(&mut sql, ", {PRIMARY_KEY_ARRAY}").unwrap();
+            }
+            for column in array_index_columns(depth).skip(1) {
+                write!(&mut sql, ", {column} ASC").unwrap();
+            }
+            write!(&mut sql, ")").unwrap();
+        }
+        write!(&mut sql, ") STRICT;").unwrap();
+        sql
+    }
+
+    /// Checks that the `index`-th table of an event, stored in `storage`,
+    /// fits the event. The table might have existed already.
+    fn check_table(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        index: usize,
+        storage: &str,
+        column_count: usize,
+    ) -> Result<()> {
+        let EventLayout {
+            name,
+            options,
+            ref union_table,
+            ref nullable_columns,
+            ..
+        } = *layout;
+        let (depth, table) = layout.table(index);
+        let is_array = depth > 0;
+        let union_table = union_table.as_ref().filter(|_| !is_array);
+        let packed_key = !is_array && self.packed_key();
+        // Its primary key decides which logs are considered duplicates so it
+        // has to match.
+        let transaction_index_key: bool = con
+            .query_row(
+                "SELECT pk > 0 FROM pragma_table_info(?1) WHERE name = 'transaction_index';",
+                (storage,),
+                |row| row.get(0),
+            )
+            .context("query primary key")?;
+        let composite_key: bool = con
+            .query_row(
+                "SELECT pk > 0 FROM pragma_table_info(?1) WHERE name = 'block_number';",
+                (storage,),
+                |row| row.get(0),
+            )
+            .context("query primary key")?;
+        if transaction_index_key != self.options.transaction_index_key
+            || composite_key == packed_key
+        {
+            return Err(anyhow!(
+                "table {} exists with a different primary key",
+                storage
+            ));
+        }
+        let generated_address: bool = con
+            .query_row(
+                "SELECT hidden != 0 FROM pragma_table_xinfo(?1) WHERE name = 'address';",
+                (storage,),
+                |row| row.get(0),
+            )
+            .context("query address column")?;
+        if generated_address != options.address.is_some() {
+            return Err(anyhow!(
+                "table {} exists with a different address column",
+                storage
+            ));
+        }
+        let existing_count: usize = con
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_xinfo(?1);",
+                (storage,),
+                |row| row.get(0),
+            )
+            .context("query column count")?;
+        let column_count = column_count + usize::from(union_table.is_some());
+        if existing_count != column_count {
+            return Err(anyhow!(
+                "table {} exists with {existing_count} columns but event {name} needs \
+                 {column_count}",
+                storage
+            ));
+        }
+        let existing_columns = con
+            .prepare("SELECT name FROM pragma_table_xinfo(?1);")
+            .context("prepare table columns")?
+            .query_map((storage,), |row| row.get::<_, String>(0))
+            .context("query table columns")?
+            .collect::<Result<HashSet<_>, _>>()
+            .context("read table columns")?;
+        // Union tables have the column names of the union's first member.
+        let missing_column = table
+            .columns
+            .iter()
+            .filter(|_| union_table.is_none())
+            .flat_map(|column| self.column_names(column))
+            .find(|column| !existing_columns.contains(column));
+        if let Some(column) = missing_column {
+            return Err(anyhow!(
+                "table {} exists without column {column} of event {name}",
+                storage
+            ));
+        }
+        for column in &table.columns {
+            if !nullable_columns.contains(&column.name) {
+                continue;
+            }
+            for name in self.column_names(column) {
+                let not_null: bool = con
+                    .query_row(
+                        "SELECT \"notnull\" FROM pragma_table_info(?1) WHERE name = ?2;",
+                        (storage, &name),
+                        |row| row.get(0),
+                    )
+                    .context("query notnull")?;
+                if not_null {
+                    return Err(anyhow!(
+                        "column {name} of table {} exists as NOT NULL",
+                        storage
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Per table, primary first, the statement inserting its rows.
+    fn insert_statements(&self, layout: &EventLayout) -> Vec<InsertStatement> {
+        let EventLayout {
+            name,
+            options,
+            ref tables,
+            ref physical_arrays,
+            ref depths,
+            ref union_table,
+            ..
+        } = *layout;
+        std::iter::once((0, &tables.primary))
+            .chain(depths.iter().copied().zip(&tables.dynamic_arrays))
+            .enumerate()
+            .map(|(i, (depth, table))| {
+                let is_array = depth > 0;
+                let coalesced = (is_array && self.options.coalesce_arrays).then(|| i - 1);
+                // Array rows of conflicting logs are never inserted because
+                // they are either skipped or cleared first.
+                let insert = match self.options.on_conflict {
+                    OnConflict::Ignore if !is_array => "INSERT OR IGNORE",
+                    OnConflict::Replace if !is_array => "INSERT OR REPLACE",
+                    _ => "INSERT",
+                };
+                let raw_columns = if !is_array && self.options.raw_logs {
+                    &RAW_COLUMN_NAMES[..]
+                } else {
+                    &[]
+                };
+                let fields = table
+                    .columns
+                    .iter()
+                    .map(|column| self.column_names(column).len())
+                    .sum::<usize>()
+                    + raw_columns.len();
+                let mut sql = String::new();
+                if !is_array && self.packed_key() {
+                    // The rowid can only be set by naming the columns.
+                    let columns = ["block_number", "log_index", "transaction_index"]
+                        .into_iter()
+                        .map(str::to_string)
+                        .chain(options.address.is_none().then(|| "address".to_string()))
+                        .chain(
+                            table
+                                .columns
+                                .iter()
+                                .flat_map(|column| self.column_names(column)),
+                        )
+                        .chain(raw_columns.iter().map(|name| name.to_string()))
+                        .collect::<Vec<_>>();
+                    write!(
+                        &mut sql,
+                        "(rowid, {}) VALUES({PACKED_KEY},",
+                        columns.join(", ")
+                    )
+                    .unwrap();
+                } else if let Some(array) = coalesced {
+                    // Only this array's columns are set.
+                    let columns =
+                        self.options
+                            .chain_id
+                            .then(|| "chain_id".to_string())
+                            .into_iter()
+                            .chain(
+                                ["block_number", "log_index", "transaction_index"]
+                                    .into_iter()
+                                    .map(str::to_string),
+                            )
+                            .chain(options.address.is_none().then(|| "address".to_string()))
+                            .chain(std::iter::once("array_index".to_string()))
+                            .chain(table.columns.iter().flat_map(|column| {
+                                self.column_names(&coalesced_column(array, column))
+                            }))
+                            .collect::<Vec<_>>();
+                    write!(&mut sql, "({}, array_id) VALUES(", columns.join(", ")).unwrap();
+                } else {
+                    write!(&mut sql, "VALUES(").unwrap();
+                }
+                // The `source_event` column of union tables follows the fixed
+                // columns.
+                let source_event = union_table
+                    .as_ref()
+                    .filter(|_| !is_array)
+                    .map(|_| self.options.chain_id as usize + FIXED_COLUMNS_COUNT);
+                // A generated address isn't inserted.
+                for i in 0..self.options.chain_id as usize + FIXED_COLUMNS_COUNT
+                    - options.address.is_some() as usize
+                    + depth
+                    + fields
+                {
+                    if source_event == Some(i) {
+                        write!(&mut sql, "{},", sql_string(name)).unwrap();
+                    }
+                    write!(&mut sql, "?{},", i + 1).unwrap();
+                }
+                assert_eq!(sql.pop(), Some(','));
+                if let Some(array) = coalesced {
+                    write!(&mut sql, ", {array}").unwrap();
+                }
+                write!(&mut sql, ");").unwrap();
+                let table = match (coalesced, &union_table) {
+                    (Some(_), _) => physical_arrays[0].name.clone(),
+                    (None, Some(union_table)) if !is_array => union_table.clone(),
+                    (None, _) => table.name.clone(),
+                };
+                let statement = InsertStatement {
+                    table,
+                    insert,
+                    values: sql,
+                    fields,
+                };
+                tracing::debug!(
+                    "creating insert statement:\n{}",
+                    statement.sql(&statement.table)
+                );
+                statement
+            })
+            .collect()
+    }
+
+    /// See `PreparedEvent::remove_conditions`.
+    fn remove_conditions(&self, layout: &EventLayout) -> Vec<String> {
+        let EventLayout {
+            ref tables,
+            ref physical_arrays,
+            ..
+        } = *layout;
+        // Array tables first so that with foreign keys the array rows are
+        // counted instead of silently cascaded.
+        physical_arrays
+            .iter()
+            .chain(std::iter::once(&tables.primary))
+            .map(|table| {
+                if table.name == tables.primary.name && self.packed_key() {
+                    // Uses the rowid instead of scanning the table.
+                    "rowid >= (?1 << 32)".to_string()
+                } else {
+                    "block_number >= ?1".to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// See `PreparedEvent::clear_array_condition`.
+    fn clear_array_condition(&self) -> String {
+        self.options
+            .chain_id
+            .then_some("chain_id")
+            .into_iter()
+            .chain(["block_number", "log_index"])
+            .chain(
+                self.options
+                    .transaction_index_key
+                    .then_some("transaction_index"),
+            )
+            .enumerate()
+            .map(|(i, column)| format!("{column} = ?{}", i + 1))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+
+    /// See `PreparedEvent::array_sources`.
+    fn array_sources(&self, layout: &EventLayout) -> Vec<String> {
+        let EventLayout {
+            ref tables,
+            ref physical_arrays,
+            ..
+        } = *layout;
+        if self.options.coalesce_arrays {
+            let fixed = self
+                .options
+                .chain_id
+                .then_some("chain_id")
+                .into_iter()
+                .chain([
+                    "block_number",
+                    "log_index",
+                    "transaction_index",
+                    "address",
+                    "array_index",
+                ])
+                .map(str::to_string);
+            tables
+                .dynamic_arrays
+                .iter()
+                .enumerate()
+                .map(|(array, table)| {
+                    let columns =
+                        fixed
+                            .clone()
+                            .chain(table.columns.iter().flat_map(|column| {
+                                self.column_names(&coalesced_column(array, column))
+                            }))
+                            .collect::<Vec<_>>();
+                    format!(
+                        "(SELECT {} FROM {} WHERE array_id = {array})",
+                        columns.join(", "),
+                        physical_arrays[0].name
+                    )
+                })
+                .collect()
+        } else {
+            physical_arrays
+                .iter()
+                .map(|table| table.name.clone())
+                .collect()
+        }
+    }
+
+    /// Checks that the statements of an event are valid.
+    fn check_statements(
+        &self,
+        con: &Connection,
+        layout: &EventLayout,
+        insert_statements: &[InsertStatement],
+        remove_conditions: &[String],
+        clear_array_condition: &str,
+    ) -> Result<()> {
+        let EventLayout {
+            name,
+            ref tables,
+            ref physical_arrays,
+            ref union_table,
+            ..
+        } = *layout;
+        // Unfortunately we can't distinguish the statement being wrong from
+        // other Sqlite errors like being unable to access the database file on
+        // disk.
+        for statement in insert_statements {
+            con.prepare_cached(&statement.sql(&self.storage_table(&statement.table)))
+                .context("invalid prepared insert statement")?;
+        }
+        let table_conditions = physical_arrays
+            .iter()
+            .chain([&tables.primary])
+            .zip(remove_conditions.iter().map(String::as_str))
+            .chain(
+                physical_arrays
+                    .iter()
+                    .map(|table| (table, clear_array_condition)),
+            );
+        for (table, condition) in table_conditions {
+            let storage = match union_table {
+                Some(union_table) if table.name == tables.primary.name => Storage {
+                    table: union_table.clone(),
+                    source_event: Some(name.to_string()),
+                },
+                _ => Storage::plain(self.storage_table(&table.name)),
+            };
+            con.prepare_cached(&storage.delete(condition))
+                .context("invalid prepared remove statement")?;
+        }
+        Ok(())
+    }
+
+    /// Whether reads of primary tables can search the packed rowid key. The
+    /// views of partitioned tables have no rowid.
+    fn rowid_key(&self) -> bool {
+        self.packed_key() && self.options.partition_size.is_none()
+    }
+
+    /// `key_order` for the primary table, which is the rowid with packed
+    /// keys so that reads don't sort.
+    fn primary_order(&self) -> String {
+        match self.rowid_key() {
+            true => "rowid".to_string(),
+            false => self.key_order(),
+        }
+    }
+
+    /// Whether primary tables are keyed by the rowid, see
+    /// `Options::packed_key`.
+    fn packed_key(&self) -> bool {
+        self.options.packed_key
+            && !self.options.chain_id
+            && !self.options.transaction_index_key
+            && !self.options.foreign_keys
+    }
+
+    /// The tables that store the dynamic arrays of `tables`, which is a single
+    /// table with `Options::coalesce_arrays`.
+    fn physical_arrays<'a>(&self, tables: &Tables<'a>) -> Vec<Table<'a>> {
+        if !self.options.coalesce_arrays || tables.dynamic_arrays.is_empty() {
+            return tables.dynamic_arrays.clone();
+        }
+        vec![Table {
+            name: format!("{}_arrays", tables.primary.name),
+            columns: tables
+                .dynamic_arrays
+                .iter()
+                .enumerate()
+                .flat_map(|(array, table)| {
+                    table
+                        .columns
+                        .iter()
+                        .map(move |column| coalesced_column(array, column))
+                })
+                .collect(),
+        }]
+    }
+
+    /// The primary key columns without array index for `ORDER BY`.
+    fn key_order(&self) -> String {
+        format!(
+            "{}block_number, log_index{}",
+            if self.options.chain_id {
+                "chain_id, "
+            } else {
+                ""
+            },
+            if self.options.transaction_index_key {
+                ", transaction_index"
+            } else {
+                ""
+            },
+        )
+    }
+
+    /// Like `sql_type` for the tables of an event, see
+    /// `PreparedEvent::blob_strings`.
+    fn leaf_sql_type(
+        &self,
+        kind: &AbiKind,
+        blob_strings: bool,
+        integer_encoding: IntegerEncoding,
+    ) -> SqlType {
+        match kind {
+            AbiKind::String if blob_strings && type_mapper(&self.options, kind).is_none() => {
+                SqlType::Blob
+            }
+            kind => self.sql_type(kind, integer_encoding),
+        }
+    }
+
+    /// SQL type of a leaf kind's column.
+    fn sql_type(&self, kind: &AbiKind, integer_encoding: IntegerEncoding) -> SqlType {
+        if let Some(type_) = type_mapper(&self.options, kind).and_then(|m| m.column_type(kind)) {
+            return type_;
+        }
+        match kind {
+            AbiKind::Int(_) | AbiKind::Uint(_)
+                if integer_encoding == IntegerEncoding::DecimalText =>
+            {
+                SqlType::Text
+            }
+            kind => abi_kind_to_sql_type(kind).unwrap(),
+        }
+    }
+
+    fn sanitizer(&self) -> &dyn NameSanitizer {
+        match &self.options.name_sanitizer {
+            Some(sanitizer) => sanitizer.as_ref(),
+            None => &DefaultSanitizer,
+        }
+    }
+
+    /// Whether `kind` is stored in two columns, see `Options::split_functions`.
+    fn split_function(&self, kind: &AbiKind) -> bool {
+        self.options.split_functions
+            && kind == &AbiKind::Function
+            && type_mapper(&self.options, kind).is_none()
+    }
+
+    /// Names of the SQL columns that store an event column.
+    fn column_names(&self, column: &Column) -> Vec<String> {
+        if self.split_function(column.kind) {
+            vec![
+                format!("{}_address", column.name),
+                format!("{}_selector", column.name),
+            ]
+        } else {
+            vec![column.name.clone()]
+        }
+    }
+
+    fn store_event<'a>(
+        &self,
+        conn: &Connection,
+        Log {
+            event,
+            block_number,
+            log_index,
+            transaction_index,
+            address,
+            fields,
+            chain_id,
+            data,
+            topics,
+            nulls,
+        }: &'a Log,
+    ) -> Result<()> {
+        let name = event;
+        let event = self.events.get(event.as_ref()).context("unknown event")?;
+        let chain_id = match (self.options.chain_id, chain_id) {
+            (true, Some(chain_id)) => Some(ToSqlOutput::Owned(SqlValue::Integer(
+                (*chain_id).try_into().context("chain id out of bounds")?,
+            ))),
+            (true, None) => return Err(anyhow!("log is missing chain id")),
+            (false, _) => None,
+        };
+
+        let len = fields.len();
+        let expected_len = event.descriptor.inputs.len();
+        if fields.len() != expected_len {
+            return Err(anyhow!(
+                "event value has {len} fields but should have {expected_len}"
+            ));
+        }
+        for (i, (value, kind)) in fields.iter().zip(&event.descriptor.inputs).enumerate() {
+            if value.kind() != kind.field.kind {
+                return Err(anyhow!("event field {i} doesn't match event descriptor"));
+            }
+        }
+        if let Some(column) = nulls
+            .iter()
+            .find(|column| !event.nullable_columns.contains(column))
+        {
+            return Err(anyhow!("column {column:?} of event {name} isn't nullable"));
+        }
+
+        // Per table, primary first, the tables of the arrays directly in its
+        // elements.
+        let tables = event.insert_statements.len();
+        let mut children = vec![Vec::new(); tables];
+        for (array, parent) in event.array_parents.iter().enumerate() {
+            children[parent.map_or(0, |parent| parent + 1)].push(array + 1);
+        }
+        // Outer vec maps to tables. Inner vec maps to the occurrences of the
+        // table's array: (indices of the enclosing arrays' elements, array
+        // element count, columns).
+        let mut sql_values: Vec<Vec<ArrayValues<'a>>> = vec![Vec::new(); tables];
+        sql_values[0].push((Vec::new(), None, Vec::new()));
+        // The tables being visited, innermost last, with the indices of the
+        // enclosing arrays' elements and the number of arrays started in
+        // their elements so far.
+        let mut frames: Vec<(usize, Vec<usize>, usize)> = vec![(0, Vec::new(), 0)];
+        // Per table the SQL column the next leaf value goes into, which wraps
+        // to the first column when an array element's row is complete.
+        let mut positions = vec![0; tables];
+        // `visit_value` doesn't yield containers but a leaf value we can't store
+        // should be an error instead of a panic.
+        let mut unsupported = None;
+        // The values of the current table.
+        fn table<'b, 'a>(
+            sql_values: &'b mut [Vec<ArrayValues<'a>>],
+            frames: &[(usize, Vec<usize>, usize)],
+        ) -> &'b mut Vec<ToSqlOutput<'a>> {
+            let (table, ..) = frames.last().unwrap();
+            &mut sql_values[*table].last_mut().unwrap().2
+        }
+        let mut visitor = |value: VisitValue<'a>| {
+            if let VisitValue::Value(value) = value {
+                let index = frames.last().unwrap().0;
+                let nullable = &event.nullable[index];
+                let position = positions[index];
+                let columns = if self.split_function(&value.kind()) {
+                    2
+                } else {
+                    1
+                };
+                positions[index] += columns;
+                if positions[index] == nullable.len() {
+                    positions[index] = 0;
+                }
+                if nullable[position]
+                    .as_ref()
+                    .is_some_and(|column| nulls.contains(column))
+                {
+                    for _ in 0..columns {
+                        table(&mut sql_values, &frames).push(ToSqlOutput::Owned(SqlValue::Null));
+                    }
+                    return;
+                }
+            }
+            if let VisitValue::Value(value) = value {
+                if let Some(mapper) = type_mapper(&self.options, &value.kind()) {
+                    match mapper.encode(value) {
+                        Some(sql_value) => table(&mut sql_values, &frames).push(sql_value),
+                        None => {
+                            unsupported.get_or_insert(value);
+                        }
+                    }
+                    return;
+                }
+            }
+            let sql_value = match value {
+                VisitValue::ArrayStart(len) => {
+                    // Every element of the enclosing array starts the same
+                    // arrays in the order of its children.
+                    let (parent, path, starts) = frames.last_mut().unwrap();
+                    let children = &children[*parent];
+                    let child = children[*starts % children.len()];
+                    let mut path = path.clone();
+                    if *parent != 0 {
+                        path.push(*starts / children.len());
+                    }
+                    *starts += 1;
+                    sql_values[child].push((path.clone(), Some(len), Vec::new()));
+                    frames.push((child, path, 0));
+                    return;
+                }
+                VisitValue::ArrayEnd => {
+                    frames.pop();
+                    return;
+                }
+                VisitValue::Value(AbiValue::Int(v))
+                    if event.integer_encoding == IntegerEncoding::DecimalText =>
+                {
+                    ToSqlOutput::Owned(SqlValue::Text(v.get().to_string()))
+                }
+                VisitValue::Value(AbiValue::Uint(v))
+                    if event.integer_encoding == IntegerEncoding::DecimalText =>
+                {
+                    ToSqlOutput::Owned(SqlValue::Text(v.get().to_string()))
+                }
+                VisitValue::Value(AbiValue::Int(v))
+                    if event.integer_encoding == IntegerEncoding::Biased =>
+                {
+                    ToSqlOutput::Owned(SqlValue::Blob(encode_biased_int(v).to_vec()))
+                }
+                VisitValue::Value(AbiValue::Int(v)) => {
+                    ToSqlOutput::Owned(SqlValue::Blob(v.get().to_be_bytes().to_vec()))
+                }
+                VisitValue::Value(AbiValue::Uint(v)) => {
+                    ToSqlOutput::Owned(SqlValue::Blob(v.get().to_be_bytes().to_vec()))
+                }
+                VisitValue::Value(AbiValue::Address(v)) => {
+                    ToSqlOutput::Borrowed(SqlValueRef::Blob(&v.0))
+                }
+                VisitValue::Value(AbiValue::Bool(v)) => {
+                    ToSqlOutput::Owned(SqlValue::Integer(*v as i64))
+                }
+                VisitValue::Value(AbiValue::FixedBytes(v)) => {
+                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v.as_bytes()))
+                }
+                VisitValue::Value(AbiValue::Function(v)) if self.options.split_functions => {
+                    table(&mut sql_values, &frames)
+                        .push(ToSqlOutput::Borrowed(SqlValueRef::Blob(&v.address.0)));
+                    ToSqlOutput::Borrowed(SqlValueRef::Blob(&v.selector.0))
+                }
+                VisitValue::Value(AbiValue::Function(v)) => ToSqlOutput::Owned(SqlValue::Blob(
+                    v.address
+                        .0
+                        .iter()
+                        .copied()
+                        .chain(v.selector.0.iter().copied())
+                        .collect(),
+                )),
+                VisitValue::Value(AbiValue::Bytes(v))
+                    if v.is_empty() && self.options.empty_as_null =>
+                {
+                    ToSqlOutput::Borrowed(SqlValueRef::Null)
+                }
+                VisitValue::Value(AbiValue::Bytes(v)) => {
+                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v))
+                }
+                VisitValue::Value(AbiValue::String(v))
+                    if v.is_empty() && self.options.empty_as_null =>
+                {
+                    ToSqlOutput::Borrowed(SqlValueRef::Null)
+                }
+                VisitValue::Value(AbiValue::String(v)) if event.blob_strings => {
+                    ToSqlOutput::Borrowed(SqlValueRef::Blob(v.as_bytes()))
+                }
+                VisitValue::Value(AbiValue::String(v)) => {
+                    ToSqlOutput::Borrowed(SqlValueRef::Text(v.as_bytes()))
+                }
+                VisitValue::Value(value) => {
+                    unsupported.get_or_insert(value);
+                    return;
+                }
+            };
+            table(&mut sql_values, &frames).push(sql_value);
+        };
+        for value in fields {
+            event_visitor::visit_value(value, &mut visitor)
+        }
+        if let Some(value) = unsupported {
+            return Err(anyhow!("unsupported value of kind {:?}", value.kind()));
+        }
+        if self.options.raw_logs {
+            if topics.len() > 4 {
+                return Err(anyhow!("log has {} topics but at most 4", topics.len()));
+            }
+            let primary = &mut sql_values[0][0].2;
+            primary.push(ToSqlOutput::Borrowed(SqlValueRef::Blob(data)));
+            for i in 0..4 {
+                primary.push(match topics.get(i) {
+                    Some(topic) => ToSqlOutput::Borrowed(SqlValueRef::Blob(topic)),
+                    None => ToSqlOutput::Borrowed(SqlValueRef::Null),
+                });
+            }
+        }
+
+        let log_block = block_number;
+        let block_number =
+            ToSqlOutput::Owned(SqlValue::Integer((*block_number).try_into().unwrap()));
+        let log_index = ToSqlOutput::Owned(SqlValue::Integer((*log_index).try_into().unwrap()));
+        let transaction_index =
+            ToSqlOutput::Owned(SqlValue::Integer((*transaction_index).try_into().unwrap()));
+        let address = match event.address {
+            Some(expected) if expected != *address => {
+                return Err(anyhow!(
+                    "log address {address:?} isn't the event's address {expected:?}"
+                ));
+            }
+            Some(_) => None,
+            None => Some(ToSqlOutput::Borrowed(SqlValueRef::Blob(&address.0))),
+        };
+        // With `Options::partition_size` the tables are views of partitions
+        // and the rows go into the log's partition.
+        let storage = |table: &str| match self.options.partition_size {
+            Some(size) => format!("{table}_p{}", *log_block / size),
+            None => table.to_string(),
+        };
+        if let Some(size) = self.options.partition_size {
+            self.create_partition(conn, event, *log_block / size)?;
+        }
+        if self.options.on_conflict == OnConflict::Replace {
+            let key = chain_id.iter().chain([&block_number, &log_index]).chain(
+                self.options
+                    .transaction_index_key
+                    .then_some(&transaction_index),
+            );
+            for table in &event.array_tables {
+                conn.prepare_cached(
+                    &Storage::plain(storage(table)).delete(&event.clear_array_condition),
+                )
+                .context("prepare_cached clear_array")?
+                .execute(rusqlite::params_from_iter(key.clone()))
+                .context("execute clear_array")?;
+            }
+        }
+        for (statement, arrays) in event.insert_statements.iter().zip(sql_values) {
+            let sql = statement.sql(&storage(&statement.table));
+            let mut statement_ = conn.prepare_cached(&sql).context("prepare_cached event")?;
+            for (path, array_element_count, values) in arrays {
+                let is_array = array_element_count.is_some();
+                let array_element_count = array_element_count.unwrap_or(1);
+                assert_eq!(statement.fields * array_element_count, values.len());
+                for i in 0..array_element_count {
+                    let row = &values[i * statement.fields..][..statement.fields];
+                    let array_index = path
+                        .iter()
+                        .copied()
+                        .chain(is_array.then_some(i))
+                        .map(|index| {
+                            ToSqlOutput::Owned(SqlValue::Integer(index.try_into().unwrap()))
+                        })
+                        .collect::<Vec<_>>();
+                    let params = rusqlite::params_from_iter(
+                        chain_id
+                            .iter()
+                            .chain([&block_number, &log_index, &transaction_index])
+                            .chain(address.as_ref())
+                            .chain(&array_index)
+                            .chain(row),
+                    );
+                    let inserted = statement_.execute(params).context("insert event")?;
+                    if inserted == 0 {
+                        // Ignored conflict of the primary row. The stored log
+                        // keeps its array rows.
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store_block(&self, conn: &Connection, block_time: &BlockTime) -> Result<()> {
+        let number = ToSqlOutput::Owned(SqlValue::Integer((block_time.number).try_into().unwrap()));
+        let time = ToSqlOutput::Owned(SqlValue::Text(systemtime_to_string(
+            block_time.timestamp,
+            None,
+        )));
+        conn.prepare_cached(&self.sql(INSERT_BLOCK))
+            .context("prepare_cached block insert")?
+            .execute([number, time])
+            .context("insert block")?;
+        Ok(())
+    }
+
+    fn store_transaction(&self, conn: &Connection, tx: &database::Transaction) -> Result<()> {
+        let block_number =
+            ToSqlOutput::Owned(SqlValue::Integer((tx.block_number).try_into().unwrap()));
+        let index = ToSqlOutput::Owned(SqlValue::Integer((tx.index).try_into().unwrap()));
+        let hash = ToSqlOutput::Owned(SqlValue::Blob(tx.hash.to_vec()));
+        let from = ToSqlOutput::Owned(SqlValue::Blob(tx.from.to_vec()));
+        let to = ToSqlOutput::Owned(match tx.to {
+            Some(val) => SqlValue::Blob(val.to_vec()),
+            None => SqlValue::Null,
+        });
+
+        conn.prepare_cached(&self.sql(INSERT_TRANSACTION))
+            .context("prepare_cached transaction insert")?
+            .execute([block_number, index, hash, from, to])
+            .context("insert transaction")?;
+        Ok(())
+    }
+
+    fn update<'l>(
+        &self,
+        con: &Connection,
+        blocks: &[database::EventBlock],
+        logs: impl IntoIterator<Item = impl Borrow<Log<'l>>>,
+        block_times: &[database::BlockTime],
+        transactions: &[database::Transaction],
+    ) -> Result<database::UpdateSummary> {
+        self.check_writable()?;
+        self.set_event_blocks(con, blocks, self.options.monotonic_blocks)
+            .context("set_event_blocks")?;
+        let mut summary = database::UpdateSummary::default();
+        for block in blocks {
+            summary.record_block(block.block.indexed);
+        }
+        for log in logs {
+            let log = log.borrow();
+            if self.options.on_unknown_event == UnknownEvent::Skip
+                && !self.events.contains_key(log.event.as_ref())
+            {
+                tracing::warn!(
+                    event = %log.event,
+                    block = log.block_number,
+                    log_index = log.log_index,
+                    "skipping log of unknown event"
+                );
+                summary.skipped_logs += 1;
+                continue;
+            }
+            let start = Instant::now();
+            self.store_event(con, log).context("store_event")?;
+            if let Some(metrics) = &self.options.metrics {
+                metrics.record_insert_duration(&log.event, start.elapsed());
+            }
+            summary.record_block(log.block_number);
+        }
+        for block_time in block_times {
+            self.store_block(con, block_time).context("store_block")?;
+        }
+        for tx in transactions {
+            self.store_transaction(con, tx)
+                .context("store_transaction")?;
+        }
+        Ok(summary)
+    }
+
+    /// Deletes all logs of an event and sets its indexed and finalized blocks
+    /// to 0.
+    fn reset_event(&self, con: &Connection, name: &str) -> Result<()> {
+        let event = self.events.get(name).context("unknown event")?;
+        // Array tables first, see `remove_conditions`.
+        for table in event.array_tables.iter().chain([&event.table]) {
+            for storage in self.storages(con, table, 0)? {
+                con.execute(&storage.clear(), ())
+                    .context("execute delete")?;
+            }
+        }
+        con.prepare_cached(&self.sql(SET_EVENT_BLOCK))
+            .context("prepare_cached set_event_block")?
+            .execute((name, 0, 0))
+            .context("execute set_event_block")?;
+        Ok(())
+    }
+
+    /// Returns the number of deleted rows per event table, see
+    /// `Sqlite::remove_reporting`.
+    fn remove(
+        &self,
+        connection: &Connection,
+        uncles: &[database::Uncle],
+    ) -> Result<Vec<(String, usize)>> {
+        self.check_writable()?;
+        let mut report: Vec<(String, usize)> = Vec::new();
+        let mut set_indexed_block: rusqlite::CachedStatement<'_> = connection
+            .prepare_cached(&self.sql(SET_INDEXED_BLOCK))
+            .context("prepare_cached set_indexed_block")?;
+        for uncle in uncles {
+            if uncle.number == 0 {
+                return Err(anyhow!("block 0 got uncled"));
+            }
+            let block = i64::try_from(uncle.number).context("block out of bounds")?;
+            let parent_block = block - 1;
+            let prepared = self.events.get(uncle.event).context("unprepared event")?;
+            let mut removed_rows = 0;
+            let tables = prepared.array_tables.iter().chain([&prepared.table]);
+            for (table, condition) in tables.zip(&prepared.remove_conditions) {
+                let mut table_rows = 0;
+                for storage in self.storages(connection, table, uncle.number)? {
+                    let mut remove_statement = connection
+                        .prepare_cached(&storage.delete(condition))
+                        .context("prepare_cached remove_statement")?;
+                    table_rows += remove_statement
+                        .execute((block,))
+                        .context("execute remove_statement")?;
+                }
+                removed_rows += table_rows;
+                match report.iter_mut().find(|(name, _)| name == table) {
+                    Some((_, rows)) => *rows += table_rows,
+                    None => report.push((table.clone(), table_rows)),
+                }
+                set_indexed_block
+                    .execute((uncle.event, parent_block))
+                    .context("execute set_indexed_block")?;
+            }
+            let now = (self.clock)()
+                .duration_since(UNIX_EPOCH)
+                .context("time before epoch")?
+                .as_secs();
+            connection
+                .prepare_cached(&self.sql(INSERT_REORG_LOG))
+                .context("prepare_cached insert_reorg_log")?
+                .execute((
+                    uncle.event,
+                    block,
+                    i64::try_from(removed_rows)?,
+                    i64::try_from(now)?,
+                ))
+                .context("execute insert_reorg_log")?;
+
+            // Remove blocks and transactions as well.
+            let mut remove_statement = connection.prepare_cached(&self.sql(REMOVE_BLOCKS_FROM))?;
+            remove_statement
+                .execute((block,))
+                .context("execute remove_statement (blocks)")?;
+            set_indexed_block.execute(("blocks", parent_block))?;
+
+            let mut remove_statement =
+                connection.prepare_cached(&self.sql(REMOVE_TRANSACTIONS_FROM))?;
+            remove_statement
+                .execute((block,))
+                .context("execute remove_statement (transactions)")?;
+            set_indexed_block.execute(("transactions", parent_block))?;
+        }
+        Ok(report)
+    }
+}
+
+/// Maps `sqlite://` URLs to the paths and URIs understood by SQLite.
+fn connection_path(connection: &str) -> Cow<str> {
+    match connection.strip_prefix("sqlite://") {
+        None => Cow::Borrowed(connection),
+        Some("") => Cow::Borrowed(":memory:"),
+        Some(path) if path.contains('?') => Cow::Owned(format!("file:{path}")),
+        Some(path) => Cow::Borrowed(path),
+    }
+}
+
+/// Registers SQL functions for comparing integers, which are stored as big
+/// endian blobs and can't be compared with the builtin operators:
+///
+/// - `arak_uint_cmp(a, b)` for `uint` columns
+/// - `arak_int_cmp(a, b)` for two's complement `int` columns
+///
+/// Both return -1, 0 or 1 like `memcmp` (or NULL if an argument is NULL) and
+/// accept blobs of any length, for example
+/// `WHERE arak_uint_cmp(amount, x'03e8') > 0`.
+///
+/// Also registers the `ARAK_NUMERIC` collation for decimal text integers, see
+/// `IntegerEncoding::DecimalText`.
+fn register_functions(connection: &Connection) -> Result<()> {
+    fn register(
+        connection: &Connection,
+        name: &str,
+        cmp: fn(&[u8], &[u8]) -> Ordering,
+    ) -> Result<()> {
+        connection
+            .create_scalar_function(
+                name,
+                2,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                move |context| {
+                    let blob = |i: usize| match context.get_raw(i) {
+                        SqlValueRef::Null => Ok(None),
+                        SqlValueRef::Blob(blob) => Ok(Some(blob)),
+                        value => Err(rusqlite::Error::InvalidFunctionParameterType(
+                            i,
+                            value.data_type(),
+                        )),
+                    };
+                    Ok(match (blob(0)?, blob(1)?) {
+                        (Some(a), Some(b)) => Some(cmp(a, b) as i64),
+                        _ => None,
+                    })
+                },
+            )
+            .with_context(|| format!("create function {name}"))
+    }
+
+    register(connection, "arak_uint_cmp", uint_cmp)?;
+    register(connection, "arak_int_cmp", int_cmp)?;
+    connection
+        .create_collation(NUMERIC_COLLATION, numeric_cmp)
+        .context("create collation")?;
+    Ok(())
+}
+
+const NUMERIC_COLLATION: &str = "ARAK_NUMERIC";
+
+/// Numerically compares decimal integers like `-0012` of any length. Strings
+/// that aren't decimal integers sort after all numbers.
+fn numeric_cmp(a: &str, b: &str) -> Ordering {
+    /// Returns whether the number is negative and its digits without leading
+    /// zeros.
+    fn parse(s: &str) -> Option<(bool, &str)> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let digits = digits.trim_start_matches('0');
+        Some((negative && !digits.is_empty(), digits))
+    }
+    match (parse(a), parse(b)) {
+        (Some((a_negative, a)), Some((b_negative, b))) => {
+            let magnitude = a.len().cmp(&b.len()).then_with(|| a.cmp(b));
+            match (a_negative, b_negative) {
+                (false, false) => magnitude,
+                (true, true) => magnitude.reverse(),
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+            }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Numerically compares big endian unsigned integers of any length.
+fn uint_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let trim = |v: &[u8]| {
+        let start = v.iter().position(|&byte| byte != 0).unwrap_or(v.len());
+        v[start..].to_vec()
+    };
+    let (a, b) = (trim(a), trim(b));
+    a.len().cmp(&b.len()).then_with(|| a.cmp(&b))
+}
+
+/// Numerically compares big endian two's complement integers of any length.
+fn int_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let negative = |v: &[u8]| matches!(v.first(), Some(byte) if byte & 0x80 != 0);
+    match (negative(a), negative(b)) {
+        (true, false) => return Ordering::Less,
+        (false, true) => return Ordering::Greater,
+        _ => (),
+    }
+    // With equal signs, sign extending both to the same length makes the byte
+    // order match the numeric order.
+    let len = a.len().max(b.len());
+    let extend = |v: &[u8]| {
+        let fill = if negative(v) { 0xff } else { 0 };
+        let mut extended = vec![fill; len - v.len()];
+        extended.extend_from_slice(v);
+        extended
+    };
+    extend(a).cmp(&extend(b))
+}
+
+/// Decodes a value of `kind` from the columns that `event_to_tables` created
+/// for it. Dynamic arrays take the rows of the next array table.
+fn decode_kind_(
+    kind: &AbiKind,
+    options: &Options,
+    encoding: IntegerEncoding,
+    columns: &mut dyn Iterator<Item = SqlValue>,
+    tables: &mut dyn Iterator<Item = Vec<ArrayRow>>,
+) -> Result<AbiValue> {
+    Ok(match kind {
+        AbiKind::Tuple(kinds) => AbiValue::Tuple(
+            kinds
+                .iter()
+                .map(|kind| decode_kind(kind, options, encoding, columns, tables))
+                .collect::<Result<_>>()?,
+        ),
+        AbiKind::FixedArray(len, kind) => AbiValue::FixedArray(
+            FixedArray::new(
+                (**kind).clone(),
+                (0..*len)
+                    .map(|_| decode_kind(kind, options, encoding, columns, tables))
+                    .collect::<Result<_>>()?,
+            )
+            .context("invalid fixed array")?,
+        ),
+        AbiKind::Array(kind) => {
+            let rows = tables.next().context("missing array table")?;
+            let values = rows
+                .into_iter()
+                .map(|row| {
+                    let mut arrays = row.arrays.into_iter();
+                    let mut row = row.values.into_iter();
+                    let value = decode_kind(kind, options, encoding, &mut row, &mut arrays)?;
+                    if row.next().is_some() || arrays.next().is_some() {
+                        return Err(anyhow!("unexpected array columns"));
+                    }
+                    Ok(value)
+                })
+                .collect::<Result<_>>()?;
+            AbiValue::Array(Array::new((**kind).clone(), values).context("invalid array")?)
+        }
+        kind if type_mapper(options, kind).is_some() => {
+            let value = columns.next().context("missing column")?;
+            type_mapper(options, kind)
+                .unwrap()
+                .decode(kind, (&value).into())?
+        }
+        AbiKind::Function if options.split_functions => {
+            let address = columns.next().context("missing column")?;
+            let selector = columns.next().context("missing column")?;
+            let (SqlValue::Blob(address), SqlValue::Blob(selector)) = (address, selector) else {
+                return Err(anyhow!("expected blobs for function"));
+            };
+            AbiValue::Function(ExternalFunction {
+                address: Address(address.try_into().ok().context("invalid address length")?),
+                selector: Selector(
+                    selector
+                        .try_into()
+                        .ok()
+                        .context("invalid selector length")?,
+                ),
+            })
+        }
+        AbiKind::String if options.lossy_utf8 => match columns.next().context("missing column")? {
+            SqlValue::Blob(bytes) => AbiValue::String(String::from_utf8_lossy(&bytes).into_owned()),
+            value => Sqlite::decode_value(kind, (&value).into(), encoding)?,
+        },
+        kind => {
+            let value = columns.next().context("missing column")?;
+            Sqlite::decode_value(kind, (&value).into(), encoding)?
+        }
+    })
+}
+
+/// Like `decode_kind_` but NULL in a leaf column decodes as the zero value,
+/// see `EventOptions::nullable`.
+fn decode_kind(
+    kind: &AbiKind,
+    options: &Options,
+    encoding: IntegerEncoding,
+    columns: &mut dyn Iterator<Item = SqlValue>,
+    tables: &mut dyn Iterator<Item = Vec<ArrayRow>>,
+) -> Result<AbiValue> {
+    if matches!(
+        kind,
+        AbiKind::Tuple(_) | AbiKind::FixedArray(..) | AbiKind::Array(_)
+    ) {
+        return decode_kind_(kind, options, encoding, columns, tables);
+    }
+    let value = columns.next().context("missing column")?;
+    if value != SqlValue::Null {
+        return decode_kind_(
+            kind,
+            options,
+            encoding,
+            &mut std::iter::once(value).chain(columns),
+            tables,
+        );
+    }
+    if kind == &AbiKind::Function && options.split_functions && type_mapper(options, kind).is_none()
+    {
+        columns.next().context("missing column")?;
+    }
+    Ok(zero_value(kind))
+}
+
+/// The value of a leaf kind whose ABI encoding is all zeros.
+fn zero_value(kind: &AbiKind) -> AbiValue {
+    match kind {
+        AbiKind::Int(bits) => AbiValue::Int(Int::new(bits.get(), I256::ZERO).unwrap()),
+        AbiKind::Uint(bits) => AbiValue::Uint(Uint::new(bits.get(), U256::ZERO).unwrap()),
+        AbiKind::Address => AbiValue::Address(Address([0; 20])),
+        AbiKind::Bool => AbiValue::Bool(false),
+        AbiKind::FixedBytes(len) => {
+            AbiValue::FixedBytes(FixedBytes::new(&vec![0; len.get()]).unwrap())
+        }
+        AbiKind::Function => AbiValue::Function(ExternalFunction {
+            address: Address([0; 20]),
+            selector: Selector([0; 4]),
+        }),
+        AbiKind::Bytes => AbiValue::Bytes(Vec::new()),
+        AbiKind::String => AbiValue::String(String::new()),
+        AbiKind::Tuple(_) | AbiKind::FixedArray(..) | AbiKind::Array(_) => {
+            unreachable!("not a leaf kind")
+        }
+    }
+}
+
+/// Decodes a big endian two's complement integer. Blobs shorter than 32 bytes
+/// are sign extended.
+fn decode_int(bits: usize, blob: &[u8]) -> Result<Int> {
+    if blob.len() > 32 {
+        return Err(anyhow!("int blob longer than 32 bytes"));
+    }
+    let negative = matches!(blob.first(), Some(byte) if byte & 0x80 != 0);
+    let mut bytes = [if negative { 0xff } else { 0 }; 32];
+    bytes[32 - blob.len()..].copy_from_slice(blob);
+    Int::new(bits, I256::from_be_bytes(bytes)).with_context(|| format!("int{bits} out of range"))
+}
+
+/// Encodes `int<M>` as the unsigned value plus `2^(M-1)`. The result fits
+/// into `M` bits and is stored as 32 big endian bytes.
+fn encode_biased_int(value: &Int) -> [u8; 32] {
+    let bias = U256::ONE << (value.bit_width().get() as u32 - 1);
+    value.get().as_u256().wrapping_add(bias).to_be_bytes()
+}
+
+/// Inverse of `encode_biased_int`.
+fn decode_biased_int(bits: usize, blob: &[u8]) -> Result<Int> {
+    let bytes: [u8; 32] = blob.try_into().context("biased int blob isn't 32 bytes")?;
+    let bias = U256::ONE << (bits as u32 - 1);
+    let value = U256::from_be_bytes(bytes).wrapping_sub(bias).as_i256();
+    Int::new(bits, value).with_context(|| format!("int{bits} out of range"))
+}
+
+/// Decodes a big endian unsigned integer. Blobs shorter than 32 bytes are zero
+/// extended.
+fn decode_uint(bits: usize, blob: &[u8]) -> Result<Uint> {
+    if blob.len() > 32 {
+        return Err(anyhow!("uint blob longer than 32 bytes"));
+    }
+    let mut bytes = [0; 32];
+    bytes[32 - blob.len()..].copy_from_slice(blob);
+    Uint::new(bits, U256::from_be_bytes(bytes)).with_context(|| format!("uint{bits} out of range"))
+}
+
+/// The sorted names of `EventOptions::nullable`.
+fn nullable_columns(options: &EventOptions) -> Vec<String> {
+    let mut columns = options
+        .nullable
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>();
+    columns.sort();
+    columns.dedup();
+    columns
+}
+
+/// Canonical declaration of an event with everything that affects its
+/// storage, for example `event Transfer(address indexed from, (uint8 a,
+/// bool b)[] c)`. Differently formatted declarations of the same event give
+/// the same signature.
+fn event_signature(event: &EventDescriptor) -> String {
+    fn type_(kind: &AbiKind, components: &[Field]) -> String {
+        match kind {
+            AbiKind::Tuple(_) if !components.is_empty() => {
+                let components = components
+                    .iter()
+                    .map(|field| {
+                        let type_ =
+                            type_(&field.kind, field.components.as_deref().unwrap_or_default());
+                        format!("{type_} {}", field.name)
+                    })
+                    .collect::<Vec<_>>();
+                format!("({})", components.join(","))
+            }
+            AbiKind::FixedArray(len, kind) => format!("{}[{len}]", type_(kind, components)),
+            AbiKind::Array(kind) => format!("{}[]", type_(kind, components)),
+            kind => kind_name(kind),
+        }
+    }
+
+    let inputs = event
+        .inputs
+        .iter()
+        .map(|input| {
+            let field = &input.field;
+            let type_ = type_(&field.kind, field.components.as_deref().unwrap_or_default());
+            let indexed = if input.indexed { " indexed" } else { "" };
+            format!("{type_}{indexed} {}", field.name)
+        })
+        .collect::<Vec<_>>();
+    let anonymous = if event.anonymous { " anonymous" } else { "" };
+    format!("event {}({}){anonymous}", event.name, inputs.join(","))
+}
+
+/// 64 bit FNV-1a hash of an `event_signature`. Unlike `std`'s hashers it is
+/// stable across Rust versions so it can be stored.
+fn signature_hash(signature: &str) -> i64 {
+    let hash = signature
+        .bytes()
+        .fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    hash as i64
+}
+
+/// The Solidity type of a kind, for example `(uint8,bool[])[2]`.
+fn kind_name(kind: &AbiKind) -> String {
+    match kind {
+        AbiKind::Int(bits) => format!("int{}", bits.get()),
+        AbiKind::Uint(bits) => format!("uint{}", bits.get()),
+        AbiKind::Address => "address".to_string(),
+        AbiKind::Bool => "bool".to_string(),
+        AbiKind::FixedBytes(len) => format!("bytes{}", len.get()),
+        AbiKind::Function => "function".to_string(),
+        AbiKind::Bytes => "bytes".to_string(),
+        AbiKind::String => "string".to_string(),
+        AbiKind::Tuple(kinds) => {
+            let kinds = kinds.iter().map(kind_name).collect::<Vec<_>>();
+            format!("({})", kinds.join(","))
+        }
+        AbiKind::FixedArray(len, kind) => format!("{}[{len}]", kind_name(kind)),
+        AbiKind::Array(kind) => format!("{}[]", kind_name(kind)),
+    }
+}
+
+fn sql_type_name(type_: SqlType) -> &'static str {
+    match type_ {
+        SqlType::Null => "NULL",
+        SqlType::Integer => "INTEGER",
+        SqlType::Real => "REAL",
+        SqlType::Text => "TEXT",
+        SqlType::Blob => "BLOB",
+    }
+}
+
+fn abi_kind_to_sql_type(value: &AbiKind) -> Option<SqlType> {
+    match value {
+        AbiKind::Int(_) => Some(SqlType::Blob),
+        AbiKind::Uint(_) => Some(SqlType::Blob),
+        AbiKind::Address => Some(SqlType::Blob),
+        AbiKind::Bool => Some(SqlType::Integer),
+        AbiKind::FixedBytes(_) => Some(SqlType::Blob),
+        AbiKind::Function => Some(SqlType::Blob),
+        AbiKind::Bytes => Some(SqlType::Blob),
+        AbiKind::String => Some(SqlType::Text),
+        AbiKind::FixedArray(_, _) | AbiKind::Tuple(_) | AbiKind::Array(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{migration::Migration, read::READ_ONE_FILTER, *},
+        crate::database::DatabaseError,
+        solabi::{
+            digest,
+            value::{BitWidth, ByteLength},
+        },
+    };
+
+    #[test]
+    fn new_for_test() {
+        Sqlite::new_for_test();
+    }
+
+    #[test]
+    fn sqlite_version() {
+        for version in ["3.37.0", "3.45.1", "4.0.0"] {
+            check_sqlite_version(version).unwrap();
+        }
+        for version in ["3.36.0", "3.8.11", "2.8.17", "", "3"] {
+            assert!(check_sqlite_version(version).is_err(), "{version}");
+        }
+        let err = check_sqlite_version("3.31.1").unwrap_err();
+        assert!(err.to_string().contains("requires SQLite >= 3.37"), "{err}");
+    }
+
+    #[test]
+    fn uint_cmp_ordering() {
+        for (a, b, expected) in [
+            (&[][..], &[][..], Ordering::Equal),
+            (&[0, 0, 1], &[1], Ordering::Equal),
+            (&[0, 0], &[], Ordering::Equal),
+            (&[1, 0], &[0xff], Ordering::Greater),
+            (&[0, 0xff], &[1, 0], Ordering::Less),
+            (&[2], &[0, 0, 0, 3], Ordering::Less),
+            (&[0xff; 32], &[0xff; 31], Ordering::Greater),
+        ] {
+            assert_eq!(uint_cmp(a, b), expected, "{a:?} {b:?}");
+            assert_eq!(uint_cmp(b, a), expected.reverse(), "{b:?} {a:?}");
+        }
+    }
+
+    #[test]
+    fn int_cmp_ordering() {
+        for (a, b, expected) in [
+            (&[][..], &[0][..], Ordering::Equal),
+            (&[0xff], &[0xff, 0xff, 0xff], Ordering::Equal),
+            (&[0, 1], &[1], Ordering::Equal),
+            (&[0xff], &[0], Ordering::Less),
+            (&[0x80], &[0xff, 0x7f], Ordering::Greater),
+            (&[0xff, 0x7f], &[0x80, 0], Ordering::Greater),
+            (&[0x7f], &[0, 0x80], Ordering::Less),
+            (&[0xff, 0xfe], &[0xff], Ordering::Less),
+            (&[0x80; 32], &[0x7f; 1], Ordering::Less),
+        ] {
+            assert_eq!(int_cmp(a, b), expected, "{a:?} {b:?}");
+            assert_eq!(int_cmp(b, a), expected.reverse(), "{b:?} {a:?}");
+        }
+    }
+
+    #[test]
+    fn cmp_functions() {
+        let sqlite = Sqlite::new_for_test();
+        let query = |sql: &str| -> Option<i64> {
+            sqlite
+                .connection
+                .query_row(sql, (), |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(query("SELECT arak_uint_cmp(x'0100', x'ff')"), Some(1));
+        assert_eq!(query("SELECT arak_uint_cmp(x'00ff', x'ff')"), Some(0));
+        assert_eq!(query("SELECT arak_int_cmp(x'ff', x'0001')"), Some(-1));
+        assert_eq!(query("SELECT arak_int_cmp(NULL, x'00')"), None);
+        assert!(sqlite
+            .connection
+            .query_row("SELECT arak_uint_cmp(1, x'00')", (), |row| row
+                .get::<_, Option<i64>>(0))
+            .is_err());
+
+        // Works on stored values, which `arak_int_cmp` expects in two's
+        // complement.
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                integer_encoding: Some(IntegerEncoding::TwosComplement),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(int8 a, uint256 b)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs: Vec<Log> = [(-2i32, 1u32), (3, 1000), (-100, 2000)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (a, b))| Log {
+                event: "event".into(),
+                log_index: i as u64,
+                fields: vec![
+                    AbiValue::Int(Int::new(8, a.into()).unwrap()),
+                    AbiValue::Uint(Uint::new(256, b.into()).unwrap()),
+                ],
+                ..Default::default()
+            })
+            .collect();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+        let count = |sql: &str| -> i64 {
+            sqlite
+                .connection
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM event WHERE {sql}"),
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(count("arak_uint_cmp(b_1, x'03e8') >= 0"), 2);
+        assert_eq!(count("arak_int_cmp(a_0, x'00') < 0"), 2);
+        assert_eq!(count("arak_int_cmp(a_0, x'fe') < 0"), 1);
+    }
+
+    #[test]
+    fn connection_paths() {
+        for (connection, expected) in [
+            ("file:arak.db", "file:arak.db"),
+            ("arak.db", "arak.db"),
+            ("sqlite://", ":memory:"),
+            ("sqlite://:memory:", ":memory:"),
+            (
+                "sqlite://:memory:?cache=shared",
+                "file::memory:?cache=shared",
+            ),
+            ("sqlite://arak.db", "arak.db"),
+            ("sqlite://arak.db?mode=ro", "file:arak.db?mode=ro"),
+        ] {
+            assert_eq!(connection_path(connection), expected);
+        }
+    }
+
+    #[test]
+    fn shared_in_memory() {
+        let mut first = Sqlite::open("sqlite://:memory:?cache=shared").unwrap();
+        let second = Sqlite::open("sqlite://:memory:?cache=shared").unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        first
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        first
+            .update_iter(
+                &[],
+                &[Log {
+                    event: "event".into(),
+                    fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(count_rows(&second, "event"), 1);
+
+        // Private in-memory databases are not shared.
+        let mut first = Sqlite::open("sqlite://").unwrap();
+        let second = Sqlite::open("sqlite://").unwrap();
+        first
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let exists: bool = second
+            .connection
+            .query_row(TABLE_EXISTS, ("event",), |row| row.get(0))
+            .unwrap();
+        assert!(!exists);
+    }
+
+    #[test]
+    fn unknown_event() {
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        let logs = ["event", "unknown", "event"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| Log {
+                event: event.into(),
+                log_index: i as u64,
+                fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(sqlite.update_iter(&[], &logs, &[], &[]).is_err());
+        assert_eq!(count_rows(&sqlite, "event"), 0);
+
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                on_unknown_event: UnknownEvent::Skip,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let summary = sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+        assert_eq!(summary.skipped_logs, 1);
+        assert_eq!(summary.indexed_block, Some(0));
+        assert_eq!(count_rows(&sqlite, "event"), 2);
+    }
+
+    #[test]
+    fn on_conflict() {
+        let event = EventDescriptor::parse_declaration("event Event(uint8, bool[])").unwrap();
+        let log = |value: u8, len: usize| Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::Uint(Uint::new(8, value.into()).unwrap()),
+                AbiValue::Array(
+                    Array::new(AbiKind::Bool, vec![AbiValue::Bool(true); len]).unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let sqlite = |on_conflict| {
+            let mut sqlite = Sqlite::with_options(
+                Connection::open_in_memory().unwrap(),
+                Options {
+                    on_conflict,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            sqlite.update_iter(&[], &[log(1, 3)], &[], &[]).unwrap();
+            sqlite
+        };
+
+        let mut sqlite_ = sqlite(OnConflict::Error);
+        assert!(sqlite_.update_iter(&[], &[log(2, 1)], &[], &[]).is_err());
+
+        let mut sqlite_ = sqlite(OnConflict::Ignore);
+        sqlite_.update_iter(&[], &[log(2, 1)], &[], &[]).unwrap();
+        assert_eq!(
+            sqlite_.read_logs("event", 0, 0).unwrap()[0].fields,
+            log(1, 3).fields
+        );
+        assert_eq!(count_rows(&sqlite_, "event_array_0"), 3);
+
+        let mut sqlite_ = sqlite(OnConflict::Replace);
+        sqlite_.update_iter(&[], &[log(2, 1)], &[], &[]).unwrap();
+        assert_eq!(
+            sqlite_.read_logs("event", 0, 0).unwrap()[0].fields,
+            log(2, 1).fields
+        );
+        assert_eq!(count_rows(&sqlite_, "event"), 1);
+        assert_eq!(count_rows(&sqlite_, "event_array_0"), 1);
+    }
+
+    #[test]
+    fn split_functions() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                split_functions: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(function f, function[] fs)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let columns = |table: &str| -> Vec<String> {
+            let mut statement = sqlite
+                .connection
+                .prepare("SELECT name FROM pragma_table_info(?1);")
+                .unwrap();
+            let rows = statement.query_map((table,), |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+        assert!(columns("event").ends_with(&["f_0_address".into(), "f_0_selector".into()]));
+        assert!(columns("event_fs_0").ends_with(&["fs_0_address".into(), "fs_0_selector".into()]));
+
+        let function = |byte: u8| {
+            AbiValue::Function(ExternalFunction {
+                address: Address([byte; 20]),
+                selector: Selector([byte; 4]),
+            })
+        };
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                function(1),
+                AbiValue::Array(
+                    Array::new(AbiKind::Function, vec![function(2), function(3)]).unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(
+            sqlite.read_logs("event", 0, 0).unwrap()[0].fields,
+            log.fields
+        );
+
+        let count: i64 = sqlite
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM event_fs_0 WHERE fs_0_address = ?1;",
+                ([3u8; 20],),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn batch() {
+        let connection = "sqlite://batch?mode=memory&cache=shared";
+        let mut sqlite = Sqlite::open(connection).unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+            ..Default::default()
+        };
+
+        // Outside of a batch every update commits on its own.
+        let other = Sqlite::open(connection).unwrap();
+        sqlite.update_iter(&[], [log(0)], &[], &[]).unwrap();
+        assert_eq!(count_rows(&other, "event"), 1);
+
+        assert!(sqlite.commit_batch().is_err());
+        sqlite.begin_batch().unwrap();
+        assert!(sqlite.begin_batch().is_err());
+        for block in 1..4 {
+            sqlite.update_iter(&[], [log(block)], &[], &[]).unwrap();
+        }
+        // A failing update only discards its own changes.
+        assert!(sqlite.update_iter(&[], [log(4), log(4)], &[], &[]).is_err());
+        assert_eq!(count_rows(&sqlite, "event"), 4);
+        sqlite.commit_batch().unwrap();
+        assert_eq!(count_rows(&other, "event"), 4);
+
+        // Dropping an uncommitted batch rolls it back.
+        sqlite.begin_batch().unwrap();
+        sqlite.update_iter(&[], [log(5)], &[], &[]).unwrap();
+        drop(sqlite);
+        assert_eq!(count_rows(&other, "event"), 4);
+    }
+
+    #[test]
+    fn debug_query_plan() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                debug_query_plan: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint8)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite.read_logs("event", 0, 10).unwrap();
+
+        let plan = sqlite
+            .inner
+            .explain(
+                &sqlite.connection,
+                "SELECT * FROM event WHERE block_number BETWEEN ?1 AND ?2;",
+                (0, 10),
+            )
+            .unwrap();
+        assert!(!plan.is_empty());
+        assert!(plan.iter().any(|line| line.contains("event")), "{plan:?}");
+
+        sqlite.inner.options.debug_query_plan = false;
+        let plan = sqlite
+            .inner
+            .explain(&sqlite.connection, "SELECT * FROM event;", [])
+            .unwrap();
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn schema_json() {
+        let mut sqlite = Sqlite::new_for_test();
+        for (name, declaration) in [
+            ("transfer", "event Transfer(address from, uint256 value)"),
+            ("batch", "event Batch(bool ok, int8[] values)"),
+        ] {
+            let event = EventDescriptor::parse_declaration(declaration).unwrap();
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let schema = sqlite.schema_json().unwrap();
+
+        let transfer = &schema["transfer"]["tables"];
+        assert_eq!(transfer.as_array().unwrap().len(), 1);
+        assert_eq!(transfer[0]["name"], "transfer");
+        assert_eq!(transfer[0]["fixed_columns"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            transfer[0]["columns"],
+            json!([
+                {"name": "from_0", "sql_type": "BLOB", "kind": "address"},
+                {"name": "value_1", "sql_type": "BLOB", "kind": "uint256"},
+            ])
+        );
+
+        let batch = &schema["batch"]["tables"];
+        assert_eq!(
+            batch[0]["columns"],
+            json!([{"name": "ok_0", "sql_type": "INTEGER", "kind": "bool"}])
+        );
+        assert_eq!(batch[1]["name"], "batch_values_0");
+        assert_eq!(
+            batch[1]["fixed_columns"][4],
+            json!({"name": "array_index", "sql_type": "INTEGER"})
+        );
+        assert_eq!(
+            batch[1]["columns"],
+            json!([{"name": "values_0", "sql_type": "BLOB", "kind": "int8"}])
+        );
+    }
+
+    #[test]
+    fn query() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8 value)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..6u8)
+            .map(|i| Log {
+                event: "event".into(),
+                block_number: (i / 2).into(),
+                log_index: (i % 2).into(),
+                address: Address([i % 3; 20]),
+                fields: vec![AbiValue::Uint(Uint::new(8, i.into()).unwrap())],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let values = |filter: QueryFilter| -> Vec<u8> {
+            sqlite
+                .query("event", filter)
+                .unwrap()
+                .iter()
+                .map(|row| match row.get("value_0").unwrap() {
+                    SqlValue::Blob(blob) => *blob.last().unwrap(),
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(values(Default::default()), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            values(QueryFilter {
+                from_block: Some(1),
+                ..Default::default()
+            }),
+            [2, 3, 4, 5]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                to_block: Some(1),
+                ..Default::default()
+            }),
+            [0, 1, 2, 3]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                address: Some(Address([1; 20])),
+                ..Default::default()
+            }),
+            [1, 4]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                from_block: Some(1),
+                to_block: Some(1),
+                address: Some(Address([0; 20])),
+                ..Default::default()
+            }),
+            [3]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                order: Order::Desc,
+                ..Default::default()
+            }),
+            [5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                order: Order::Desc,
+                limit: Some(2),
+                ..Default::default()
+            }),
+            [5, 4]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                from_block: Some(1),
+                limit: Some(0),
+                ..Default::default()
+            }),
+            [0u8; 0]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                after: Some((1, 0)),
+                limit: Some(2),
+                ..Default::default()
+            }),
+            [3, 4]
+        );
+        assert_eq!(
+            values(QueryFilter {
+                order: Order::Desc,
+                after: Some((1, 0)),
+                ..Default::default()
+            }),
+            [1, 0]
+        );
+
+        let rows = sqlite.query("event", Default::default()).unwrap();
+        let names = rows[0]
+            .columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "block_number",
+                "log_index",
+                "transaction_index",
+                "address",
+                "value_0"
+            ]
+        );
+        assert!(sqlite.query("unknown", Default::default()).is_err());
+    }
+
+    #[test]
+    fn query_filter_binds_parameters() {
+        let filter = QueryFilter {
+            from_block: Some(1234),
+            to_block: Some(5678),
+            address: Some(Address([0x27; 20])),
+            order: Order::Desc,
+            limit: Some(42),
+            after: None,
+        };
+        let (sql, params) = filter.sql("event", false).unwrap();
+        assert_eq!(
+            sql,
+            "SELECT * FROM event WHERE block_number >= ?1 AND block_number <= ?2 AND address = \
+             ?3 ORDER BY block_number DESC, log_index DESC LIMIT ?4;"
+        );
+        assert_eq!(
+            params,
+            [
+                SqlValue::Integer(1234),
+                SqlValue::Integer(5678),
+                SqlValue::Blob(vec![0x27; 20]),
+                SqlValue::Integer(42),
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_inputs() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let columns: i64 = sqlite
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('event');",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(columns, FIXED_COLUMNS_COUNT as i64);
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            log_index: 2,
+            transaction_index: 3,
+            address: Address([4; 20]),
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        let read = sqlite.read_logs("event", 0, 1).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(
+            (
+                read[0].block_number,
+                read[0].log_index,
+                read[0].transaction_index
+            ),
+            (1, 2, 3)
+        );
+        assert_eq!(read[0].address, log.address);
+        assert!(read[0].fields.is_empty());
+    }
+
+    #[test]
+    fn name_sanitizer() {
+        #[derive(Debug)]
+        struct Uppercase;
+
+        impl NameSanitizer for Uppercase {
+            fn id(&self) -> &str {
+                "uppercase"
+            }
+
+            fn sanitize(&self, name: &str) -> String {
+                DefaultSanitizer.sanitize(name).to_ascii_uppercase()
+            }
+        }
+
+        let connection = "sqlite://name_sanitizer?mode=memory&cache=shared";
+        let mut sqlite = Sqlite::with_options(
+            Connection::open(connection_path(connection).as_ref()).unwrap(),
+            Options {
+                name_sanitizer: Some(Arc::new(Uppercase)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(uint8 value, bool[] flags)").unwrap();
+        sqlite
+            .prepare_event("EVENT", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let names = |sqlite: &Sqlite, sql: &str| -> Vec<String> {
+            let mut statement = sqlite.connection.prepare(sql).unwrap();
+            let rows = statement.query_map((), |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+        assert_eq!(
+            names(
+                &sqlite,
+                "SELECT name FROM sqlite_schema WHERE type = 'table' AND name LIKE 'EVENT%' \
+                 ORDER BY name;"
+            ),
+            ["EVENT", "EVENT_FLAGS_0"]
+        );
+        assert!(
+            names(&sqlite, "SELECT name FROM pragma_table_info('EVENT');")
+                .ends_with(&["VALUE_0".to_string()])
+        );
+
+        // Reopening with another sanitizer would look for other tables.
+        let mut reopened = Sqlite::open(connection).unwrap();
+        let result = reopened
+            .prepare_event("EVENT", &event)
+            .now_or_never()
+            .unwrap();
+        assert!(result.is_err());
+        drop(sqlite);
+    }
+
+    #[test]
+    fn hostile_field_names() {
+        let names = [
+            "x\"); DROP TABLE _event_block; --",
+            "x'); DROP TABLE _event_block; --",
+            "--",
+            "/*",
+            "1",
+            "é🦀",
+            "select",
+            "",
+            "block_number",
+            "sqlite_master",
+        ];
+        let declaration = format!(
+            "event Event({})",
+            vec!["bool"; names.len()].join(", ") + ", bool[]"
+        );
+        let mut event = EventDescriptor::parse_declaration(&declaration).unwrap();
+        for (input, name) in event.inputs.iter_mut().zip(names) {
+            input.field.name = name.to_string();
+        }
+        event.inputs.last_mut().unwrap().field.name = "a\"; DELETE FROM blocks; --".to_string();
+
+        let mut sqlite = Sqlite::new_for_test();
+        let event_blocks = count_rows(&sqlite, "_event_block");
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            fields: vec![AbiValue::Bool(true); names.len()]
+                .into_iter()
+                .chain([AbiValue::Array(
+                    Array::new(AbiKind::Bool, vec![AbiValue::Bool(false)]).unwrap(),
+                )])
+                .collect(),
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+
+        let columns = |table: &str| -> Vec<String> {
+            let mut statement = sqlite
+                .connection
+                .prepare("SELECT name FROM pragma_table_info(?1);")
+                .unwrap();
+            let rows = statement.query_map((table,), |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+        assert_eq!(
+            columns("event")[FIXED_COLUMNS_COUNT..],
+            [
+                "xDROPTABLE_event_block_0",
+                "xDROPTABLE_event_block_1",
+                "_2",
+                "_3",
+                "_1_4",
+                "_5",
+                "select_6",
+                "field_7",
+                "block_number_8",
+                "sqlite_master_9",
+            ]
+        );
+        assert_eq!(
+            columns("event_aDELETEFROMblocks_0").len(),
+            FIXED_COLUMNS_COUNT + 2
+        );
+        assert_eq!(count_rows(&sqlite, "_event_block"), event_blocks + 1);
+        assert_eq!(
+            sqlite.read_logs("event", 0, 0).unwrap()[0].fields,
+            log.fields
+        );
+    }
+
+    #[test]
+    fn read_fields() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event =
+            EventDescriptor::parse_declaration("event Event(bool b, uint256[] values, address a)")
+                .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..4u8)
+            .map(|i| Log {
+                event: "event".into(),
+                block_number: i.into(),
+                fields: vec![
+                    AbiValue::Bool(i > 1),
+                    AbiValue::Array(
+                        Array::from_values(vec![AbiValue::Uint(Uint::new(256, i.into()).unwrap())])
+                            .unwrap(),
+                    ),
+                    AbiValue::Address(Address([i; 20])),
+                ],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let rows = sqlite
+            .read_fields("event", &["block_number", "a_1", "b_0"], 1, 2)
+            .unwrap();
+        assert_eq!(
+            rows,
+            [1, 2].map(|i: u8| vec![
+                SqlValue::Integer(i.into()),
+                SqlValue::Blob(vec![i; 20]),
+                SqlValue::Integer((i > 1).into()),
+            ])
+        );
+
+        assert!(sqlite.read_fields("event", &["values_0"], 0, 3).is_err());
+        assert!(sqlite.read_fields("event", &["b_0; DROP"], 0, 3).is_err());
+        assert!(sqlite.read_fields("event", &[], 0, 3).is_err());
+        assert!(sqlite.read_fields("unknown", &["b_0"], 0, 3).is_err());
+    }
+
+    #[test]
+    fn resync_indexed() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(sqlite.resync_indexed("event").unwrap(), 0);
+
+        let logs = [5, 7].map(|block_number| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+            ..Default::default()
+        });
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 10,
+                finalized: 2,
+            },
+        }];
+        sqlite.update_iter(&blocks, &logs, &[], &[]).unwrap();
+        let event_block =
+            |sqlite: &mut Sqlite| sqlite.event_block("event").now_or_never().unwrap().unwrap();
+
+        // Blocks without logs after the last stored log are kept.
+        assert_eq!(sqlite.resync_indexed("event").unwrap(), 10);
+        assert_eq!(event_block(&mut sqlite).indexed, 10);
+
+        sqlite
+            .connection
+            .execute(
+                "UPDATE _event_block SET indexed = 3 WHERE event = 'event'",
+                (),
+            )
+            .unwrap();
+        assert_eq!(sqlite.resync_indexed("event").unwrap(), 7);
+        assert_eq!(
+            event_block(&mut sqlite),
+            database::Block {
+                indexed: 7,
+                finalized: 2
+            }
+        );
+
+        assert!(sqlite.resync_indexed("unknown").is_err());
+    }
+
+    #[test]
+    fn small_statement_cache() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                statement_cache_capacity: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256, bool[])").unwrap();
+        let names = (0..20).map(|i| format!("event{i}")).collect::<Vec<_>>();
+        for name in &names {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        for block_number in 0..2 {
+            let logs = names.iter().map(|name| Log {
+                event: name.into(),
+                block_number,
+                fields: vec![
+                    AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap()),
+                    AbiValue::Array(Array::from_values(vec![AbiValue::Bool(true)]).unwrap()),
+                ],
+                ..Default::default()
+            });
+            sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+        }
+        for name in &names {
+            assert_eq!(count_rows(&sqlite, name), 2);
+            assert_eq!(count_rows(&sqlite, &format!("{name}_array_0")), 2);
+        }
+    }
+
+    #[test]
+    fn backup() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..3).map(|block_number| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+
+        let path = std::env::temp_dir().join(format!("arak_backup_{}.db", std::process::id()));
+        sqlite
+            .backup_to(&path, Some(|progress| assert!(progress.remaining >= 0)))
+            .unwrap();
+        let backup = Sqlite::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(count_rows(&backup, "event"), 3);
+        drop(backup);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Pins the generated schema. Changing the output of this test means that
+    /// existing databases become incompatible and need a migration.
+    #[test]
+    fn schema_stability() {
+        fn schema(declaration: &str) -> (Vec<String>, Vec<String>) {
+            let mut sqlite = Sqlite::new_for_test();
+            let event = EventDescriptor::parse_declaration(declaration).unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let mut statement = sqlite
+                .connection
+                .prepare("SELECT sql FROM sqlite_schema WHERE name LIKE 'event%' ORDER BY rowid")
+                .unwrap();
+            let create = statement
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let insert = sqlite.inner.events["event"]
+                .insert_statements
+                .iter()
+                .map(|statement| statement.sql(&statement.table))
+                .collect();
+            (create, insert)
+        }
+
+        let cases: &[(&str, &[&str], &[&str])] = &[
+            (
+                "event Event(bool b0, (bool b0, bool b1) my_bools, address a0)",
+                &[
+                    "CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT \
+                   NULL, transaction_index INTEGER NOT NULL, address BLOB NOT NULL, b0_0 \
+                   INTEGER NOT NULL, my_bools_b0_1 INTEGER NOT NULL, my_bools_b1_2 INTEGER NOT \
+                   NULL, a0_3 BLOB NOT NULL, PRIMARY KEY(block_number ASC, log_index ASC)) \
+                   STRICT",
+                ],
+                &["INSERT INTO event VALUES(?1,?2,?3,?4,?5,?6,?7,?8);"],
+            ),
+            // CoW Protocol settlement trade.
+            (
+                "event Trade(address indexed owner, address sellToken, address buyToken, \
+                 uint256 sellAmount, uint256 buyAmount, uint256 feeAmount, bytes orderUid)",
+                &[
+                    "CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT \
+                   NULL, transaction_index INTEGER NOT NULL, address BLOB NOT NULL, owner_0 \
+                   BLOB NOT NULL, sellToken_1 BLOB NOT NULL, buyToken_2 BLOB NOT NULL, \
+                   sellAmount_3 BLOB NOT NULL, buyAmount_4 BLOB NOT NULL, feeAmount_5 BLOB NOT \
+                   NULL, orderUid_6 BLOB NOT NULL, PRIMARY KEY(block_number ASC, log_index \
+                   ASC)) STRICT",
+                ],
+                &["INSERT INTO event VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11);"],
+            ),
+            (
+                "event Event(bool b, (uint8 a, (bool, bytes4 c) inner) t, address[2] pair, \
+                 (bool x, string y)[] entries, int16 i, uint256[] amounts)",
+                &[
+                    "CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT \
+                     NULL, transaction_index INTEGER NOT NULL, address BLOB NOT NULL, b_0 \
+                     INTEGER NOT NULL, t_a_1 BLOB NOT NULL, t_inner_2 INTEGER NOT NULL, \
+                     t_inner_c_3 BLOB NOT NULL CHECK(length(t_inner_c_3) = 4), pair_0_4 BLOB \
+                     NOT NULL, pair_1_5 BLOB NOT NULL, i_6 BLOB NOT NULL, PRIMARY \
+                     KEY(block_number ASC, log_index ASC)) STRICT",
+                    "CREATE TABLE event_entries_0 (block_number INTEGER NOT NULL, log_index \
+                     INTEGER NOT NULL, transaction_index INTEGER NOT NULL, address BLOB NOT \
+                     NULL, array_index INTEGER NOT NULL, x_0 INTEGER NOT NULL, y_1 TEXT NOT \
+                     NULL, PRIMARY KEY(block_number ASC, log_index ASC, array_index ASC)) \
+                     STRICT",
+                    "CREATE TABLE event_amounts_1 (block_number INTEGER NOT NULL, log_index \
+                     INTEGER NOT NULL, transaction_index INTEGER NOT NULL, address BLOB NOT \
+                     NULL, array_index INTEGER NOT NULL, amounts_0 BLOB NOT NULL, PRIMARY \
+                     KEY(block_number ASC, log_index ASC, array_index ASC)) STRICT",
+                ],
+                &[
+                    "INSERT INTO event VALUES(?1,?2,?3,?4,?5,?6,?7,?8,?9,?10,?11);",
+                    "INSERT INTO event_entries_0 VALUES(?1,?2,?3,?4,?5,?6,?7);",
+                    "INSERT INTO event_amounts_1 VALUES(?1,?2,?3,?4,?5,?6);",
+                ],
+            ),
+        ];
+        for (declaration, expected_create, expected_insert) in cases {
+            let (create, insert) = schema(declaration);
+            assert_eq!(&create, expected_create, "{declaration}");
+            assert_eq!(&insert, expected_insert, "{declaration}");
+        }
+    }
+
+    #[test]
+    fn decode_signed_int() {
+        for bits in [8, 16, 64, 256] {
+            let min = I256::MIN >> (256 - bits as u32);
+            let max = !min;
+            for value in [I256::MINUS_ONE, min, max, I256::new(5)] {
+                let int = Int::new(bits, value).unwrap();
+                let stored = int.get().to_be_bytes();
+                assert_eq!(decode_int(bits, &stored).unwrap(), int);
+
+                // Shorter blobs are sign extended.
+                let len = bits / 8;
+                assert_eq!(decode_int(bits, &stored[32 - len..]).unwrap(), int);
+            }
+        }
+        assert_eq!(decode_int(256, &[0xff]).unwrap().get(), I256::MINUS_ONE);
+        assert_eq!(decode_int(256, &[0x7f]).unwrap().get(), I256::new(0x7f));
+        assert_eq!(decode_int(256, &[]).unwrap().get(), I256::ZERO);
+        assert!(decode_int(8, &[0, 0x80]).is_err());
+        assert!(decode_int(256, &[0; 33]).is_err());
+    }
+
+    #[test]
+    fn biased_int_sort_order() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                integer_encoding: Some(IntegerEncoding::Biased),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(int8, int256, uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let values = [
+            (-128, I256::MIN, U256::ZERO),
+            (-1, I256::new(-1), U256::ONE),
+            (0, I256::ZERO, U256::new(255)),
+            (1, I256::ONE, U256::new(256)),
+            (127, I256::MAX, U256::MAX),
+        ];
+        // Stored in reverse order so that the rowid order doesn't match.
+        let logs = values
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, (small, large, unsigned))| Log {
+                event: "event".into(),
+                log_index: i as u64,
+                fields: vec![
+                    AbiValue::Int(Int::new(8, I256::new(*small)).unwrap()),
+                    AbiValue::Int(Int::new(256, *large).unwrap()),
+                    AbiValue::Uint(Uint::new(256, *unsigned).unwrap()),
+                ],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        for column in ["field_0", "field_1", "field_2"] {
+            let log_indices = sqlite
+                .connection
+                .prepare(&format!("SELECT log_index FROM event ORDER BY {column};"))
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect::<Vec<i64>>();
+            assert_eq!(log_indices, [4, 3, 2, 1, 0], "{column}");
+        }
+        let stored: Vec<u8> = sqlite
+            .connection
+            .query_row(
+                "SELECT field_0 FROM event WHERE log_index = 4;",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, [0; 32]);
+
+        let read = sqlite.read_logs("event", 0, 0).unwrap();
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+        }
+    }
+
+    #[test]
+    fn same_event_multiple_names() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Transfer(uint256 amount)").unwrap();
+        for name in ["raw", "filtered"] {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let log = |event: &'static str, amount: u8| Log {
+            event: event.into(),
+            block_number: amount.into(),
+            fields: vec![AbiValue::Uint(Uint::new(256, amount.into()).unwrap())],
+            ..Default::default()
+        };
+        let blocks = [database::EventBlock {
+            event: "filtered",
+            block: database::Block {
+                indexed: 5,
+                finalized: 0,
+            },
+        }];
+        sqlite
+            .update_iter(
+                &blocks,
+                &[log("raw", 1), log("raw", 2), log("filtered", 2)],
+                &[],
+                &[],
+            )
+            .unwrap();
+
+        let amounts = |name| {
+            sqlite
+                .read_logs(name, 0, 10)
+                .unwrap()
+                .into_iter()
+                .map(|read| {
+                    assert_eq!(read.event, name);
+                    let expected = log(name, read.block_number as u8);
+                    assert_eq!(read.fields, expected.fields);
+                    read.block_number
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(amounts("raw"), [1, 2]);
+        assert_eq!(amounts("filtered"), [2]);
+        let block = |sqlite: &Sqlite, name| sqlite.inner.event_block(&sqlite.connection, name);
+        assert_eq!(block(&sqlite, "raw").unwrap().indexed, 0);
+        assert_eq!(block(&sqlite, "filtered").unwrap().indexed, 5);
+    }
+
+    #[test]
+    fn update_indexed_block() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+
+        let summary = sqlite.update_iter(&[], &[] as &[Log], &[], &[]).unwrap();
+        assert_eq!(summary.indexed_block, None);
+
+        let logs = [3, 9, 5].map(|block_number| Log {
+            event: "event".into(),
+            block_number,
+            ..Default::default()
+        });
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 7,
+                finalized: 2,
+            },
+        }];
+        let summary = sqlite.update_iter(&blocks, &logs, &[], &[]).unwrap();
+        assert_eq!(summary.indexed_block, Some(9));
+
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 12,
+                finalized: 2,
+            },
+        }];
+        let summary = sqlite
+            .update_iter(&blocks, &[] as &[Log], &[], &[])
+            .unwrap();
+        assert_eq!(summary.indexed_block, Some(12));
+        let indexed = sqlite
+            .inner
+            .event_block(&sqlite.connection, "event")
+            .unwrap();
+        assert_eq!(summary.indexed_block, Some(indexed.indexed));
+    }
+
+    #[test]
+    fn numeric_cmp_ordering() {
+        let sorted = [
+            "-1000", "-20", "-0003", "-1", "0", "-0", "000", "1", "+2", "0010", "999", "1000", "",
+            "abc",
+        ];
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                let expected = match (a, b) {
+                    (&"0" | &"-0" | &"000", &"0" | &"-0" | &"000") => Ordering::Equal,
+                    _ => i.cmp(&j),
+                };
+                assert_eq!(numeric_cmp(a, b), expected, "{a} {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn decimal_text() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                integer_encoding: Some(IntegerEncoding::DecimalText),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(int256, uint8)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let values = [7, -100, 1000, 0, -5, 20, i64::MIN];
+        let logs = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Log {
+                event: "event".into(),
+                log_index: i as u64,
+                fields: vec![
+                    AbiValue::Int(Int::new(256, I256::new((*value).into())).unwrap()),
+                    AbiValue::Uint(Uint::new(8, U256::new(i as u128 * 40)).unwrap()),
+                ],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let query = |sql: &str| -> Vec<String> {
+            sqlite
+                .connection
+                .prepare(sql)
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        let mut sorted = values.to_vec();
+        sorted.sort();
+        assert_eq!(
+            query("SELECT field_0 FROM event ORDER BY field_0;"),
+            sorted.iter().map(i64::to_string).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            query("SELECT field_0 FROM event WHERE field_0 > '10' ORDER BY field_0;"),
+            ["20", "1000"]
+        );
+        assert_eq!(
+            query("SELECT field_1 FROM event WHERE field_1 >= '200' ORDER BY field_1 DESC;"),
+            ["240", "200"]
+        );
+
+        let read = sqlite.read_logs("event", 0, 0).unwrap();
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+        }
+    }
+
+    #[test]
+    fn decode_value_round_trip() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(int8, int256, uint64, address, bool, bytes3, function, bytes, string)",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let fields = vec![
+            AbiValue::Int(Int::new(8, I256::new(-128)).unwrap()),
+            AbiValue::Int(Int::new(256, I256::MIN).unwrap()),
+            AbiValue::Uint(Uint::new(64, U256::new(u64::MAX.into())).unwrap()),
+            AbiValue::Address(Address([1; 20])),
+            AbiValue::Bool(true),
+            AbiValue::FixedBytes(FixedBytes::new(&[2; 3]).unwrap()),
+            AbiValue::Function(ExternalFunction {
+                address: Address([3; 20]),
+                selector: Selector([4; 4]),
+            }),
+            AbiValue::Bytes(vec![5, 6]),
+            AbiValue::String("arak".to_string()),
+        ];
+        let log = Log {
+            event: "event".into(),
+            fields: fields.clone(),
+            ..Default::default()
+        };
+        sqlite.update_iter(&[], [log], &[], &[]).unwrap();
+
+        let columns = sqlite.inner.events["event"].columns.clone();
+        let columns = columns.iter().map(String::as_str).collect::<Vec<_>>();
+        let row = sqlite
+            .read_fields("event", &columns, 0, 0)
+            .unwrap()
+            .remove(0);
+        let encoding = sqlite.integer_encoding("event").unwrap();
+        assert_eq!(encoding, IntegerEncoding::Biased);
+        let decoded = fields
+            .iter()
+            .zip(&row)
+            .map(|(field, value)| {
+                Sqlite::decode_value(&field.kind(), value.into(), encoding).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, fields);
+
+        assert!(Sqlite::decode_value(&AbiKind::Bool, SqlValueRef::Integer(2), encoding).is_err());
+        assert!(
+            Sqlite::decode_value(&AbiKind::Address, SqlValueRef::Blob(&[0; 19]), encoding).is_err()
+        );
+    }
+
+    #[test]
+    fn prepare_events() {
+        let mut sqlite = Sqlite::new_for_test();
+        let valid = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        let tables = |sqlite: &Sqlite| -> i64 {
+            sqlite
+                .connection
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_schema WHERE name LIKE 'event%'",
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+
+        // Names starting with an underscore are reserved.
+        let result =
+            sqlite.prepare_events(&[("event0", &valid), ("event1", &valid), ("_event2", &valid)]);
+        assert!(result.is_err());
+        assert_eq!(tables(&sqlite), 0);
+        assert!(sqlite.inner.events.is_empty());
+        assert!(sqlite
+            .event_block("event0")
+            .now_or_never()
+            .unwrap()
+            .is_err());
+
+        sqlite
+            .prepare_events(&[("event0", &valid), ("event1", &valid)])
+            .unwrap();
+        assert_eq!(tables(&sqlite), 2);
+        assert_eq!(
+            sqlite
+                .event_block("event1")
+                .now_or_never()
+                .unwrap()
+                .unwrap(),
+            database::Block::default()
+        );
+    }
+
+    #[test]
+    fn empty_as_null() {
+        for empty_as_null in [false, true] {
+            let mut sqlite = Sqlite::with_options(
+                Connection::open_in_memory().unwrap(),
+                Options {
+                    empty_as_null,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let event = EventDescriptor::parse_declaration("event Event(bytes, string)").unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let fields = [
+                vec![AbiValue::Bytes(vec![]), AbiValue::String(String::new())],
+                vec![AbiValue::Bytes(vec![1]), AbiValue::String("a".to_string())],
+            ];
+            let logs = fields.iter().enumerate().map(|(i, fields)| Log {
+                event: "event".into(),
+                log_index: i as u64,
+                fields: fields.clone(),
+                ..Default::default()
+            });
+            sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+
+            let rows = sqlite
+                .read_fields("event", &["field_0", "field_1"], 0, 0)
+                .unwrap();
+            let empty = if empty_as_null {
+                vec![SqlValue::Null, SqlValue::Null]
+            } else {
+                vec![SqlValue::Blob(vec![]), SqlValue::Text(String::new())]
+            };
+            assert_eq!(rows[0], empty);
+            assert_eq!(
+                rows[1],
+                [SqlValue::Blob(vec![1]), SqlValue::Text("a".to_string())]
+            );
+            for (row, fields) in rows.iter().zip(&fields) {
+                for (value, field) in row.iter().zip(fields) {
+                    assert_eq!(
+                        Sqlite::decode_value(&field.kind(), value.into(), IntegerEncoding::Biased)
+                            .unwrap(),
+                        *field
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn managed_tables() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            table_prefix: "arak_".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        for (name, event) in [
+            ("transfer", "event Transfer(address, uint256)"),
+            ("batch", "event Batch(uint256[], bytes[])"),
+        ] {
+            sqlite
+                .prepare_event(name, &EventDescriptor::parse_declaration(event).unwrap())
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let tables = sqlite.managed_tables().unwrap();
+        assert_eq!(
+            tables,
+            [
+                "arak__column_metadata",
+                "arak__event_block",
+                "arak__event_naming",
+                "arak__event_signature",
+                "arak__event_tables",
+                "arak__reorg_log",
+                "arak_batch",
+                "arak_batch_array_0",
+                "arak_batch_array_1",
+                "arak_blocks",
+                "arak_transactions",
+                "arak_transfer",
+            ]
+        );
+        // Every table in the database is managed.
+        let stored: Vec<String> = sqlite
+            .connection
+            .prepare("SELECT name FROM sqlite_schema WHERE type = 'table' ORDER BY name;")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(stored, tables);
+
+        sqlite
+            .connection
+            .execute("DROP TABLE arak_batch_array_1;", ())
+            .unwrap();
+        assert!(sqlite.managed_tables().is_err());
+    }
+
+    #[test]
+    fn verify_cursors() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        for name in ["a", "b"] {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let logs = [5, 7].map(|block_number| Log {
+            event: "a".into(),
+            block_number,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        });
+        let blocks = [database::EventBlock {
+            event: "a",
+            block: database::Block {
+                indexed: 10,
+                finalized: 2,
+            },
+        }];
+        sqlite.update_iter(&blocks, &logs, &[], &[]).unwrap();
+        assert_eq!(sqlite.verify_cursors().unwrap(), []);
+
+        let set = |sqlite: &Sqlite, indexed: u64, finalized: u64| {
+            sqlite
+                .connection
+                .execute(
+                    "UPDATE _event_block SET indexed = ?1, finalized = ?2 WHERE event = 'a'",
+                    (indexed as i64, finalized as i64),
+                )
+                .unwrap();
+        };
+        set(&sqlite, 3, 4);
+        assert_eq!(
+            sqlite.verify_cursors().unwrap(),
+            [
+                CursorWarning::LogsPastIndexed {
+                    event: "a".to_string(),
+                    indexed: 3,
+                    max_block: 7,
+                },
+                CursorWarning::FinalizedPastIndexed {
+                    event: "a".to_string(),
+                    indexed: 3,
+                    finalized: 4,
+                },
+            ]
+        );
+        set(&sqlite, 7 + SUSPICIOUS_CURSOR_GAP + 1, 0);
+        assert_eq!(
+            sqlite.verify_cursors().unwrap(),
+            [CursorWarning::LargeGap {
+                event: "a".to_string(),
+                indexed: 7 + SUSPICIOUS_CURSOR_GAP + 1,
+                max_block: 7,
+            }]
+        );
+        // Reading doesn't fix anything.
+        assert_eq!(sqlite.verify_cursors().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn check_integrity() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool, uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::Bool(true),
+                AbiValue::Array(
+                    Array::from_values(vec![AbiValue::Uint(Uint::new(8, 1u32.into()).unwrap())])
+                        .unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        sqlite.update_iter(&[], [log], &[], &[]).unwrap();
+        assert_eq!(sqlite.check_integrity("event").unwrap(), []);
+
+        sqlite
+            .connection
+            .execute(
+                "INSERT INTO event_array_0 VALUES (2, 3, 0, x'', 0, x'01'), (2, 3, 0, x'', 1, x'02')",
+                (),
+            )
+            .unwrap();
+        assert_eq!(
+            sqlite.check_integrity("event").unwrap(),
+            [Inconsistency::OrphanedArrayRow {
+                table: "event_array_0".to_string(),
+                block_number: 2,
+                log_index: 3,
+            }]
+        );
+        assert!(sqlite.check_integrity("unknown").is_err());
+    }
+
+    #[test]
+    fn prepare_existing_event() {
+        static STATEMENTS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+        fn trace(statement: &str) {
+            STATEMENTS.lock().unwrap().push(statement.to_string());
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "arak_prepare_existing_event_{}.db",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256, bool[])").unwrap();
+        let prepare = || {
+            let mut sqlite = Sqlite::open(path).unwrap();
+            STATEMENTS.lock().unwrap().clear();
+            sqlite.connection.trace(Some(trace));
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            sqlite.connection.trace(None);
+            (sqlite, std::mem::take(&mut *STATEMENTS.lock().unwrap()))
+        };
+        let (_, statements) = prepare();
+        let creates = statements
+            .iter()
+            .filter(|statement| statement.starts_with("CREATE TABLE"))
+            .count();
+        assert_eq!(creates, 2);
+        // Restarting only looks up how the tables were created.
+        let (mut sqlite, statements) = prepare();
+        let work = statements
+            .iter()
+            .filter(|statement| !statement.contains("_rusqlite_sp"))
+            .collect::<Vec<_>>();
+        assert_eq!(work.len(), 5, "{work:#?}");
+        assert!(work.iter().all(
+            |statement| statement.starts_with("SELECT") && !statement.contains("pragma_table")
+        ));
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap()),
+                AbiValue::Array(Array::new(AbiKind::Bool, vec![AbiValue::Bool(true)]).unwrap()),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(
+            sqlite.read_logs("event", 0, 0).unwrap()[0].fields,
+            log.fields
+        );
+        drop(sqlite);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let path =
+            std::env::temp_dir().join(format!("arak_concurrent_reads_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool[])").unwrap();
+        let open = |options| {
+            let connection = Connection::open(path).unwrap();
+            let mut sqlite = Sqlite::with_options(connection, options).unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            sqlite
+        };
+        let mut writer = open(Default::default());
+        let strict = open(Options {
+            require_wal: true,
+            ..Default::default()
+        });
+        assert!(strict.read_logs("event", 0, 1000).is_err());
+        writer.enable_wal().unwrap();
+        assert!(strict.read_logs("event", 0, 1000).unwrap().is_empty());
+        let reader = open(Default::default());
+
+        let fields = vec![AbiValue::Array(
+            Array::new(AbiKind::Bool, vec![AbiValue::Bool(true); 3]).unwrap(),
+        )];
+        let writer = {
+            let fields = fields.clone();
+            std::thread::spawn(move || {
+                for block_number in 1..=100 {
+                    let log = |log_index| Log {
+                        event: "event".into(),
+                        block_number,
+                        log_index,
+                        fields: fields.clone(),
+                        ..Default::default()
+                    };
+                    writer
+                        .update_iter(&[], &[log(0), log(1)], &[], &[])
+                        .unwrap();
+                }
+            })
+        };
+        // Every update stores two logs and a log is only read with all of its
+        // array elements.
+        let mut read = 0;
+        loop {
+            let done = writer.is_finished();
+            let logs = reader.read_logs("event", 0, 1000).unwrap();
+            assert_eq!(logs.len() % 2, 0);
+            assert!(logs.len() >= read);
+            assert!(logs.iter().all(|log| log.fields == fields));
+            read = logs.len();
+            if done {
+                break;
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(read, 200);
+        drop((strict, reader));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{path}{suffix}"));
+        }
+    }
+
+    #[test]
+    fn read_only() {
+        let path = std::env::temp_dir().join(format!("arak_read_only_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(Sqlite::open_readonly(path).is_err());
+
+        let mut sqlite = Sqlite::open(path).unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![AbiValue::Uint(Uint::new(256, 1u32.into()).unwrap())],
+            ..Default::default()
+        };
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 2,
+                finalized: 1,
+            },
+        }];
+        sqlite
+            .update_iter(&blocks, std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        drop(sqlite);
+
+        let mut sqlite = Sqlite::open_readonly(path).unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sqlite.read_logs("event", 0, 2).unwrap()[0].fields,
+            log.fields
+        );
+        let other = EventDescriptor::parse_declaration("event Other(uint256)").unwrap();
+        let err = sqlite
+            .prepare_event("other", &other)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("read-only"), "{err}");
+        let block = sqlite.event_block("event").now_or_never().unwrap();
+        assert_eq!(
+            block.unwrap(),
+            database::Block {
+                indexed: 2,
+                finalized: 1
+            }
+        );
+        let err = sqlite
+            .update_iter(&blocks, &[], &[], &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("read-only"), "{err}");
+        assert!(sqlite.remove(&[]).now_or_never().unwrap().is_err());
+        drop(sqlite);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reorg_history() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (1..4).map(|block_number| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![AbiValue::Array(
+                Array::from_values(vec![AbiValue::Uint(Uint::new(8, 1u32.into()).unwrap())])
+                    .unwrap(),
+            )],
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+        assert_eq!(sqlite.reorg_history("event").unwrap(), []);
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        let uncle = |number| database::Uncle {
+            event: "event",
+            number,
+        };
+        sqlite.remove(&[uncle(2)]).now_or_never().unwrap().unwrap();
+        sqlite.remove(&[uncle(5)]).now_or_never().unwrap().unwrap();
+        let history = sqlite.reorg_history("event").unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.from_block, entry.removed_rows))
+                .collect::<Vec<_>>(),
+            [(2, 4), (5, 0)]
+        );
+        assert!(history.iter().all(|entry| entry.at >= before));
+        assert_eq!(sqlite.reorg_history("other").unwrap(), []);
+    }
+
+    #[test]
+    fn clock() {
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite.set_clock(|| UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 3,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let history = sqlite.reorg_history("event").unwrap();
+        assert_eq!(
+            history[0].at,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn open_with_flags() {
+        let path =
+            std::env::temp_dir().join(format!("arak_open_with_flags_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let path_str = path.to_str().unwrap();
+
+        let err = Sqlite::open_with_flags(path_str, OpenFlags::SQLITE_OPEN_READ_WRITE)
+            .err()
+            .unwrap();
+        assert!(format!("{err:?}").contains("open "), "{err:?}");
+        assert!(!path.exists());
+
+        Sqlite::open_with_flags(
+            path_str,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        )
+        .unwrap();
+        assert!(path.exists());
+        Sqlite::open_with_flags(path_str, OpenFlags::SQLITE_OPEN_READ_WRITE).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn transaction_index_key() {
+        let path = std::env::temp_dir().join(format!(
+            "arak_transaction_index_key_{}.db",
+            std::process::id()
+        ));
+        let open = |transaction_index_key| {
+            Sqlite::with_options(
+                Connection::open(&path).unwrap(),
+                Options {
+                    transaction_index_key,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        let mut sqlite = open(true);
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let sql: String = sqlite
+            .connection
+            .query_row(
+                "SELECT sql FROM sqlite_schema WHERE name = 'event_array_0'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(sql.ends_with(
+            "PRIMARY KEY(block_number ASC, log_index ASC, transaction_index ASC, array_index \
+             ASC)) STRICT"
+        ));
+
+        let logs = (0..2).map(|transaction_index| Log {
+            event: "event".into(),
+            block_number: 1,
+            log_index: 1,
+            transaction_index,
+            fields: vec![AbiValue::Array(
+                Array::from_values(vec![AbiValue::Uint(Uint::new(8, 1u32.into()).unwrap())])
+                    .unwrap(),
+            )],
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 2);
+        assert_eq!(count_rows(&sqlite, "event_array_0"), 2);
+        drop(sqlite);
+
+        // The key of existing tables can't change.
+        let mut sqlite = open(false);
+        assert!(sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .is_err());
+        drop(sqlite);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Small deterministic pseudo random number generator (xorshift64*) for
+    /// the property test.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545f4914f6cdd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        fn bytes32(&mut self) -> [u8; 32] {
+            self.bytes(32).try_into().unwrap()
+        }
+
+        /// A kind that arak supports, so without dynamic arrays in dynamic
+        /// arrays.
+        fn kind(&mut self, depth: usize, in_array: bool) -> AbiKind {
+            let choice = if depth == 0 { 0 } else { self.below(11) };
+            match choice {
+                8 => AbiKind::Tuple(
+                    (0..1 + self.below(3))
+                        .map(|_| self.kind(depth - 1, in_array))
+                        .collect(),
+                ),
+                9 => {
+                    AbiKind::FixedArray(1 + self.below(3), Box::new(self.kind(depth - 1, in_array)))
+                }
+                10 if !in_array => AbiKind::Array(Box::new(self.kind(depth - 1, true))),
+                _ => match self.below(8) {
+                    0 => AbiKind::Int(BitWidth::new(8 * (1 + self.below(32))).unwrap()),
+                    1 => AbiKind::Uint(BitWidth::new(8 * (1 + self.below(32))).unwrap()),
+                    2 => AbiKind::Address,
+                    3 => AbiKind::Bool,
+                    4 => AbiKind::FixedBytes(ByteLength::new(1 + self.below(32)).unwrap()),
+                    5 => AbiKind::Function,
+                    6 => AbiKind::Bytes,
+                    _ => AbiKind::String,
+                },
+            }
+        }
+
+        fn value(&mut self, kind: &AbiKind) -> AbiValue {
+            match kind {
+                AbiKind::Int(bits) => {
+                    let shift = 256 - bits.get() as u32;
+                    let value = (I256::from_be_bytes(self.bytes32()) << shift) >> shift;
+                    AbiValue::Int(Int::new(bits.get(), value).unwrap())
+                }
+                AbiKind::Uint(bits) => {
+                    let value = U256::from_be_bytes(self.bytes32()) >> (256 - bits.get() as u32);
+                    AbiValue::Uint(Uint::new(bits.get(), value).unwrap())
+                }
+                AbiKind::Address => AbiValue::Address(Address(self.bytes(20).try_into().unwrap())),
+                AbiKind::Bool => AbiValue::Bool(self.below(2) == 0),
+                AbiKind::FixedBytes(len) => {
+                    AbiValue::FixedBytes(FixedBytes::new(&self.bytes(len.get())).unwrap())
+                }
+                AbiKind::Function => AbiValue::Function(ExternalFunction {
+                    address: Address(self.bytes(20).try_into().unwrap()),
+                    selector: Selector(self.bytes(4).try_into().unwrap()),
+                }),
+                AbiKind::Bytes => {
+                    let len = self.below(4);
+                    AbiValue::Bytes(self.bytes(len))
+                }
+                AbiKind::String => AbiValue::String(
+                    (0..self.below(4))
+                        .map(|_| ['a', 'β', '🦀'][self.below(3)])
+                        .collect(),
+                ),
+                AbiKind::Tuple(kinds) => {
+                    AbiValue::Tuple(kinds.iter().map(|kind| self.value(kind)).collect())
+                }
+                AbiKind::FixedArray(len, kind) => AbiValue::FixedArray(
+                    FixedArray::new(
+                        (**kind).clone(),
+                        (0..*len).map(|_| self.value(kind)).collect(),
+                    )
+                    .unwrap(),
+                ),
+                AbiKind::Array(kind) => AbiValue::Array(
+                    Array::new(
+                        (**kind).clone(),
+                        (0..self.below(3)).map(|_| self.value(kind)).collect(),
+                    )
+                    .unwrap(),
+                ),
+            }
+        }
+    }
+
+    /// Stores random values of random event signatures and checks that they
+    /// are read back unchanged.
+    #[test]
+    fn round_trip_random_events() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..500 {
+            let kinds = (0..1 + rng.below(3))
+                .map(|_| rng.kind(3, false))
+                .collect::<Vec<_>>();
+            let declaration = format!(
+                "event Event({})",
+                kinds.iter().map(kind_name).collect::<Vec<_>>().join(", ")
+            );
+            let event = EventDescriptor::parse_declaration(&declaration).unwrap();
+            let mut sqlite = Sqlite::new_for_test();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let logs = (0..3)
+                .map(|log_index| Log {
+                    event: "event".into(),
+                    block_number: 1,
+                    log_index,
+                    transaction_index: rng.next() >> 1,
+                    address: Address(rng.bytes(20).try_into().unwrap()),
+                    fields: kinds.iter().map(|kind| rng.value(kind)).collect(),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+            let read = sqlite.read_logs("event", 0, 1).unwrap();
+            assert_eq!(read.len(), logs.len(), "{declaration}");
+            for (read, log) in read.iter().zip(&logs) {
+                assert_eq!(
+                    (
+                        read.block_number,
+                        read.log_index,
+                        read.transaction_index,
+                        read.address
+                    ),
+                    (
+                        log.block_number,
+                        log.log_index,
+                        log.transaction_index,
+                        log.address
+                    ),
+                    "{declaration}"
+                );
+                assert_eq!(read.fields, log.fields, "{declaration}");
+            }
+        }
+    }
+
+    fn print_table(con: &Connection, table: &str) {
+        let mut statement = con.prepare(&format!("SELECT * FROM {table}")).unwrap();
         let mut rows = statement.query(()).unwrap();
         while let Some(row) = rows.next().unwrap() {
             for i in 0..row.as_ref().column_count() {
@@ -648,255 +6431,3604 @@ mod tests {
                 let value = row.get_ref(i).unwrap();
                 println!("{name}: {value:?}");
             }
-            println!();
+            println!();
+        }
+    }
+
+    #[tokio::test]
+    async fn full_leaf_types() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = r#"
+            event Event (
+                int256,
+                uint256,
+                address,
+                bool,
+                bytes1,
+                function,
+                bytes,
+                string
+            )
+        "#;
+        let event = EventDescriptor::parse_declaration(event).unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let fields = vec![
+            AbiValue::Int(Int::new(256, 1i32.into()).unwrap()),
+            AbiValue::Uint(Uint::new(256, 2u32.into()).unwrap()),
+            AbiValue::Address(Address([3; 20])),
+            AbiValue::Bool(true),
+            AbiValue::FixedBytes(FixedBytes::new(&[4]).unwrap()),
+            AbiValue::Function(ExternalFunction {
+                address: Address([6; 20]),
+                selector: Selector([7, 8, 9, 10]),
+            }),
+            AbiValue::Bytes(vec![11, 12]),
+            AbiValue::String("abcd".to_string()),
+        ];
+        sqlite
+            .update(
+                &[],
+                &[Log {
+                    event: "event".into(),
+                    block_number: 1,
+                    log_index: 2,
+                    transaction_index: 3,
+                    address: Address([4; 20]),
+                    fields,
+                    ..Default::default()
+                }],
+                &[database::BlockTime {
+                    number: 1,
+                    timestamp: SystemTime::UNIX_EPOCH,
+                }],
+                &[database::Transaction {
+                    block_number: 1,
+                    index: 2,
+                    hash: digest!(
+                        "0x0000000000000000000000000101010101010101010101010101010101010101"
+                    ),
+                    from: Address([4; 20]),
+                    to: Some(Address([4; 20])),
+                }],
+            )
+            .await
+            .unwrap();
+
+        print_table(&sqlite.connection, "event");
+        print_table(&sqlite.connection, "blocks");
+        print_table(&sqlite.connection, "transactions");
+    }
+
+    #[tokio::test]
+    async fn with_array() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = r#"
+            event Event (
+                (bool, string)[]
+            )
+        "#;
+        let event = EventDescriptor::parse_declaration(event).unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 0,
+            fields: vec![AbiValue::Array(
+                Array::from_values(vec![
+                    AbiValue::Tuple(vec![
+                        AbiValue::Bool(false),
+                        AbiValue::String("hello".to_string()),
+                    ]),
+                    AbiValue::Tuple(vec![
+                        AbiValue::Bool(true),
+                        AbiValue::String("world".to_string()),
+                    ]),
+                ])
+                .unwrap(),
+            )],
+            ..Default::default()
+        };
+        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![AbiValue::Array(
+                Array::new(AbiKind::Tuple(vec![AbiKind::Bool, AbiKind::String]), vec![]).unwrap(),
+            )],
+            ..Default::default()
+        };
+        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+
+        print_table(&sqlite.connection, "event");
+        print_table(&sqlite.connection, "event_array_0");
+        let mut statement = sqlite
+            .connection
+            .prepare("SELECT typeof(field_1), field_1 FROM event_array_0 ORDER BY array_index")
+            .unwrap();
+        let strings: Vec<(String, String)> = statement
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            strings,
+            [
+                ("text".to_string(), "hello".to_string()),
+                ("text".to_string(), "world".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn string_as_text() {
+        let event = EventDescriptor::parse_declaration("event Event(string, string[])").unwrap();
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::String("arak".to_string()),
+                AbiValue::Array(
+                    Array::new(AbiKind::String, vec![AbiValue::String("é".to_string())]).unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let types = |sqlite: &Sqlite| {
+            [
+                "SELECT typeof(field_0) FROM event",
+                "SELECT typeof(field_0) FROM event_array_0",
+            ]
+            .map(|sql| {
+                sqlite
+                    .connection
+                    .query_row(sql, (), |row| row.get::<_, String>(0))
+                    .unwrap()
+            })
+        };
+
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(types(&sqlite), ["text", "text"]);
+        assert_eq!(
+            sqlite.read_logs("event", 0, 0).unwrap()[0].fields,
+            log.fields
+        );
+        assert_eq!(
+            sqlite.read_fields("event", &["field_0"], 0, 0).unwrap(),
+            [[SqlValue::Text("arak".to_string())]]
+        );
+
+        // Tables from before strings were stored as text keep storing blobs.
+        let mut statement = sqlite
+            .connection
+            .prepare("SELECT sql FROM sqlite_schema WHERE name LIKE 'event%' ORDER BY rowid")
+            .unwrap();
+        let creates: Vec<String> = statement
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        for create in creates {
+            connection
+                .execute(&create.replace(" TEXT ", " BLOB "), ())
+                .unwrap();
+        }
+        let mut sqlite = Sqlite::new(connection).unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(types(&sqlite), ["blob", "blob"]);
+        assert_eq!(
+            sqlite.read_logs("event", 0, 0).unwrap()[0].fields,
+            log.fields
+        );
+        assert_eq!(
+            sqlite.search_text("event", "field_0", "RA").unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn tuple_with_dynamic_array() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event((uint256 id, bytes[] data, bool flag) t0, address a0)",
+        )
+        .unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::Tuple(vec![
+                    AbiValue::Uint(Uint::new(256, 7u32.into()).unwrap()),
+                    AbiValue::Array(
+                        Array::from_values(vec![
+                            AbiValue::Bytes(vec![1]),
+                            AbiValue::Bytes(vec![2, 2]),
+                        ])
+                        .unwrap(),
+                    ),
+                    AbiValue::Bool(true),
+                ]),
+                AbiValue::Address(Address([3; 20])),
+            ],
+            ..Default::default()
+        };
+        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+
+        let primary: (u64, Vec<u8>, bool, Vec<u8>) = sqlite
+            .connection
+            .query_row(
+                "SELECT block_number, t0_id_0, t0_flag_1, a0_2 FROM event",
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        let mut id = [0; 32];
+        id[31] = 7;
+        assert_eq!(primary, (1, id.to_vec(), true, vec![3; 20]));
+
+        let mut statement = sqlite
+            .connection
+            .prepare("SELECT block_number, array_index, data_0 FROM event_data_0")
+            .unwrap();
+        let array: Vec<(u64, u64, Vec<u8>)> = statement
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(array, [(1, 0, vec![1]), (1, 1, vec![2, 2])]);
+    }
+
+    #[tokio::test]
+    async fn fixed_bytes_lengths() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bytes4, bytes32)").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::FixedBytes(FixedBytes::new(&[1; 4]).unwrap()),
+                AbiValue::FixedBytes(FixedBytes::new(&[2; 32]).unwrap()),
+            ],
+            ..Default::default()
+        };
+        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+
+        let (field_0, field_1): (Vec<u8>, Vec<u8>) = sqlite
+            .connection
+            .query_row("SELECT field_0, field_1 FROM event", (), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(field_0, [1; 4]);
+        assert_eq!(field_1, [2; 32]);
+
+        // The length is enforced by the schema.
+        let result = sqlite.connection.execute(
+            "INSERT INTO event VALUES (1, 0, 0, ?1, ?2, ?3)",
+            (&[0u8; 20], &[1u8; 3], &[2u8; 32]),
+        );
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn update_iter() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let logs = (0..10u64).map(|i| Log {
+            event: "event".into(),
+            block_number: i,
+            fields: vec![AbiValue::Uint(Uint::new(256, i.into()).unwrap())],
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 10);
+    }
+
+    #[test]
+    fn max_batch_size() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                max_batch_size: Some(3),
+                on_conflict: OnConflict::Ignore,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |i: u64, event: &'static str| Log {
+            event: event.into(),
+            block_number: i,
+            fields: vec![AbiValue::Uint(Uint::new(256, i.into()).unwrap())],
+            ..Default::default()
+        };
+        let blocks = |indexed| {
+            [database::EventBlock {
+                event: "event",
+                block: database::Block {
+                    indexed,
+                    finalized: 0,
+                },
+            }]
+        };
+        let event_block = |sqlite: &Sqlite| {
+            sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap()
+                .indexed
+        };
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        sqlite.set_on_commit(Some(Box::new({
+            let seen = seen.clone();
+            move |blocks, logs| {
+                let logs = logs.iter().map(|log| log.block_number).collect::<Vec<_>>();
+                seen.lock().unwrap().push((blocks.len(), logs));
+            }
+        })));
+
+        let logs = (0..10).map(|i| log(i, "event"));
+        sqlite.update_iter(&blocks(9), logs, &[], &[]).unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 10);
+        assert_eq!(event_block(&sqlite), 9);
+        // Every chunk is reported once it is committed, the blocks with the
+        // last one.
+        assert_eq!(
+            std::mem::take(&mut *seen.lock().unwrap()),
+            [
+                (0, vec![0, 1, 2]),
+                (0, vec![3, 4, 5]),
+                (0, vec![6, 7, 8]),
+                (1, vec![9])
+            ]
+        );
+
+        // The chunks before the failing one stay stored but the indexed block
+        // doesn't advance. They are reported because they are committed.
+        let logs = (10..20).map(|i| log(i, if i == 17 { "unknown" } else { "event" }));
+        assert!(sqlite.update_iter(&blocks(19), logs, &[], &[]).is_err());
+        assert_eq!(count_rows(&sqlite, "event"), 16);
+        assert_eq!(event_block(&sqlite), 9);
+        assert_eq!(
+            std::mem::take(&mut *seen.lock().unwrap()),
+            [(0, vec![10, 11, 12]), (0, vec![13, 14, 15])]
+        );
+
+        // Resuming delivers the stored logs again, which are ignored.
+        let logs = (10..20).map(|i| log(i, "event"));
+        sqlite.update_iter(&blocks(19), logs, &[], &[]).unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 20);
+        assert_eq!(event_block(&sqlite), 19);
+
+        // Without ignoring or replacing them the resumed update would fail.
+        assert!(Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                max_batch_size: Some(3),
+                ..Default::default()
+            },
+        )
+        .is_err());
+
+        assert!(Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                max_batch_size: Some(0),
+                on_conflict: OnConflict::Ignore,
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn monotonic_blocks() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            monotonic_blocks: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let set = |sqlite: &mut Sqlite, indexed, finalized| {
+            let blocks = [database::EventBlock {
+                event: "event",
+                block: database::Block { indexed, finalized },
+            }];
+            sqlite.update_iter(&blocks, &[] as &[Log], &[], &[])
+        };
+        let get = |sqlite: &Sqlite| {
+            sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap()
+        };
+
+        set(&mut sqlite, 10, 5).unwrap();
+        set(&mut sqlite, 10, 5).unwrap();
+        assert!(set(&mut sqlite, 9, 5).is_err());
+        assert!(set(&mut sqlite, 11, 4).is_err());
+        assert_eq!(
+            get(&sqlite),
+            database::Block {
+                indexed: 10,
+                finalized: 5
+            }
+        );
+
+        // Reorgs still move the indexed block back.
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 8,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(get(&sqlite).indexed, 7);
+        set(&mut sqlite, 8, 5).unwrap();
+
+        // The pseudo-events follow the earliest event.
+        for event in ["blocks", "transactions"] {
+            for indexed in [10, 3] {
+                let blocks = [database::EventBlock {
+                    event,
+                    block: database::Block {
+                        indexed,
+                        finalized: 0,
+                    },
+                }];
+                sqlite
+                    .update_iter(&blocks, &[] as &[Log], &[], &[])
+                    .unwrap();
+            }
+        }
+
+        // Without the option blocks are set as they are.
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        set(&mut sqlite, 10, 5).unwrap();
+        set(&mut sqlite, 9, 4).unwrap();
+        assert_eq!(
+            get(&sqlite),
+            database::Block {
+                indexed: 9,
+                finalized: 4
+            }
+        );
+
+        // Unless they are set through the monotonic variant.
+        let block = |indexed, finalized| {
+            [database::EventBlock {
+                event: "event",
+                block: database::Block { indexed, finalized },
+            }]
+        };
+        let err = sqlite.set_event_blocks_monotonic(&block(8, 4)).unwrap_err();
+        assert!(format!("{err:#}").contains("would regress"), "{err:#}");
+        sqlite.set_event_blocks_monotonic(&block(12, 6)).unwrap();
+        assert_eq!(
+            get(&sqlite),
+            database::Block {
+                indexed: 12,
+                finalized: 6
+            }
+        );
+    }
+
+    #[test]
+    fn nullable_columns() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(address token, uint256 amount, (bool, string)[])",
+        )
+        .unwrap();
+        let err = sqlite
+            .prepare_event_with(
+                "event",
+                &event,
+                &EventOptions {
+                    nullable: &["amount"],
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert!(
+            format!("{err:?}").contains("no column \"amount\""),
+            "{err:?}"
+        );
+        sqlite
+            .prepare_event_with(
+                "event",
+                &event,
+                &EventOptions {
+                    nullable: &["amount_1", "field_1"],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        sqlite
+            .prepare_event_with(
+                "event",
+                &event,
+                &EventOptions {
+                    nullable: &["field_1", "amount_1"],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(sqlite
+            .prepare_event_with(
+                "event",
+                &event,
+                &EventOptions {
+                    nullable: &["amount_1"],
+                    ..Default::default()
+                },
+            )
+            .is_err());
+
+        // The first log doesn't have the nullable columns while the second one
+        // has a zero amount, which is stored like any other amount.
+        let nulls = [
+            vec!["amount_1".to_string(), "field_1".to_string()],
+            Vec::new(),
+        ];
+        let logs = [0u8, 7]
+            .into_iter()
+            .zip(nulls)
+            .map(|(i, nulls)| Log {
+                event: "event".into(),
+                log_index: i.into(),
+                fields: vec![
+                    AbiValue::Address(Address([1; 20])),
+                    AbiValue::Uint(Uint::new(256, 0u32.into()).unwrap()),
+                    AbiValue::Array(
+                        solabi::value::Array::new(
+                            AbiKind::Tuple(vec![AbiKind::Bool, AbiKind::String]),
+                            vec![
+                                AbiValue::Tuple(vec![
+                                    AbiValue::Bool(false),
+                                    AbiValue::String(String::new()),
+                                ]),
+                                AbiValue::Tuple(vec![
+                                    AbiValue::Bool(true),
+                                    AbiValue::String("x".repeat(i.into())),
+                                ]),
+                            ],
+                        )
+                        .unwrap(),
+                    ),
+                ],
+                nulls,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let not_nullable = Log {
+            nulls: vec!["token_0".to_string()],
+            ..logs[0].clone()
+        };
+        let err = sqlite
+            .update_iter(&[], std::slice::from_ref(&not_nullable), &[], &[])
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("isn't nullable"), "{err:?}");
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let count = |sql: &str| -> i64 {
+            sqlite
+                .connection
+                .query_row(sql, (), |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event WHERE amount_1 IS NULL;"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event WHERE amount_1 IS NOT NULL;"),
+            1
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event WHERE token_0 IS NULL;"),
+            0
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event_array_0 WHERE field_1 IS NULL;"),
+            2
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event_array_0 WHERE field_1 IS NOT NULL;"),
+            2
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM event_array_0 WHERE field_0 IS NULL;"),
+            0
+        );
+
+        let read = sqlite.read_logs("event", 0, 0).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+            assert_eq!(read.nulls, log.nulls);
+        }
+    }
+
+    #[test]
+    fn describe() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Transfer(address indexed from, uint8[] amounts, string[] notes)",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("transfer", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "transfer".into(),
+            fields: vec![
+                AbiValue::Address(Address([1; 20])),
+                AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap()); 2],
+                    )
+                    .unwrap(),
+                ),
+                AbiValue::Array(solabi::value::Array::new(AbiKind::String, vec![]).unwrap()),
+            ],
+            ..Default::default()
+        };
+        sqlite.update_iter(&[], [log], &[], &[]).unwrap();
+
+        let description = sqlite.describe("transfer").unwrap();
+        for line in [
+            "event transfer: Transfer(address indexed from, uint8[] amounts, string[] notes)",
+            "table transfer (1 rows)",
+            "  from_0 BLOB NOT NULL",
+            "  PRIMARY KEY(block_number, log_index)",
+            "table transfer_amounts_0 (2 rows)",
+            "  array_index INTEGER NOT NULL",
+            "  PRIMARY KEY(block_number, log_index, array_index)",
+            "table transfer_notes_1 (0 rows)",
+        ] {
+            assert!(
+                description.lines().any(|l| l == line),
+                "{line:?} missing from\n{description}"
+            );
+        }
+        assert!(sqlite.describe("unknown").is_err());
+    }
+
+    #[test]
+    fn owned_log() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log: Log<'static> = Log {
+            event: Cow::Owned(["ev", "ent"].concat()),
+            block_number: 1,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        };
+        sqlite
+            .update(&[], std::slice::from_ref(&log), &[], &[])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 1);
+        assert!(matches!(read[0].event, Cow::Borrowed("event")));
+        assert_eq!(read[0].fields, log.fields);
+    }
+
+    #[test]
+    fn replay() {
+        let event =
+            EventDescriptor::parse_declaration("event Event(address indexed, string[])").unwrap();
+        let mut source = Sqlite::in_memory(Options {
+            raw_logs: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut target = Sqlite::new_for_test();
+        for sqlite in [&mut source, &mut target] {
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let logs = (0..3)
+            .map(|i| Log {
+                event: "event".into(),
+                block_number: i,
+                log_index: 2 * i,
+                transaction_index: i + 1,
+                address: Address([i as u8; 20]),
+                fields: vec![
+                    AbiValue::Address(Address([0xaa; 20])),
+                    AbiValue::Array(
+                        Array::new(
+                            AbiKind::String,
+                            vec![AbiValue::String(i.to_string()); i as usize],
+                        )
+                        .unwrap(),
+                    ),
+                ],
+                data: vec![i as u8; 32],
+                topics: vec![[i as u8; 32]],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        source.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let replayed = {
+            // The name doesn't need to outlive the logs.
+            let name = String::from("event");
+            source.replay(&name, 0, 10).unwrap()
+        };
+        assert_eq!(replayed.len(), logs.len());
+        assert_eq!(replayed[1].data, logs[1].data);
+        target.update_iter(&[], &replayed, &[], &[]).unwrap();
+        let read = target.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), logs.len());
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(
+                (read.block_number, read.log_index, read.transaction_index),
+                (log.block_number, log.log_index, log.transaction_index)
+            );
+            assert_eq!(read.address, log.address);
+            assert_eq!(read.fields, log.fields);
+        }
+    }
+
+    #[test]
+    fn max_lengths() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event =
+            EventDescriptor::parse_declaration("event Event(string name, bytes data)").unwrap();
+        let unknown = EventOptions {
+            max_lengths: &[("unknown", 4)],
+            ..Default::default()
+        };
+        assert!(sqlite
+            .prepare_event_with("event", &event, &unknown)
+            .is_err());
+        let options = EventOptions {
+            max_lengths: &[("name_0", 4), ("data_1", 2)],
+            ..Default::default()
+        };
+        sqlite
+            .prepare_event_with("event", &event, &options)
+            .unwrap();
+        let log = |name: &str, data: Vec<u8>| Log {
+            event: "event".into(),
+            fields: vec![AbiValue::String(name.to_string()), AbiValue::Bytes(data)],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], &[log("abcd", vec![1, 2])], &[], &[])
+            .unwrap();
+        for log in [log("abcde", Vec::new()), log("", vec![1, 2, 3])] {
+            let err = sqlite.update_iter(&[], &[log], &[], &[]).unwrap_err();
+            assert!(format!("{err:?}").contains("CHECK"), "{err:?}");
+        }
+        assert_eq!(sqlite.read_logs("event", 0, 10).unwrap().len(), 1);
+
+        let event = EventDescriptor::parse_declaration("event Other(uint8 value)").unwrap();
+        let options = EventOptions {
+            max_lengths: &[("value_0", 4)],
+            ..Default::default()
+        };
+        assert!(sqlite
+            .prepare_event_with("other", &event, &options)
+            .is_err());
+    }
+
+    #[test]
+    fn single_contract_address() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        let contract = Address([0xc0; 20]);
+        let options = EventOptions {
+            address: Some(contract),
+            ..Default::default()
+        };
+        sqlite
+            .prepare_event_with("event", &event, &options)
+            .unwrap();
+        sqlite
+            .prepare_event_with("event", &event, &options)
+            .unwrap();
+        assert!(sqlite
+            .prepare_event_with("event", &event, &Default::default())
+            .is_err());
+
+        // The column isn't stored.
+        for table in ["event", "event_array_0"] {
+            let stored: Vec<String> = sqlite
+                .connection
+                .prepare("SELECT name FROM pragma_table_info(?1);")
+                .unwrap()
+                .query_map((table,), |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert!(!stored.iter().any(|name| name == "address"), "{stored:?}");
+        }
+
+        let log = |address| Log {
+            event: "event".into(),
+            address,
+            fields: vec![AbiValue::Array(
+                solabi::value::Array::new(
+                    AbiKind::Uint(BitWidth::MIN),
+                    vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                )
+                .unwrap(),
+            )],
+            ..Default::default()
+        };
+        sqlite.update_iter(&[], [log(contract)], &[], &[]).unwrap();
+        assert!(sqlite
+            .update_iter(&[], [log(Address([1; 20]))], &[], &[])
+            .is_err());
+
+        let read = sqlite.read_logs("event", 0, 0).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].address, contract);
+        assert_eq!(read[0].fields, log(contract).fields);
+        let rows = sqlite
+            .query(
+                "event",
+                QueryFilter {
+                    address: Some(contract),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+
+        // A table with a stored address column can't become single contract.
+        sqlite
+            .prepare_event("other", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        assert!(sqlite
+            .prepare_event_with("other", &event, &options)
+            .is_err());
+    }
+
+    #[test]
+    fn warm_cache() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            chain_id: true,
+            transaction_index_key: true,
+            ..Default::default()
+        })
+        .unwrap();
+        sqlite.warm_cache().unwrap();
+        for (name, declaration) in [
+            ("a", "event Event(uint256)"),
+            ("b", "event Event(uint8[], (bool, string)[])"),
+            ("c", "event Event()"),
+        ] {
+            let event = EventDescriptor::parse_declaration(declaration).unwrap();
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        sqlite.warm_cache().unwrap();
+
+        let log = Log {
+            event: "c".into(),
+            block_number: 1,
+            chain_id: Some(1),
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        for name in ["a", "b"] {
+            assert!(sqlite.read_logs(name, 0, 1).unwrap().is_empty());
+        }
+        assert_eq!(sqlite.read_logs("c", 0, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn too_many_columns() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event((uint8, bool)[1000])").unwrap();
+        let err = sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            format!("{err:?}").contains("table event of event event would have 2004 columns"),
+            "{err:?}"
+        );
+        assert!(!sqlite.contains_event("event"));
+
+        let mut sqlite = Sqlite::in_memory(Options {
+            max_columns: Some(8),
+            ..Default::default()
+        })
+        .unwrap();
+        // 4 fixed columns, the array index and 3 fields.
+        let event =
+            EventDescriptor::parse_declaration("event Event(uint8, (bool, bool, bool)[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint8, uint8[4][])").unwrap();
+        let err = sqlite
+            .prepare_event("wide", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            format!("{err:?}").contains("would have 9 columns but at most 8"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn foreign_keys() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            foreign_keys: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..2u8)
+            .map(|block| Log {
+                event: "event".into(),
+                block_number: block.into(),
+                fields: vec![AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        vec![AbiValue::Uint(Uint::new(8, block.into()).unwrap()); 2],
+                    )
+                    .unwrap(),
+                )],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let count = |sqlite: &Sqlite| -> i64 {
+            sqlite
+                .connection
+                .query_row("SELECT COUNT(*) FROM event_array_0;", (), |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count(&sqlite), 4);
+        sqlite
+            .connection
+            .execute("DELETE FROM event WHERE block_number = 1;", ())
+            .unwrap();
+        assert_eq!(count(&sqlite), 2);
+        // Array rows need their log.
+        let insert = |block: i64| {
+            sqlite.connection.execute(
+                "INSERT INTO event_array_0 VALUES (?1, 0, 0, zeroblob(20), 5, x'00');",
+                (block,),
+            )
+        };
+        assert!(insert(1).is_err());
+        insert(0).unwrap();
+
+        // Removing still counts the array rows.
+        assert_eq!(sqlite.remove_range("event", 0, 0).unwrap(), 4);
+        assert_eq!(count(&sqlite), 0);
+    }
+
+    #[test]
+    fn raw_connection() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..3).map(|log_index| Log {
+            event: "event".into(),
+            log_index,
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], logs, &[], &[]).unwrap();
+
+        let count: i64 = sqlite
+            .raw_connection()
+            .query_row("SELECT COUNT(*) FROM event;", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        let count: i64 = sqlite
+            .with_connection(|connection| {
+                connection.query_row(
+                    "SELECT COUNT(*) FROM event WHERE log_index > 0;",
+                    (),
+                    |row| row.get(0),
+                )
+            })
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn remove_all_from() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        for name in ["a", "b"] {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let logs = ["a", "b"]
+            .into_iter()
+            .flat_map(|event| {
+                (0..4).map(move |block_number| Log {
+                    event: event.into(),
+                    block_number,
+                    fields: vec![AbiValue::Array(
+                        solabi::value::Array::new(
+                            AbiKind::Uint(BitWidth::MIN),
+                            vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                        )
+                        .unwrap(),
+                    )],
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let blocks = ["a", "b"].map(|event| database::EventBlock {
+            event,
+            block: database::Block {
+                indexed: 3,
+                finalized: 0,
+            },
+        });
+        sqlite.update_iter(&blocks, &logs, &[], &[]).unwrap();
+
+        assert!(sqlite.remove_all_from(0).is_err());
+        sqlite.remove_all_from(2).unwrap();
+        for name in ["a", "b"] {
+            let read = sqlite.read_logs(name, 0, 10).unwrap();
+            assert_eq!(
+                read.iter().map(|log| log.block_number).collect::<Vec<_>>(),
+                [0, 1]
+            );
+            assert_eq!(
+                sqlite
+                    .inner
+                    .event_block(&sqlite.connection, name)
+                    .unwrap()
+                    .indexed,
+                1
+            );
+            let array_rows: i64 = sqlite
+                .connection
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {name}_array_0;"),
+                    (),
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(array_rows, 2);
+        }
+    }
+
+    #[test]
+    fn remove_from_genesis() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..3)
+            .map(|block_number| Log {
+                event: "event".into(),
+                block_number,
+                fields: vec![AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                    )
+                    .unwrap(),
+                )],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 2,
+                finalized: 1,
+            },
+        }];
+        let block_times = (0..3)
+            .map(|number| BlockTime {
+                number,
+                timestamp: UNIX_EPOCH,
+            })
+            .collect::<Vec<_>>();
+        let transactions = (0..3)
+            .map(|block_number| database::Transaction {
+                block_number,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite
+            .update_iter(&blocks, &logs, &block_times, &transactions)
+            .unwrap();
+
+        // A reorg can't uncle block 0.
+        assert!(sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 0,
+            }])
+            .now_or_never()
+            .unwrap()
+            .is_err());
+
+        sqlite.remove_from_genesis().unwrap();
+        for table in ["event", "event_array_0", "blocks", "transactions"] {
+            let count: i64 = sqlite
+                .connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table};"), (), |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(count, 0, "{table}");
+        }
+        assert_eq!(
+            sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap(),
+            database::Block::default()
+        );
+    }
+
+    #[test]
+    fn packed_key() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            packed_key: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number, log_index| Log {
+            event: "event".into(),
+            block_number,
+            log_index,
+            fields: vec![AbiValue::Array(
+                solabi::value::Array::new(
+                    AbiKind::Uint(BitWidth::MIN),
+                    vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                )
+                .unwrap(),
+            )],
+            ..Default::default()
+        };
+        let logs = [log(2, 0), log(1, 7), log(1, 1), log(3, 4)];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        // The rowid orders like the composite key.
+        let keys: Vec<(i64, i64, i64)> = sqlite
+            .connection
+            .prepare("SELECT rowid, block_number, log_index FROM event ORDER BY rowid;")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            keys,
+            [
+                (1 << 32 | 1, 1, 1),
+                (1 << 32 | 7, 1, 7),
+                (2 << 32, 2, 0),
+                (3 << 32 | 4, 3, 4)
+            ]
+        );
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        let read: Vec<_> = read
+            .iter()
+            .map(|log| (log.block_number, log.log_index))
+            .collect();
+        assert_eq!(read, [(1, 1), (1, 7), (2, 0), (3, 4)]);
+
+        // The key is still unique.
+        assert!(sqlite.update_iter(&[], &[log(1, 7)], &[], &[]).is_err());
+        assert!(sqlite
+            .update_iter(&[], &[log(1, 1 << 32)], &[], &[])
+            .is_err());
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 2,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 2);
+
+        // Keys that don't fit fall back to the composite key.
+        let mut sqlite = Sqlite::in_memory(Options {
+            packed_key: true,
+            chain_id: true,
+            ..Default::default()
+        })
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let composite_key: bool = sqlite
+            .connection
+            .query_row(
+                "SELECT pk > 0 FROM pragma_table_info('event') WHERE name = 'block_number';",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(composite_key);
+    }
+
+    #[test]
+    fn signature_hash_drift() {
+        let hash = |declaration: &str| {
+            let event = EventDescriptor::parse_declaration(declaration).unwrap();
+            signature_hash(&event_signature(&event))
+        };
+        let event = "event Event(uint256 indexed a, (bool b, uint8[] c)[2] d)";
+        assert_eq!(
+            event_signature(&EventDescriptor::parse_declaration(event).unwrap()),
+            "event Event(uint256 indexed a,(bool b,uint8[] c)[2] d)"
+        );
+        assert_eq!(
+            hash(event),
+            hash("event  Event( uint indexed a , ( bool b , uint8[] c )[2] d )")
+        );
+        for changed in [
+            "event Event(uint256 a, (bool b, uint8[] c)[2] d)",
+            "event Event(uint256 indexed x, (bool b, uint8[] c)[2] d)",
+            "event Event(uint256 indexed a, (bool c, uint8[] b)[2] d)",
+            "event Event(uint256 indexed a, (bool b, uint8[] c)[3] d)",
+            "event Other(uint256 indexed a, (bool b, uint8[] c)[2] d)",
+        ] {
+            assert_ne!(hash(event), hash(changed), "{changed}");
+        }
+
+        // The stored hash catches changes across restarts.
+        let mut sqlite = Sqlite::new_for_test();
+        let prepare = |sqlite: &mut Sqlite, declaration: &str| {
+            let event = EventDescriptor::parse_declaration(declaration).unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+        };
+        prepare(&mut sqlite, "event Event(uint256 indexed a)").unwrap();
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        let err = prepare(&mut sqlite, "event Event(uint256 a)").unwrap_err();
+        assert!(
+            format!("{err:?}").contains(
+                "created with signature event Event(uint256 indexed a) instead of event \
+                 Event(uint256 a)"
+            ),
+            "{err:?}"
+        );
+        prepare(&mut sqlite, "event Event(uint indexed a)").unwrap();
+    }
+
+    #[test]
+    fn raw_logs() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            raw_logs: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(bool indexed, uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |log_index, topics: Vec<[u8; 32]>| Log {
+            event: "event".into(),
+            block_number: 1,
+            log_index,
+            fields: vec![
+                AbiValue::Bool(true),
+                AbiValue::Array(
+                    Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                    )
+                    .unwrap(),
+                ),
+            ],
+            data: vec![0xaa; 96],
+            topics,
+            ..Default::default()
+        };
+        let logs = [log(0, vec![[1; 32], [2; 32]]), log(1, Vec::new())];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+            assert_eq!(read.data, log.data);
+            assert_eq!(read.topics, log.topics);
+        }
+        let topic1: Option<Vec<u8>> = sqlite
+            .connection
+            .query_row("SELECT topic1 FROM event WHERE log_index = 0;", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(topic1, Some(vec![2; 32]));
+
+        assert!(sqlite
+            .update_iter(&[], &[log(2, vec![[0; 32]; 5])], &[], &[])
+            .is_err());
+
+        // Off by default.
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert!(read[0].data.is_empty() && read[0].topics.is_empty());
+    }
+
+    #[test]
+    fn coalesce_arrays() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            coalesce_arrays: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(bool, uint8[], string[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number, uints: Vec<u8>, strings: Vec<&str>| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![
+                AbiValue::Bool(true),
+                AbiValue::Array(
+                    Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        uints
+                            .into_iter()
+                            .map(|uint| AbiValue::Uint(Uint::new(8, uint.into()).unwrap()))
+                            .collect(),
+                    )
+                    .unwrap(),
+                ),
+                AbiValue::Array(
+                    Array::new(
+                        AbiKind::String,
+                        strings
+                            .into_iter()
+                            .map(|string| AbiValue::String(string.to_string()))
+                            .collect(),
+                    )
+                    .unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let logs = [
+            log(1, vec![1, 2], vec!["a"]),
+            log(2, Vec::new(), vec!["b", "c"]),
+        ];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let tables: Vec<String> = sqlite
+            .connection
+            .prepare("SELECT name FROM sqlite_master WHERE name LIKE 'event%' ORDER BY name;")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(tables, ["event", "event_arrays"]);
+        let rows: i64 = sqlite
+            .connection
+            .query_row("SELECT COUNT(*) FROM event_arrays;", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 5);
+
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+        }
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 2,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].fields, logs[0].fields);
+        let rows: i64 = sqlite
+            .connection
+            .query_row("SELECT COUNT(*) FROM event_arrays;", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 3);
+    }
+
+    #[test]
+    fn partition_size() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            partition_size: Some(10),
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number: u64| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![AbiValue::Array(
+                Array::new(
+                    AbiKind::Uint(BitWidth::MIN),
+                    vec![AbiValue::Uint(Uint::new(8, block_number.into()).unwrap()); 2],
+                )
+                .unwrap(),
+            )],
+            ..Default::default()
+        };
+        let logs = [1, 5, 12, 25].map(log);
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let names = |sqlite: &Sqlite, type_: &str| -> Vec<String> {
+            sqlite
+                .connection
+                .prepare(
+                    "SELECT name FROM sqlite_schema WHERE type = ?1 AND name LIKE 'event%' ORDER \
+                     BY name;",
+                )
+                .unwrap()
+                .query_map((type_,), |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(names(&sqlite, "view"), ["event", "event_array_0"]);
+        assert_eq!(
+            names(&sqlite, "table"),
+            [
+                "event_array_0_p0",
+                "event_array_0_p1",
+                "event_array_0_p2",
+                "event_p0",
+                "event_p1",
+                "event_p2"
+            ]
+        );
+        let count = |sqlite: &Sqlite, table: &str| -> i64 {
+            sqlite
+                .connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table};"), (), |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        assert_eq!(count(&sqlite, "event_p0"), 2);
+        assert_eq!(count(&sqlite, "event_p1"), 1);
+        assert_eq!(count(&sqlite, "event_array_0_p2"), 2);
+
+        let read = sqlite.read_logs("event", 0, 100).unwrap();
+        assert_eq!(read.len(), logs.len());
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.block_number, log.block_number);
+            assert_eq!(read.fields, log.fields);
+        }
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 5,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(count(&sqlite, "event_p0"), 1);
+        assert_eq!(count(&sqlite, "event_p1"), 0);
+        assert_eq!(count(&sqlite, "event_array_0"), 2);
+        assert_eq!(sqlite.read_logs("event", 0, 100).unwrap().len(), 1);
+
+        assert!(Sqlite::in_memory(Options {
+            partition_size: Some(10),
+            foreign_keys: true,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn partial_tables() {
+        let event = EventDescriptor::parse_declaration("event Event(bool, uint8[])").unwrap();
+        let prepare = |sqlite: &mut Sqlite| {
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+        };
+
+        // Only the primary table was created.
+        let mut sqlite = Sqlite::new_for_test();
+        prepare(&mut sqlite).unwrap();
+        sqlite
+            .connection
+            .execute("DROP TABLE event_array_0;", ())
+            .unwrap();
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        let err = prepare(&mut sqlite).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("partial set of tables: missing [\"event_array_0\"]"),
+            "{err:?}"
+        );
+
+        // The primary table has different columns.
+        let sqlite = Sqlite::new_for_test();
+        sqlite
+            .connection
+            .execute(
+                "CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT \
+                 NULL, transaction_index INTEGER NOT NULL, address BLOB NOT NULL, PRIMARY \
+                 KEY(block_number, log_index)) STRICT;",
+                (),
+            )
+            .unwrap();
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        let err = sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            format!("{err:?}")
+                .contains("table event exists with 4 columns but event event needs 5"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn type_mapper() {
+        /// Stores addresses as `0x` prefixed hex text.
+        #[derive(Debug)]
+        struct HexAddresses;
+
+        impl TypeMapper for HexAddresses {
+            fn column_type(&self, kind: &AbiKind) -> Option<SqlType> {
+                (kind == &AbiKind::Address).then_some(SqlType::Text)
+            }
+
+            fn encode<'a>(&self, value: &'a AbiValue) -> Option<ToSqlOutput<'a>> {
+                let AbiValue::Address(address) = value else {
+                    return None;
+                };
+                let hex = address
+                    .0
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>();
+                Some(ToSqlOutput::Owned(SqlValue::Text(format!("0x{hex}"))))
+            }
+
+            fn decode(&self, _: &AbiKind, value: SqlValueRef) -> Result<AbiValue> {
+                let hex = value.as_str()?.strip_prefix("0x").context("missing 0x")?;
+                let bytes = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(AbiValue::Address(Address(
+                    bytes.try_into().ok().context("not 20 bytes")?,
+                )))
+            }
+        }
+
+        let mut sqlite = Sqlite::in_memory(Options {
+            type_mapper: Some(Arc::new(HexAddresses)),
+            ..Default::default()
+        })
+        .unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(address a, address[] b, bool c)")
+                .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let type_of = |table: &str, column: &str| -> String {
+            sqlite
+                .connection
+                .query_row(
+                    "SELECT type FROM pragma_table_info(?1) WHERE name = ?2;",
+                    (table, column),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(type_of("event", "a_0"), "TEXT");
+        assert_eq!(type_of("event_b_0", "b_0"), "TEXT");
+        assert_eq!(type_of("event", "c_1"), "INTEGER");
+        // The log's own address isn't a field.
+        assert_eq!(type_of("event", "address"), "BLOB");
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            address: Address([1; 20]),
+            fields: vec![
+                AbiValue::Address(Address([0xab; 20])),
+                AbiValue::Array(
+                    Array::new(AbiKind::Address, vec![AbiValue::Address(Address([2; 20]))])
+                        .unwrap(),
+                ),
+                AbiValue::Bool(true),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        let stored: String = sqlite
+            .connection
+            .query_row("SELECT a_0 FROM event;", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, format!("0x{}", "ab".repeat(20)));
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read[0].address, log.address);
+        assert_eq!(read[0].fields, log.fields);
+    }
+
+    #[test]
+    fn read_page() {
+        use std::sync::atomic::{self, AtomicUsize};
+
+        static ARRAY_READS: AtomicUsize = AtomicUsize::new(0);
+        fn trace(statement: &str) {
+            if statement.starts_with("SELECT * FROM event_") {
+                ARRAY_READS.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+        }
+
+        let mut sqlite = Sqlite::new_for_test();
+        let event =
+            EventDescriptor::parse_declaration("event Event(uint8[], bool, string[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number, n: u8| Log {
+            event: "event".into(),
+            block_number,
+            fields: vec![
+                AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::Uint(BitWidth::MIN),
+                        (0..n)
+                            .map(|i| AbiValue::Uint(Uint::new(8, i.into()).unwrap()))
+                            .collect(),
+                    )
+                    .unwrap(),
+                ),
+                AbiValue::Bool(n > 1),
+                AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::String,
+                        (0..n).map(|i| AbiValue::String(i.to_string())).collect(),
+                    )
+                    .unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let logs = (1..=6).map(|i| log(i, i as u8 % 3)).collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        sqlite.connection.trace(Some(trace));
+        let filter = QueryFilter {
+            from_block: Some(2),
+            order: Order::Desc,
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = sqlite.read_page("event", &filter).unwrap();
+        sqlite.connection.trace(None);
+        assert_eq!(
+            page.iter().map(|log| log.block_number).collect::<Vec<_>>(),
+            [6, 5, 4]
+        );
+        for log in &page {
+            assert_eq!(log.fields, logs[log.block_number as usize - 1].fields);
+        }
+        // One query per array table for the whole page.
+        assert_eq!(ARRAY_READS.load(atomic::Ordering::SeqCst), 2);
+
+        let filter = QueryFilter {
+            from_block: Some(10),
+            ..Default::default()
+        };
+        assert!(sqlite.read_page("event", &filter).unwrap().is_empty());
+    }
+
+    #[test]
+    fn interleaved_indexed_fields() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Transfer(address indexed from, uint8 value, address indexed to, bool flag)",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::Address(Address([1; 20])),
+                AbiValue::Uint(Uint::new(8, 3u8.into()).unwrap()),
+                AbiValue::Address(Address([2; 20])),
+                AbiValue::Bool(true),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+
+        let row: (Vec<u8>, Vec<u8>, Vec<u8>, bool) = sqlite
+            .connection
+            .query_row(
+                "SELECT from_0, value_1, to_2, flag_3 FROM event;",
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(row.0, [1; 20]);
+        assert_eq!(row.1[31], 3);
+        assert_eq!(row.2, [2; 20]);
+        assert!(row.3);
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read[0].fields, log.fields);
+    }
+
+    #[test]
+    fn read_events_named() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(address from, uint8 select, bool, (bool a, bool b) pair)",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::Address(Address([1; 20])),
+                AbiValue::Uint(Uint::new(8, 2u8.into()).unwrap()),
+                AbiValue::Bool(true),
+                AbiValue::Tuple(vec![AbiValue::Bool(false), AbiValue::Bool(true)]),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+
+        let events = sqlite.read_events_named("event", 0, 10).unwrap();
+        assert_eq!(events.len(), 1);
+        let names = events[0]
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["from_", "select_", "field_2", "pair"]);
+        let values = events[0]
+            .iter()
+            .map(|(_, value)| value.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(values, log.fields);
+    }
+
+    #[test]
+    fn read_one() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8, string[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = |block_number, log_index, value: u8, strings: &[&str]| Log {
+            event: "event".into(),
+            block_number,
+            log_index,
+            fields: vec![
+                AbiValue::Uint(Uint::new(8, value.into()).unwrap()),
+                AbiValue::Array(
+                    solabi::value::Array::new(
+                        AbiKind::String,
+                        strings
+                            .iter()
+                            .map(|s| AbiValue::String(s.to_string()))
+                            .collect(),
+                    )
+                    .unwrap(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let logs = [log(1, 0, 1, &["a", "b"]), log(1, 1, 2, &[])];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        for expected in &logs {
+            let (log, fields) = sqlite
+                .read_one("event", expected.block_number, expected.log_index)
+                .unwrap()
+                .unwrap();
+            assert_eq!(log.block_number, expected.block_number);
+            assert_eq!(log.log_index, expected.log_index);
+            assert_eq!(fields, expected.fields);
+        }
+        assert!(sqlite.read_one("event", 1, 2).unwrap().is_none());
+        assert!(sqlite.read_one("other", 1, 0).is_err());
+    }
+
+    #[test]
+    fn reindex() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint8[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 3,
+            fields: vec![AbiValue::Array(
+                solabi::value::Array::new(
+                    AbiKind::Uint(BitWidth::MIN),
+                    vec![AbiValue::Uint(Uint::new(8, 1u8.into()).unwrap())],
+                )
+                .unwrap(),
+            )],
+            ..Default::default()
+        };
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 5,
+                finalized: 4,
+            },
+        }];
+        sqlite
+            .update_iter(&blocks, std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+
+        sqlite.reindex("event").unwrap();
+        for table in ["event", "event_array_0"] {
+            let count: i64 = sqlite
+                .connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table};"), (), |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(count, 0);
+        }
+        assert_eq!(
+            sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap(),
+            database::Block {
+                indexed: 0,
+                finalized: 0
+            }
+        );
+        assert!(sqlite.contains_event("event"));
+        sqlite
+            .update_iter(&blocks, std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(sqlite.read_logs("event", 0, 5).unwrap().len(), 1);
+        assert!(sqlite.reindex("unknown").is_err());
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            table_prefix: "arak_".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let file: String = sqlite
+            .connection
+            .query_row("SELECT file FROM pragma_database_list;", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(file, "");
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(sqlite.contains_event("event"));
+    }
+
+    #[test]
+    fn tune() {
+        let sqlite = Sqlite::new_for_test();
+        let pragma = |name| -> i64 {
+            sqlite
+                .connection
+                .pragma_query_value(None, name, |row| row.get(0))
+                .unwrap()
+        };
+        sqlite.tune(Profile::Backfill).unwrap();
+        assert_eq!(pragma("synchronous"), 0);
+        assert_eq!(pragma("cache_size"), -256 * 1024);
+        assert_eq!(pragma("temp_store"), 2);
+        sqlite.tune(Profile::Durable).unwrap();
+        assert_eq!(pragma("synchronous"), 2);
+        assert_eq!(pragma("cache_size"), -2000);
+        assert_eq!(pragma("temp_store"), 0);
+    }
+
+    #[test]
+    fn table_name_collision() {
+        let mut sqlite = Sqlite::new_for_test();
+        let array = EventDescriptor::parse_declaration("event Event(uint256[])").unwrap();
+        let plain = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        sqlite
+            .prepare_event("foo", &array)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let err = sqlite
+            .prepare_event("foo_array_0", &plain)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            format!("{err:?}")
+                .contains("table foo_array_0 of event foo_array_0 already belongs to event foo"),
+            "{err:?}"
+        );
+        assert!(!sqlite.inner.events.contains_key("foo_array_0"));
+
+        // The other way around the array table collides.
+        sqlite
+            .prepare_event("bar_array_0", &plain)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(sqlite
+            .prepare_event("bar", &array)
+            .now_or_never()
+            .unwrap()
+            .is_err());
+
+        // Preparing again and distinct names are fine.
+        for name in ["foo", "bar_array_0", "foo_array"] {
+            let event = if name == "bar_array_0" {
+                &plain
+            } else {
+                &array
+            };
+            sqlite
+                .prepare_event(name, event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn contains_event() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(sqlite.contains_event("event"));
+        assert!(!sqlite.contains_event("other"));
+        // Sanitizes to "event" but can't be used to store logs.
+        assert!(!sqlite.contains_event("event;"));
+        assert!(!sqlite.contains_event("ev-ent"));
+
+        // The trait method agrees.
+        assert!(Database::contains_event(&mut sqlite, "event")
+            .now_or_never()
+            .unwrap());
+        assert!(!Database::contains_event(&mut sqlite, "event;")
+            .now_or_never()
+            .unwrap());
+    }
+
+    #[test]
+    fn on_commit() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        sqlite.set_on_commit(Some(Box::new({
+            let seen = seen.clone();
+            move |blocks, logs| {
+                let logs = logs.iter().map(|log| log.log_index).collect::<Vec<_>>();
+                seen.lock().unwrap().push((blocks.len(), logs));
+            }
+        })));
+        let log = |event: &'static str, log_index| Log {
+            event: event.into(),
+            log_index,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        };
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 1,
+                finalized: 0,
+            },
+        }];
+
+        let logs = [log("event", 0), log("event", 1)];
+        sqlite
+            .update(&blocks, &logs, &[], &[])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), [(1, vec![0, 1])]);
+
+        // Rolled back updates aren't reported.
+        let logs = [log("event", 2), log("unknown", 3)];
+        assert!(sqlite
+            .update(&[], &logs, &[], &[])
+            .now_or_never()
+            .unwrap()
+            .is_err());
+        assert_eq!(seen.lock().unwrap().len(), 1);
+
+        // Updates through `update_iter` are reported as well.
+        sqlite
+            .update_iter(&blocks, [log("event", 5)], &[], &[])
+            .unwrap();
+        assert_eq!(seen.lock().unwrap()[1], (1, vec![5]));
+
+        // A panicking callback doesn't fail the committed update.
+        sqlite.set_on_commit(Some(Box::new(|_, _| panic!("callback"))));
+        let logs = [log("event", 4)];
+        sqlite
+            .update(&[], &logs, &[], &[])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 4);
+    }
+
+    #[test]
+    fn remove_range() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool[])").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..5)
+            .map(|block_number| Log {
+                event: "event".into(),
+                block_number,
+                fields: vec![AbiValue::Array(
+                    Array::new(AbiKind::Bool, vec![AbiValue::Bool(true)]).unwrap(),
+                )],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let blocks = [database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 4,
+                finalized: 0,
+            },
+        }];
+        sqlite.update_iter(&blocks, &logs, &[], &[]).unwrap();
+
+        assert_eq!(sqlite.remove_range("event", 1, 2).unwrap(), 4);
+        let blocks = sqlite
+            .read_logs("event", 0, 4)
+            .unwrap()
+            .iter()
+            .map(|log| log.block_number)
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [0, 3, 4]);
+        assert_eq!(count_rows(&sqlite, "event_array_0"), 3);
+        assert_eq!(
+            sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap()
+                .indexed,
+            4
+        );
+        assert!(sqlite.remove_range("unknown", 0, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn table_prefix() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                table_prefix: "arak_".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool, bool[])").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::Bool(true),
+                AbiValue::Array(Array::from_values(vec![AbiValue::Bool(false)]).unwrap()),
+            ],
+            ..Default::default()
+        };
+        let blocks = database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 1,
+                finalized: 0,
+            },
+        };
+        let block_time = BlockTime {
+            number: 1,
+            timestamp: SystemTime::UNIX_EPOCH,
+        };
+        sqlite
+            .update(&[blocks], &[log], &[block_time], &[])
+            .await
+            .unwrap();
+        assert_eq!(count_rows(&sqlite, "arak_event"), 1);
+        assert_eq!(count_rows(&sqlite, "arak_event_array_0"), 1);
+        assert_eq!(count_rows(&sqlite, "arak_blocks"), 1);
+        assert_eq!(sqlite.event_block("event").await.unwrap().indexed, 1);
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 1,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(count_rows(&sqlite, "arak_event"), 0);
+        assert_eq!(count_rows(&sqlite, "arak_event_array_0"), 0);
+        assert_eq!(count_rows(&sqlite, "arak_blocks"), 0);
+
+        let mut statement = sqlite
+            .connection
+            .prepare("SELECT name FROM sqlite_schema WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let tables: Vec<String> = statement
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            tables,
+            [
+                "arak__column_metadata",
+                "arak__event_block",
+                "arak__event_naming",
+                "arak__event_signature",
+                "arak__event_tables",
+                "arak__reorg_log",
+                "arak_blocks",
+                "arak_event",
+                "arak_event_array_0",
+                "arak_transactions"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn chain_id() {
+        let mut sqlite = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                chain_id: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool[])").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let log = |chain_id: Option<u64>| Log {
+            event: "event".into(),
+            block_number: 1,
+            log_index: 2,
+            fields: vec![AbiValue::Array(
+                Array::from_values(vec![AbiValue::Bool(true)]).unwrap(),
+            )],
+            chain_id,
+            ..Default::default()
+        };
+        sqlite
+            .update(&[], &[log(Some(1)), log(Some(100))], &[], &[])
+            .await
+            .unwrap();
+        assert_eq!(count_rows(&sqlite, "event"), 2);
+        assert_eq!(count_rows(&sqlite, "event_array_0"), 2);
+        let chain_ids: Vec<i64> = sqlite
+            .connection
+            .prepare("SELECT chain_id FROM event ORDER BY chain_id")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chain_ids, [1, 100]);
+
+        // Still unique per chain.
+        assert!(sqlite.update(&[], &[log(Some(1))], &[], &[]).await.is_err());
+        assert!(sqlite.update(&[], &[log(None)], &[], &[]).await.is_err());
+    }
+
+    #[test]
+    fn invalid_table_prefix() {
+        let result = Sqlite::with_options(
+            Connection::open_in_memory().unwrap(),
+            Options {
+                table_prefix: "x; DROP TABLE y; --".to_string(),
+                ..Default::default()
+            },
+        );
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn internal_type() {
+        let mut sqlite = Sqlite::new_for_test();
+        let mut event =
+            EventDescriptor::parse_declaration("event Event(address token, uint256 amount)")
+                .unwrap();
+        event.inputs[0].field.internal_type = Some("contract IERC20".to_string());
+        sqlite.prepare_event("event", &event).await.unwrap();
+
+        let type_: String = sqlite
+            .connection
+            .query_row(
+                "SELECT type FROM pragma_table_info('event') WHERE name = 'token_0'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(type_, "BLOB");
+        let internal_type: String = sqlite
+            .connection
+            .query_row(
+                "SELECT internal_type FROM _column_metadata WHERE table_name = 'event' AND \
+                 column_name = 'token_0'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(internal_type, "contract IERC20");
+        assert_eq!(count_rows(&sqlite, "_column_metadata"), 1);
+
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::Address(Address([1; 20])),
+                AbiValue::Uint(Uint::new(256, 2u32.into()).unwrap()),
+            ],
+            ..Default::default()
+        };
+        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+        let token: Vec<u8> = sqlite
+            .connection
+            .query_row("SELECT token_0 FROM event", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(token, [1; 20]);
+    }
+
+    fn schema_version(connection: &Connection) -> u32 {
+        connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn schema_version_of_new_database() {
+        let sqlite = Sqlite::new_for_test();
+        assert_eq!(schema_version(&sqlite.connection), SCHEMA_VERSION);
+
+        // Opening again doesn't need migrations.
+        let sqlite = Sqlite::new(sqlite.connection).unwrap();
+        assert_eq!(schema_version(&sqlite.connection), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn migrations() {
+        let sqlite = Sqlite::new_for_test();
+        set_schema_version(&sqlite.connection, 0).unwrap();
+        let migrations: &[Migration] = &[
+            |_, transaction| {
+                transaction.execute("CREATE TABLE migration (version INTEGER)", ())?;
+                Ok(())
+            },
+            |_, transaction| {
+                transaction.execute("INSERT INTO migration VALUES (2)", ())?;
+                Ok(())
+            },
+        ];
+        sqlite
+            .inner
+            .migrate(&sqlite.connection, migrations)
+            .unwrap();
+        assert_eq!(schema_version(&sqlite.connection), 2);
+        assert_eq!(count_rows(&sqlite, "migration"), 1);
+
+        // Already applied migrations are skipped.
+        sqlite
+            .inner
+            .migrate(&sqlite.connection, migrations)
+            .unwrap();
+        assert_eq!(count_rows(&sqlite, "migration"), 1);
+    }
+
+    #[test]
+    fn refuses_newer_schema_version() {
+        let sqlite = Sqlite::new_for_test();
+        set_schema_version(&sqlite.connection, SCHEMA_VERSION + 1).unwrap();
+        let err = Sqlite::new(sqlite.connection).err().unwrap();
+        assert!(err.to_string().contains("newer arak"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn event_blocks() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+        let result = sqlite.event_block("event").await.unwrap();
+        assert_eq!(result.indexed, 0);
+        assert_eq!(result.finalized, 0);
+        let blocks = database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 2,
+                finalized: 3,
+            },
+        };
+        sqlite.update(&[blocks], &[], &[], &[]).await.unwrap();
+        let result = sqlite.event_block("event").await.unwrap();
+        assert_eq!(result.indexed, 2);
+        assert_eq!(result.finalized, 3);
+    }
+
+    fn count_rows(db: &Sqlite, table_name: &str) -> i64 {
+        let count: i64 = db
+            .connection
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table_name), (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        count
+    }
+
+    #[tokio::test]
+    async fn remove() {
+        let mut sqlite = Sqlite::new_for_test();
+
+        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
+        sqlite.prepare_event("event", &event).await.unwrap();
+        sqlite.prepare_event("eventAAA", &event).await.unwrap();
+        sqlite
+            .update(
+                &[],
+                &[
+                    Log {
+                        event: "event".into(),
+                        block_number: 1,
+                        ..Default::default()
+                    },
+                    Log {
+                        event: "event".into(),
+                        block_number: 2,
+                        ..Default::default()
+                    },
+                    Log {
+                        event: "event".into(),
+                        block_number: 5,
+                        ..Default::default()
+                    },
+                    Log {
+                        event: "event".into(),
+                        block_number: 6,
+                        ..Default::default()
+                    },
+                ],
+                &[
+                    BlockTime {
+                        number: 5,
+                        timestamp: SystemTime::UNIX_EPOCH,
+                    },
+                    BlockTime {
+                        number: 6,
+                        timestamp: SystemTime::UNIX_EPOCH,
+                    },
+                ],
+                &[
+                    database::Transaction {
+                        block_number: 5,
+                        index: 2,
+                        hash: digest!(
+                            "0x0000000000000000000000000000000000000000000000000000000000000111"
+                        ),
+                        from: Address([4; 20]),
+                        to: Some(Address([4; 20])),
+                    },
+                    database::Transaction {
+                        block_number: 6,
+                        index: 2,
+                        hash: digest!(
+                            "0x0000000000000000000000000000000000000000000000000000000000000222"
+                        ),
+                        from: Address([4; 20]),
+                        to: Some(Address([4; 20])),
+                    },
+                ],
+            )
+            .await
+            .unwrap();
+
+        let rows = |sqlite: &Sqlite| {
+            let count: i64 = sqlite
+                .connection
+                .query_row("SELECT COUNT(*) FROM event", (), |row| row.get(0))
+                .unwrap();
+            count
+        };
+        assert_eq!(rows(&sqlite), 4);
+        assert_eq!(count_rows(&sqlite, "transactions"), 2);
+        assert_eq!(count_rows(&sqlite, "blocks"), 2);
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 6,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(rows(&sqlite), 3);
+        assert_eq!(count_rows(&sqlite, "transactions"), 1);
+        assert_eq!(count_rows(&sqlite, "blocks"), 1);
+
+        sqlite
+            .remove(&[database::Uncle {
+                event: "eventAAA",
+                number: 1,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(rows(&sqlite), 3);
+        assert_eq!(count_rows(&sqlite, "transactions"), 0);
+        assert_eq!(count_rows(&sqlite, "blocks"), 0);
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 1,
+            }])
+            .await
+            .unwrap();
+        assert_eq!(rows(&sqlite), 0);
+    }
+    #[test]
+    fn remove_reporting() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256[])").unwrap();
+        for name in ["event", "other"] {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
         }
+        let logs = (1..=3)
+            .map(|block_number| Log {
+                event: "event".into(),
+                block_number,
+                fields: vec![AbiValue::Array(
+                    Array::new(
+                        AbiKind::Uint(BitWidth::MAX),
+                        (0..block_number)
+                            .map(|i| AbiValue::Uint(Uint::new(256, i.into()).unwrap()))
+                            .collect(),
+                    )
+                    .unwrap(),
+                )],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let uncle = |event, number| database::Uncle { event, number };
+        let report = sqlite
+            .remove_reporting(&[uncle("event", 2), uncle("other", 1)])
+            .unwrap();
+        assert_eq!(
+            report,
+            [
+                ("event_array_0".to_string(), 5),
+                ("event".to_string(), 2),
+                ("other_array_0".to_string(), 0),
+                ("other".to_string(), 0),
+            ]
+        );
+        assert_eq!(count_rows(&sqlite, "event"), 1);
+        assert_eq!(count_rows(&sqlite, "event_array_0"), 1);
+
+        // Reorgs deeper than the stored logs remove nothing.
+        let report = sqlite.remove_reporting(&[uncle("event", 5)]).unwrap();
+        assert!(report.iter().all(|(_, rows)| *rows == 0));
     }
 
-    #[tokio::test]
-    async fn full_leaf_types() {
+    #[test]
+    fn search_text() {
+        let event =
+            EventDescriptor::parse_declaration("event Event(string name, bytes data)").unwrap();
+        let prepare = |options| {
+            let mut sqlite = Sqlite::in_memory(options).unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let logs = ["Uniswap", "unicorn", "50% off", "50 of", "none"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| Log {
+                    event: "event".into(),
+                    block_number: i as u64,
+                    fields: vec![
+                        AbiValue::String(name.to_string()),
+                        AbiValue::Bytes(name.as_bytes().to_vec()),
+                    ],
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+            sqlite
+        };
+        let search = |sqlite: &Sqlite, substring| {
+            sqlite
+                .search_text("event", "name_0", substring)
+                .unwrap()
+                .into_iter()
+                .map(|log| log.block_number)
+                .collect::<Vec<_>>()
+        };
+
+        let sqlite = prepare(Default::default());
+        assert_eq!(search(&sqlite, "uni"), [0, 1]);
+        assert_eq!(search(&sqlite, "50%"), [2]);
+        assert_eq!(search(&sqlite, "0_o"), Vec::<u64>::new());
+        assert_eq!(search(&sqlite, ""), [0, 1, 2, 3, 4]);
+        let logs = sqlite.search_text("event", "name_0", "corn").unwrap();
+        assert_eq!(logs[0].fields[0], AbiValue::String("unicorn".to_string()));
+        assert!(sqlite.search_text("event", "data_1", "uni").is_err());
+        assert!(sqlite.search_text("event", "missing", "uni").is_err());
+
+        let sqlite = prepare(Options {
+            case_sensitive_like: true,
+            ..Default::default()
+        });
+        assert_eq!(search(&sqlite, "uni"), [1]);
+    }
+
+    #[test]
+    fn log_builder() {
         let mut sqlite = Sqlite::new_for_test();
-        let event = r#"
-            event Event (
-                int256,
-                uint256,
-                address,
-                bool,
-                bytes1,
-                function,
-                bytes,
-                string
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log::builder()
+            .event("event")
+            .block_number(3)
+            .log_index(1)
+            .transaction_index(2)
+            .address(Address([1; 20]))
+            .fields(vec![AbiValue::Bool(true)])
+            .build();
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        let read = sqlite.read_one("event", 3, 1).unwrap().unwrap().0;
+        assert_eq!(read.transaction_index, 2);
+        assert_eq!(read.address, log.address);
+        assert_eq!(read.fields, log.fields);
+        assert_eq!(read.chain_id, None);
+    }
+
+    #[test]
+    fn unchanged_event_blocks() {
+        use std::sync::atomic::{self, AtomicUsize};
+
+        static UPDATES: AtomicUsize = AtomicUsize::new(0);
+        static SELECTS: AtomicUsize = AtomicUsize::new(0);
+        fn trace(statement: &str) {
+            if statement.starts_with("UPDATE _event_block SET indexed") {
+                UPDATES.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+            if statement.starts_with("SELECT indexed, finalized FROM _event_block") {
+                SELECTS.fetch_add(1, atomic::Ordering::SeqCst);
+            }
+        }
+        // The number of event block updates and reads that an update
+        // executes.
+        fn update(sqlite: &mut Sqlite, indexed: u64, finalized: u64) -> (usize, usize) {
+            let block = database::EventBlock {
+                event: "event",
+                block: database::Block { indexed, finalized },
+            };
+            UPDATES.store(0, atomic::Ordering::SeqCst);
+            SELECTS.store(0, atomic::Ordering::SeqCst);
+            sqlite
+                .update_iter(&[block], &[] as &[Log], &[], &[])
+                .unwrap();
+            (
+                UPDATES.load(atomic::Ordering::SeqCst),
+                SELECTS.load(atomic::Ordering::SeqCst),
             )
-        "#;
-        let event = EventDescriptor::parse_declaration(event).unwrap();
-        sqlite.prepare_event("event", &event).await.unwrap();
+        }
+
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite.connection.trace(Some(trace));
+        // Only the first update reads the stored blocks.
+        assert_eq!(update(&mut sqlite, 5, 2), (1, 1));
+        assert_eq!(update(&mut sqlite, 5, 2), (0, 0));
+        assert_eq!(update(&mut sqlite, 5, 3), (1, 0));
+
+        // Blocks of a rolled back update aren't remembered.
+        let block = database::EventBlock {
+            event: "event",
+            block: database::Block {
+                indexed: 6,
+                finalized: 3,
+            },
+        };
+        let log = Log {
+            event: "unknown".into(),
+            ..Default::default()
+        };
+        assert!(sqlite.update_iter(&[block], [log], &[], &[]).is_err());
+        assert_eq!(update(&mut sqlite, 6, 3), (1, 0));
+        assert_eq!(update(&mut sqlite, 5, 3), (1, 0));
+
+        // Nor are the blocks of a batch before it is committed.
+        sqlite.begin_batch().unwrap();
+        assert_eq!(update(&mut sqlite, 6, 3), (1, 0));
+        sqlite.connection.execute_batch("ROLLBACK").unwrap();
+        assert_eq!(update(&mut sqlite, 6, 3), (1, 1));
+
+        // `remove` moves the indexed block back, after which the same blocks
+        // change it again.
+        sqlite
+            .remove(&[database::Uncle {
+                event: "event",
+                number: 4,
+            }])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(update(&mut sqlite, 5, 3), (1, 1));
+        assert_eq!(
+            sqlite.event_block("event").now_or_never().unwrap().unwrap(),
+            database::Block {
+                indexed: 5,
+                finalized: 3
+            }
+        );
+    }
+
+    #[test]
+    fn union_event() {
+        let mut sqlite = Sqlite::new_for_test();
+        let transfer = EventDescriptor::parse_declaration(
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+        )
+        .unwrap();
+        let deposit = EventDescriptor::parse_declaration(
+            "event Deposit(address indexed from, address indexed to, uint256 amount)",
+        )
+        .unwrap();
+        let members = [("usdc", &transfer), ("dai", &deposit)];
+        sqlite.prepare_union_event("transfers", &members).unwrap();
+        // Preparing again is fine.
+        sqlite.prepare_union_event("transfers", &members).unwrap();
+
+        let log = |event: &'static str, block_number, value: u64| Log {
+            event: event.into(),
+            block_number,
+            fields: vec![
+                AbiValue::Address(Address([1; 20])),
+                AbiValue::Address(Address([2; 20])),
+                AbiValue::Uint(Uint::new(256, value.into()).unwrap()),
+            ],
+            ..Default::default()
+        };
+        let logs = [
+            log("usdc", 1, 10),
+            log("dai", 1, 20),
+            log("usdc", 2, 30),
+            log("dai", 3, 40),
+        ];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+        assert_eq!(count_rows(&sqlite, "transfers"), 4);
+        let count = |event: &str| -> i64 {
+            sqlite
+                .connection
+                .query_row(
+                    "SELECT COUNT(*) FROM transfers WHERE source_event = ?1",
+                    (event,),
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(count("usdc"), 2);
+        assert_eq!(count("dai"), 2);
+
+        let values = |sqlite: &Sqlite, event| {
+            sqlite
+                .read_logs(event, 0, 10)
+                .unwrap()
+                .into_iter()
+                .map(|log| log.fields[2].clone())
+                .collect::<Vec<_>>()
+        };
+        let uint = |value: u64| AbiValue::Uint(Uint::new(256, value.into()).unwrap());
+        assert_eq!(values(&sqlite, "usdc"), [uint(10), uint(30)]);
+        assert_eq!(values(&sqlite, "dai"), [uint(20), uint(40)]);
+
+        // Removing logs of one member leaves the others.
+        sqlite
+            .remove_reporting(&[database::Uncle {
+                event: "usdc",
+                number: 1,
+            }])
+            .unwrap();
+        assert_eq!(values(&sqlite, "usdc"), []);
+        assert_eq!(values(&sqlite, "dai"), [uint(20), uint(40)]);
+        assert!(sqlite
+            .managed_tables()
+            .unwrap()
+            .contains(&"transfers".to_string()));
+
+        // Members need the same columns.
+        let other = EventDescriptor::parse_declaration("event Other(address, bool)").unwrap();
+        assert!(sqlite
+            .prepare_union_event("transfers", &[("other", &other)])
+            .is_err());
+        assert!(!sqlite.contains_event("other"));
+        // And members stay in their union.
+        assert!(sqlite
+            .prepare_union_event("others", &[("usdc", &transfer)])
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let event = EventDescriptor::parse_declaration("event Event(string name)").unwrap();
+        let read = |lossy_utf8| {
+            let mut sqlite = Sqlite::in_memory(Options {
+                lossy_utf8,
+                ..Default::default()
+            })
+            .unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let log = Log {
+                event: "event".into(),
+                block_number: 7,
+                log_index: 2,
+                fields: vec![AbiValue::String("arak".to_string())],
+                ..Default::default()
+            };
+            sqlite.update_iter(&[], &[log], &[], &[]).unwrap();
+            sqlite
+                .connection
+                .execute("UPDATE event SET name_0 = CAST(x'61ff72616b' AS TEXT);", ())
+                .unwrap();
+            sqlite.read_logs("event", 0, 10)
+        };
+
+        let err = read(false).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DatabaseError>(),
+            Some(&DatabaseError::InvalidUtf8 {
+                event: "event".to_string(),
+                block_number: 7,
+                log_index: 2,
+                column: "name_0".to_string(),
+            }),
+            "{err:?}"
+        );
+
+        let logs = read(true).unwrap();
+        assert_eq!(
+            logs[0].fields,
+            [AbiValue::String("a\u{fffd}rak".to_string())]
+        );
+    }
 
+    #[test]
+    fn read_event() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(int8, uint256, address, bool, bytes3, function, bytes, string, \
+             (bool, string)[], uint64[2])",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
         let fields = vec![
-            AbiValue::Int(Int::new(256, 1i32.into()).unwrap()),
-            AbiValue::Uint(Uint::new(256, 2u32.into()).unwrap()),
-            AbiValue::Address(Address([3; 20])),
+            AbiValue::Int(Int::new(8, I256::new(-128)).unwrap()),
+            AbiValue::Uint(Uint::new(256, U256::MAX).unwrap()),
+            AbiValue::Address(Address([1; 20])),
             AbiValue::Bool(true),
-            AbiValue::FixedBytes(FixedBytes::new(&[4]).unwrap()),
+            AbiValue::FixedBytes(FixedBytes::new(&[2; 3]).unwrap()),
             AbiValue::Function(ExternalFunction {
-                address: Address([6; 20]),
-                selector: Selector([7, 8, 9, 10]),
+                address: Address([3; 20]),
+                selector: Selector([4; 4]),
             }),
-            AbiValue::Bytes(vec![11, 12]),
-            AbiValue::String("abcd".to_string()),
+            AbiValue::Bytes(vec![5, 6]),
+            AbiValue::String("arak".to_string()),
+            AbiValue::Array(
+                Array::from_values(vec![
+                    AbiValue::Tuple(vec![
+                        AbiValue::Bool(false),
+                        AbiValue::String("hello".to_string()),
+                    ]),
+                    AbiValue::Tuple(vec![
+                        AbiValue::Bool(true),
+                        AbiValue::String("world".to_string()),
+                    ]),
+                ])
+                .unwrap(),
+            ),
+            AbiValue::FixedArray(
+                FixedArray::new(
+                    AbiKind::Uint(BitWidth::new(64).unwrap()),
+                    vec![
+                        AbiValue::Uint(Uint::new(64, U256::new(7)).unwrap()),
+                        AbiValue::Uint(Uint::new(64, U256::new(8)).unwrap()),
+                    ],
+                )
+                .unwrap(),
+            ),
+        ];
+        let logs = [3, 4].map(|log_index| Log {
+            event: "event".into(),
+            block_number: 2,
+            log_index,
+            fields: fields.clone(),
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        assert_eq!(sqlite.read_event("event", 2, 4).unwrap(), fields);
+        assert!(sqlite.read_event("event", 2, 5).is_err());
+        assert!(sqlite.read_event("unknown", 2, 4).is_err());
+    }
+
+    #[test]
+    fn indexed_dynamic_fields() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(string indexed name, address indexed owner, uint256[] indexed ids, \
+             string note)",
+        )
+        .unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let hash = |byte| AbiValue::FixedBytes(FixedBytes::new(&[byte; 32]).unwrap());
+        let fields = vec![
+            hash(1),
+            AbiValue::Address(Address([2; 20])),
+            hash(3),
+            AbiValue::String("note".to_string()),
         ];
+        let log = Log {
+            event: "event".into(),
+            fields: fields.clone(),
+            ..Default::default()
+        };
+        sqlite.update_iter(&[], [log], &[], &[]).unwrap();
+        assert_eq!(sqlite.read_event("event", 0, 0).unwrap(), fields);
+        let type_: String = sqlite
+            .connection
+            .query_row(
+                "SELECT type FROM pragma_table_info('event') WHERE name = 'name_0'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(type_, "BLOB");
+
+        // The values aren't in the log, only their hashes.
+        let log = Log {
+            event: "event".into(),
+            block_number: 1,
+            fields: vec![
+                AbiValue::String("name".to_string()),
+                fields[1].clone(),
+                fields[2].clone(),
+                fields[3].clone(),
+            ],
+            ..Default::default()
+        };
+        assert!(sqlite.update_iter(&[], [log], &[], &[]).is_err());
+        // Preparing the same event again is fine.
         sqlite
-            .update(
-                &[],
-                &[Log {
-                    event: "event",
-                    block_number: 1,
-                    log_index: 2,
-                    transaction_index: 3,
-                    address: Address([4; 20]),
-                    fields,
-                }],
-                &[database::BlockTime {
-                    number: 1,
-                    timestamp: SystemTime::UNIX_EPOCH,
-                }],
-                &[database::Transaction {
-                    block_number: 1,
-                    index: 2,
-                    hash: digest!(
-                        "0x0000000000000000000000000101010101010101010101010101010101010101"
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn path_column_migration() {
+        let event = EventDescriptor::parse_declaration(
+            "event Event((uint8 a, bool[2] flags) t, string name)",
+        )
+        .unwrap();
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::Tuple(vec![
+                    AbiValue::Uint(Uint::new(8, U256::new(1)).unwrap()),
+                    AbiValue::FixedArray(
+                        FixedArray::new(
+                            AbiKind::Bool,
+                            vec![AbiValue::Bool(true), AbiValue::Bool(false)],
+                        )
+                        .unwrap(),
                     ),
-                    from: Address([4; 20]),
-                    to: Some(Address([4; 20])),
-                }],
-            )
-            .await
+                ]),
+                AbiValue::String("arak".to_string()),
+            ],
+            ..Default::default()
+        };
+        let mut sqlite = Sqlite::new_for_test();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
             .unwrap();
+        let columns = |sqlite: &Sqlite| -> Vec<String> {
+            sqlite
+                .connection
+                .prepare("SELECT name FROM pragma_table_info('event') WHERE cid >= 4;")
+                .unwrap()
+                .query_map((), |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(
+            columns(&sqlite),
+            ["t_a_0", "t_flags_0_1", "t_flags_1_2", "name_3"]
+        );
+
+        // Go back to the column names of schema version 1.
+        for (new, old) in [
+            ("t_a_0", "a_0"),
+            ("t_flags_0_1", "flags_1"),
+            ("t_flags_1_2", "flags_2"),
+        ] {
+            sqlite
+                .connection
+                .execute(
+                    &format!("ALTER TABLE event RENAME COLUMN {new} TO {old};"),
+                    (),
+                )
+                .unwrap();
+        }
+        set_schema_version(&sqlite.connection, 1).unwrap();
 
-        print_table(&sqlite.connection, "event");
-        print_table(&sqlite.connection, "blocks");
-        print_table(&sqlite.connection, "transactions");
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        assert_eq!(schema_version(&sqlite.connection), SCHEMA_VERSION);
+        assert_eq!(
+            columns(&sqlite),
+            ["t_a_0", "t_flags_0_1", "t_flags_1_2", "name_3"]
+        );
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert_eq!(sqlite.read_event("event", 0, 0).unwrap(), log.fields);
     }
 
-    #[tokio::test]
-    async fn with_array() {
+    #[test]
+    fn nested_dynamic_arrays() {
+        let event = EventDescriptor::parse_declaration(
+            "event Event(string[][] names, (uint8 a, bool[] flags)[] xs)",
+        )
+        .unwrap();
         let mut sqlite = Sqlite::new_for_test();
-        let event = r#"
-            event Event (
-                (bool, string)[]
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+
+        let strings = |strings: &[&str]| match strings {
+            [] => Array::new(AbiKind::String, vec![]).unwrap(),
+            _ => Array::from_values(
+                strings
+                    .iter()
+                    .map(|s| AbiValue::String(s.to_string()))
+                    .collect(),
             )
-        "#;
-        let event = EventDescriptor::parse_declaration(event).unwrap();
-        sqlite.prepare_event("event", &event).await.unwrap();
+            .unwrap(),
+        };
+        let x = |a: u8, flags: &[bool]| {
+            let flags = match flags {
+                [] => Array::new(AbiKind::Bool, vec![]).unwrap(),
+                _ => {
+                    Array::from_values(flags.iter().copied().map(AbiValue::Bool).collect()).unwrap()
+                }
+            };
+            AbiValue::Tuple(vec![
+                AbiValue::Uint(Uint::new(8, a.into()).unwrap()),
+                AbiValue::Array(flags),
+            ])
+        };
+        let logs = [
+            Log {
+                event: "event".into(),
+                block_number: 1,
+                fields: vec![
+                    AbiValue::Array(
+                        Array::from_values(vec![
+                            AbiValue::Array(strings(&["a", "b"])),
+                            AbiValue::Array(strings(&[])),
+                            AbiValue::Array(strings(&["c"])),
+                        ])
+                        .unwrap(),
+                    ),
+                    AbiValue::Array(Array::from_values(vec![x(1, &[true]), x(2, &[])]).unwrap()),
+                ],
+                ..Default::default()
+            },
+            Log {
+                event: "event".into(),
+                block_number: 2,
+                fields: vec![
+                    AbiValue::Array(
+                        Array::new(AbiKind::Array(Box::new(AbiKind::String)), vec![]).unwrap(),
+                    ),
+                    AbiValue::Array(Array::from_values(vec![x(3, &[false, true])]).unwrap()),
+                ],
+                ..Default::default()
+            },
+        ];
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
 
-        let log = Log {
-            event: "event",
-            block_number: 0,
-            fields: vec![AbiValue::Array(
-                Array::from_values(vec![
-                    AbiValue::Tuple(vec![
-                        AbiValue::Bool(false),
-                        AbiValue::String("hello".to_string()),
-                    ]),
-                    AbiValue::Tuple(vec![
-                        AbiValue::Bool(true),
-                        AbiValue::String("world".to_string()),
-                    ]),
-                ])
-                .unwrap(),
-            )],
+        let rows: Vec<(i64, i64, String)> = sqlite
+            .connection
+            .prepare("SELECT array_index, array_index_1, names_0 FROM event_names_1")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (0, 0, "a".to_string()),
+                (0, 1, "b".to_string()),
+                (2, 0, "c".to_string())
+            ]
+        );
+        let sql = &sqlite.inner.events["event"].table_definitions[2];
+        assert!(
+            sql.contains(
+                "PRIMARY KEY(block_number ASC, log_index ASC, array_index ASC, array_index_1 ASC)"
+            ),
+            "{sql}"
+        );
+
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, log) in read.iter().zip(&logs) {
+            assert_eq!(read.fields, log.fields);
+        }
+        assert_eq!(sqlite.read_event("event", 2, 0).unwrap(), logs[1].fields);
+
+        let mut coalesced = Sqlite::in_memory(Options {
+            coalesce_arrays: true,
             ..Default::default()
-        };
-        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+        })
+        .unwrap();
+        let err = coalesced
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(err.to_string().contains("nested dynamic arrays"), "{err:?}");
+    }
 
-        let log = Log {
-            event: "event",
-            block_number: 1,
-            fields: vec![AbiValue::Array(
-                Array::new(AbiKind::Tuple(vec![AbiKind::Bool, AbiKind::String]), vec![]).unwrap(),
-            )],
+    #[test]
+    fn packed_key_range_plan() {
+        let mut sqlite = Sqlite::in_memory(Options {
+            packed_key: true,
+            debug_query_plan: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = [(1, 0), (2, 3), (2, 5), (3, 0)].map(|(block_number, log_index)| Log {
+            event: "event".into(),
+            block_number,
+            log_index,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        });
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let inner = &sqlite.inner;
+        let filter = QueryFilter {
+            from_block: Some(2),
+            to_block: Some(3),
+            after: Some((2, 3)),
             ..Default::default()
         };
-        sqlite.update(&[], &[log], &[], &[]).await.unwrap();
+        let (query, params) = filter.sql("event", inner.rowid_key()).unwrap();
+        for (sql, params) in [
+            (
+                read_sql(
+                    "event",
+                    inner.primary_filter(READ_LOGS_FILTER),
+                    &inner.primary_order(),
+                ),
+                vec![SqlValue::Integer(2), SqlValue::Integer(3)],
+            ),
+            (
+                read_sql(
+                    "event",
+                    inner.primary_filter(READ_ONE_FILTER),
+                    &inner.primary_order(),
+                ),
+                vec![SqlValue::Integer(2), SqlValue::Integer(5)],
+            ),
+            (query, params),
+        ] {
+            let plan = inner
+                .explain(
+                    &sqlite.connection,
+                    &sql,
+                    rusqlite::params_from_iter(&params),
+                )
+                .unwrap();
+            assert!(
+                plan.iter().any(|line| line.contains("INTEGER PRIMARY KEY")),
+                "{sql}: {plan:?}"
+            );
+            assert!(
+                !plan.iter().any(|line| line.contains("TEMP B-TREE")),
+                "{sql}: {plan:?}"
+            );
+        }
 
-        print_table(&sqlite.connection, "event");
-        print_table(&sqlite.connection, "event_array_0");
+        let keys = |logs: Vec<Log>| -> Vec<(u64, u64)> {
+            logs.iter()
+                .map(|log| (log.block_number, log.log_index))
+                .collect()
+        };
+        assert_eq!(
+            keys(sqlite.read_logs("event", 2, 2).unwrap()),
+            [(2, 3), (2, 5)]
+        );
+        assert_eq!(
+            keys(sqlite.read_logs("event", 0, i64::MAX as u64).unwrap()).len(),
+            4
+        );
+        assert_eq!(
+            keys(sqlite.read_page("event", &filter).unwrap()),
+            [(2, 5), (3, 0)]
+        );
+        let filter = QueryFilter {
+            order: Order::Desc,
+            after: Some((u32::MAX.into(), 0)),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            keys(sqlite.read_page("event", &filter).unwrap()),
+            [(3, 0), (2, 5)]
+        );
+        assert!(sqlite.read_one("event", 2, 5).unwrap().is_some());
+        assert!(sqlite.read_one("event", 2, 4).unwrap().is_none());
     }
 
-    #[tokio::test]
-    async fn event_blocks() {
-        let mut sqlite = Sqlite::new_for_test();
-        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
-        sqlite.prepare_event("event", &event).await.unwrap();
-        let result = sqlite.event_block("event").await.unwrap();
-        assert_eq!(result.indexed, 0);
-        assert_eq!(result.finalized, 0);
-        let blocks = database::EventBlock {
-            event: "event",
-            block: database::Block {
-                indexed: 2,
-                finalized: 3,
-            },
+    #[test]
+    fn on_commit_batch() {
+        let connection = "sqlite://on_commit_batch?mode=memory&cache=shared";
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let open = || {
+            let mut sqlite = Sqlite::open(connection).unwrap();
+            sqlite
+                .prepare_event("event", &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            sqlite.set_on_commit(Some(Box::new({
+                let seen = seen.clone();
+                move |_, logs| {
+                    seen.lock()
+                        .unwrap()
+                        .extend(logs.iter().map(|log| log.log_index));
+                }
+            })));
+            sqlite
         };
-        sqlite.update(&[blocks], &[], &[], &[]).await.unwrap();
-        let result = sqlite.event_block("event").await.unwrap();
-        assert_eq!(result.indexed, 2);
-        assert_eq!(result.finalized, 3);
+        let log = |log_index| Log {
+            event: "event".into(),
+            log_index,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        };
+
+        // Reported only once the batch is committed.
+        let mut sqlite = open();
+        let other = open();
+        sqlite.begin_batch().unwrap();
+        for log_index in [0, 1] {
+            sqlite
+                .update(&[], &[log(log_index)], &[], &[])
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        assert!(seen.lock().unwrap().is_empty());
+        sqlite.commit_batch().unwrap();
+        assert_eq!(*seen.lock().unwrap(), [0, 1]);
+
+        // Never reported if the batch is rolled back on drop.
+        sqlite.begin_batch().unwrap();
+        sqlite
+            .update(&[], &[log(2)], &[], &[])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        drop(sqlite);
+        assert_eq!(*seen.lock().unwrap(), [0, 1]);
+        assert_eq!(count_rows(&other, "event"), 2);
     }
 
-    fn count_rows(db: &Sqlite, table_name: &str) -> i64 {
-        let count: i64 = db
+    #[test]
+    fn path_column_migration_without_signatures() {
+        // Tables of a database from before event signatures were stored, as
+        // the first versions of arak created them.
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE blocks (number INTEGER PRIMARY KEY, time TEXT NOT NULL);
+                 CREATE TABLE transactions (block_number INTEGER NOT NULL, \"index\" INTEGER \
+                 NOT NULL, hash BLOB NOT NULL, \"from\" BLOB NOT NULL, \"to\" BLOB, PRIMARY \
+                 KEY (block_number, \"index\"));
+                 CREATE TABLE _event_block(event TEXT PRIMARY KEY NOT NULL, indexed INTEGER \
+                 NOT NULL, finalized INTEGER NOT NULL) STRICT;
+                 CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT \
+                 NULL, transaction_index INTEGER NOT NULL, address BLOB NOT NULL, a_0 BLOB \
+                 NOT NULL, flags_1 INTEGER NOT NULL, flags_2 INTEGER NOT NULL, name_3 BLOB NOT \
+                 NULL, PRIMARY KEY(block_number ASC, log_index ASC)) STRICT;",
+            )
+            .unwrap();
+
+        let event = EventDescriptor::parse_declaration(
+            "event Event((uint8 a, bool[2] flags) t, string name)",
+        )
+        .unwrap();
+        let mut sqlite = Sqlite::new(connection).unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let columns: Vec<String> = sqlite
             .connection
-            .query_row(&format!("SELECT COUNT(*) FROM {}", table_name), (), |row| {
-                row.get(0)
-            })
+            .prepare("SELECT name FROM pragma_table_info('event') WHERE cid >= 4;")
+            .unwrap()
+            .query_map((), |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
             .unwrap();
-        count
+        assert_eq!(columns, ["t_a_0", "t_flags_0_1", "t_flags_1_2", "name_3"]);
+
+        let log = Log {
+            event: "event".into(),
+            fields: vec![
+                AbiValue::Tuple(vec![
+                    AbiValue::Uint(Uint::new(8, U256::new(1)).unwrap()),
+                    AbiValue::FixedArray(
+                        FixedArray::new(
+                            AbiKind::Bool,
+                            vec![AbiValue::Bool(true), AbiValue::Bool(false)],
+                        )
+                        .unwrap(),
+                    ),
+                ]),
+                AbiValue::String("arak".to_string()),
+            ],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], std::slice::from_ref(&log), &[], &[])
+            .unwrap();
+        assert_eq!(sqlite.read_event("event", 0, 0).unwrap(), log.fields);
     }
 
-    #[tokio::test]
-    async fn remove() {
+    #[test]
+    fn existing_table_column_names() {
+        let event = EventDescriptor::parse_declaration("event Event(uint8 a, bool b)").unwrap();
         let mut sqlite = Sqlite::new_for_test();
-
-        let event = EventDescriptor::parse_declaration("event Event()").unwrap();
-        sqlite.prepare_event("event", &event).await.unwrap();
-        sqlite.prepare_event("eventAAA", &event).await.unwrap();
         sqlite
-            .update(
-                &[],
-                &[
-                    Log {
-                        event: "event",
-                        block_number: 1,
-                        ..Default::default()
-                    },
-                    Log {
-                        event: "event",
-                        block_number: 2,
-                        ..Default::default()
-                    },
-                    Log {
-                        event: "event",
-                        block_number: 5,
-                        ..Default::default()
-                    },
-                    Log {
-                        event: "event",
-                        block_number: 6,
-                        ..Default::default()
-                    },
-                ],
-                &[
-                    BlockTime {
-                        number: 5,
-                        timestamp: SystemTime::UNIX_EPOCH,
-                    },
-                    BlockTime {
-                        number: 6,
-                        timestamp: SystemTime::UNIX_EPOCH,
-                    },
-                ],
-                &[
-                    database::Transaction {
-                        block_number: 5,
-                        index: 2,
-                        hash: digest!(
-                            "0x0000000000000000000000000000000000000000000000000000000000000111"
-                        ),
-                        from: Address([4; 20]),
-                        to: Some(Address([4; 20])),
-                    },
-                    database::Transaction {
-                        block_number: 6,
-                        index: 2,
-                        hash: digest!(
-                            "0x0000000000000000000000000000000000000000000000000000000000000222"
-                        ),
-                        from: Address([4; 20]),
-                        to: Some(Address([4; 20])),
-                    },
-                ],
+            .connection
+            .execute(
+                "CREATE TABLE event (block_number INTEGER NOT NULL, log_index INTEGER NOT NULL, \
+                 transaction_index INTEGER NOT NULL, address BLOB NOT NULL, x_0 BLOB NOT NULL, \
+                 y_1 INTEGER NOT NULL, PRIMARY KEY(block_number ASC, log_index ASC)) STRICT;",
+                (),
             )
-            .await
             .unwrap();
+        let err = sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("exists without column a_0"),
+            "{err:#}"
+        );
+    }
 
-        let rows = |sqlite: &Sqlite| {
-            let count: i64 = sqlite
+    #[test]
+    fn integer_encoding_per_event() {
+        let event = EventDescriptor::parse_declaration("event Event(int8)").unwrap();
+        let log = |event: &str| Log {
+            event: event.to_string().into(),
+            fields: vec![AbiValue::Int(Int::new(8, I256::new(-1)).unwrap())],
+            ..Default::default()
+        };
+        let stored = |sqlite: &Sqlite, table: &str| -> Vec<u8> {
+            sqlite
                 .connection
-                .query_row("SELECT COUNT(*) FROM event", (), |row| row.get(0))
-                .unwrap();
-            count
+                .query_row(&format!("SELECT field_0 FROM {table};"), (), |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        };
+        let prepare = |sqlite: &mut Sqlite, name: &str| {
+            sqlite.prepare_event(name, &event).now_or_never().unwrap()
         };
-        assert_eq!(rows(&sqlite), 4);
-        assert_eq!(count_rows(&sqlite, "transactions"), 2);
-        assert_eq!(count_rows(&sqlite, "blocks"), 2);
 
+        let mut sqlite = Sqlite::new_for_test();
+        // The table of an event from before encodings were stored.
         sqlite
-            .remove(&[database::Uncle {
-                event: "event",
-                number: 6,
-            }])
-            .await
+            .connection
+            .execute(
+                "CREATE TABLE old (block_number INTEGER NOT NULL, log_index INTEGER NOT NULL, \
+                 transaction_index INTEGER NOT NULL, address BLOB NOT NULL, field_0 BLOB NOT \
+                 NULL, PRIMARY KEY(block_number ASC, log_index ASC)) STRICT;",
+                (),
+            )
             .unwrap();
-        assert_eq!(rows(&sqlite), 3);
-        assert_eq!(count_rows(&sqlite, "transactions"), 1);
-        assert_eq!(count_rows(&sqlite, "blocks"), 1);
-
+        prepare(&mut sqlite, "old").unwrap();
+        prepare(&mut sqlite, "new").unwrap();
+        assert_eq!(
+            sqlite.integer_encoding("old"),
+            Some(IntegerEncoding::TwosComplement)
+        );
+        assert_eq!(
+            sqlite.integer_encoding("new"),
+            Some(IntegerEncoding::Biased)
+        );
         sqlite
-            .remove(&[database::Uncle {
-                event: "eventAAA",
-                number: 1,
-            }])
-            .await
+            .update_iter(&[], [log("old"), log("new")], &[], &[])
             .unwrap();
-        assert_eq!(rows(&sqlite), 3);
-        assert_eq!(count_rows(&sqlite, "transactions"), 0);
-        assert_eq!(count_rows(&sqlite, "blocks"), 0);
+        assert_eq!(stored(&sqlite, "old"), [0xff; 32]);
+        let mut biased = [0; 32];
+        biased[31] = 0x7f;
+        assert_eq!(stored(&sqlite, "new"), biased);
+
+        // The encodings are stored so they don't depend on the tables anymore.
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        prepare(&mut sqlite, "old").unwrap();
+        prepare(&mut sqlite, "new").unwrap();
+        assert_eq!(
+            sqlite.integer_encoding("old"),
+            Some(IntegerEncoding::TwosComplement)
+        );
+        assert_eq!(
+            sqlite.integer_encoding("new"),
+            Some(IntegerEncoding::Biased)
+        );
+        assert_eq!(sqlite.read_event("old", 0, 0).unwrap(), log("old").fields);
+        assert_eq!(sqlite.read_event("new", 0, 0).unwrap(), log("new").fields);
+
+        let mut sqlite = Sqlite::with_options(
+            sqlite.connection,
+            Options {
+                integer_encoding: Some(IntegerEncoding::Biased),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let err = prepare(&mut sqlite, "old").unwrap_err();
+        assert!(
+            format!("{err:#}")
+                .contains("created with integer encoding TwosComplement instead of Biased"),
+            "{err:#}"
+        );
+        prepare(&mut sqlite, "new").unwrap();
+
+        // Events of databases from before the migration have the encoding
+        // that used to be the default.
         sqlite
-            .remove(&[database::Uncle {
-                event: "event",
-                number: 1,
-            }])
-            .await
+            .connection
+            .execute_batch(
+                "ALTER TABLE _event_naming DROP COLUMN integer_encoding;
+                 PRAGMA user_version = 2;",
+            )
             .unwrap();
-        assert_eq!(rows(&sqlite), 0);
+        let mut sqlite = Sqlite::new(sqlite.connection).unwrap();
+        assert_eq!(schema_version(&sqlite.connection), SCHEMA_VERSION);
+        prepare(&mut sqlite, "old").unwrap();
+        assert_eq!(
+            sqlite.integer_encoding("old"),
+            Some(IntegerEncoding::TwosComplement)
+        );
     }
 }
@@ -0,0 +1,533 @@
+//! Reading events as Arrow record batches for analytics libraries like Polars
+//! or DataFusion.
+//!
+//! Every event field becomes one Arrow column:
+//!
+//! - `int<M>` and `uint<M>` as 32 byte big endian two's complement fixed size
+//!   binary
+//! - `address`, `bytes<M>` and `function` as fixed size binary
+//! - `bytes` as binary and `string` as UTF-8
+//! - tuples as structs, fixed arrays as fixed size lists and dynamic arrays as
+//!   lists
+
+use {
+    super::{PreparedEvent, QueryFilter, Sqlite},
+    crate::database,
+    anyhow::{anyhow, Context, Result},
+    arrow_array::{
+        ArrayRef, BinaryArray, BooleanArray, FixedSizeBinaryArray, FixedSizeListArray, ListArray,
+        RecordBatch, StringArray, StructArray, UInt64Array,
+    },
+    arrow_buffer::{Buffer, OffsetBuffer},
+    arrow_schema::{DataType, Field, Fields, Schema},
+    solabi::value::{Value as AbiValue, ValueKind as AbiKind},
+    std::sync::Arc,
+};
+
+impl Sqlite {
+    /// Reads the logs of an event in the inclusive block range like
+    /// `read_logs` into a record batch.
+    ///
+    /// The batch starts with the columns `block_number`, `log_index`,
+    /// `transaction_index` and `address` (preceded by `chain_id` if
+    /// `Options::chain_id` is set) followed by one column per event field.
+    ///
+    /// Both bounds are inclusive like in `read_logs`. To export incrementally,
+    /// read up to the event's indexed block and pass the block after it as
+    /// `from` of the next export. Every row is then exported exactly once.
+    pub fn read_arrow(&self, name: &str, from: u64, to: u64) -> Result<RecordBatch> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let logs = self.read_logs(name, from, to)?;
+        record_batch(self, event, &logs)
+    }
+
+    /// Like `read_arrow` but reads the logs a page of at most `page_size`
+    /// logs at a time and passes each page as its own record batch to
+    /// `write` before reading the next, so that exporting a large range only
+    /// keeps one page in memory. Returns the number of exported logs.
+    ///
+    /// Pages continue after the key of the previous page's last log, which
+    /// isn't unique with `Options::chain_id`, so that isn't supported.
+    pub fn export_arrow(
+        &self,
+        name: &str,
+        from: u64,
+        to: u64,
+        page_size: u64,
+        mut write: impl FnMut(RecordBatch) -> Result<()>,
+    ) -> Result<u64> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        if page_size == 0 {
+            return Err(anyhow!("page_size must not be 0"));
+        }
+        if self.inner.options.chain_id {
+            return Err(anyhow!("can't page through logs of several chains"));
+        }
+        let mut filter = QueryFilter {
+            from_block: Some(from),
+            to_block: Some(to),
+            limit: Some(page_size),
+            ..Default::default()
+        };
+        let mut exported = 0;
+        loop {
+            let logs = self.read_page(name, &filter)?;
+            let Some(last) = logs.last() else {
+                break;
+            };
+            filter.after = Some((last.block_number, last.log_index));
+            exported += logs.len() as u64;
+            let last_page = (logs.len() as u64) < page_size;
+            write(record_batch(self, event, &logs)?)?;
+            if last_page {
+                break;
+            }
+        }
+        Ok(exported)
+    }
+}
+
+/// The record batch of `logs` of `event`, see `Sqlite::read_arrow`.
+fn record_batch(
+    sqlite: &Sqlite,
+    event: &PreparedEvent,
+    logs: &[database::Log],
+) -> Result<RecordBatch> {
+    let mut fields = Vec::new();
+    let mut columns: Vec<ArrayRef> = Vec::new();
+    if sqlite.inner.options.chain_id {
+        fields.push(Field::new("chain_id", DataType::UInt64, false));
+        columns.push(Arc::new(UInt64Array::from_iter_values(
+            logs.iter().map(|log| log.chain_id.unwrap_or_default()),
+        )));
+    }
+    for (name, get) in [
+        (
+            "block_number",
+            (|log| log.block_number) as fn(&database::Log) -> u64,
+        ),
+        ("log_index", |log| log.log_index),
+        ("transaction_index", |log| log.transaction_index),
+    ] {
+        fields.push(Field::new(name, DataType::UInt64, false));
+        columns.push(Arc::new(UInt64Array::from_iter_values(
+            logs.iter().map(get),
+        )));
+    }
+    fields.push(Field::new("address", DataType::FixedSizeBinary(20), false));
+    columns.push(fixed_size_binary(
+        20,
+        logs.iter().map(|log| log.address.0.as_slice()),
+    ));
+
+    for (i, input) in event.descriptor.inputs.iter().enumerate() {
+        let kind = &input.field.kind;
+        let name = match input.field.name.as_str() {
+            "" => format!("field_{i}"),
+            name => name.to_string(),
+        };
+        let values = logs.iter().map(|log| &log.fields[i]).collect::<Vec<_>>();
+        fields.push(Field::new(name, data_type(kind), false));
+        columns.push(array(kind, &values)?);
+    }
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).context("record batch")
+}
+
+fn data_type(kind: &AbiKind) -> DataType {
+    match kind {
+        AbiKind::Int(_) | AbiKind::Uint(_) => DataType::FixedSizeBinary(32),
+        AbiKind::Address => DataType::FixedSizeBinary(20),
+        AbiKind::Bool => DataType::Boolean,
+        AbiKind::FixedBytes(len) => DataType::FixedSizeBinary(len.get() as i32),
+        AbiKind::Function => DataType::FixedSizeBinary(24),
+        AbiKind::Bytes => DataType::Binary,
+        AbiKind::String => DataType::Utf8,
+        AbiKind::Tuple(kinds) => DataType::Struct(tuple_fields(kinds)),
+        AbiKind::FixedArray(len, kind) => DataType::FixedSizeList(element_field(kind), *len as i32),
+        AbiKind::Array(kind) => DataType::List(element_field(kind)),
+    }
+}
+
+fn tuple_fields(kinds: &[AbiKind]) -> Fields {
+    kinds
+        .iter()
+        .enumerate()
+        .map(|(i, kind)| Field::new(i.to_string(), data_type(kind), false))
+        .collect()
+}
+
+fn element_field(kind: &AbiKind) -> Arc<Field> {
+    Arc::new(Field::new("item", data_type(kind), false))
+}
+
+/// Builds the column of `values`, which all have the kind `kind`.
+fn array(kind: &AbiKind, values: &[&AbiValue]) -> Result<ArrayRef> {
+    let mismatch = || anyhow!("value doesn't match kind {kind:?}");
+    Ok(match kind {
+        AbiKind::Int(_) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Int(value) => Ok(value.get().to_be_bytes()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            fixed_size_binary(32, values.iter().map(|value| value.as_slice()))
+        }
+        AbiKind::Uint(_) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Uint(value) => Ok(value.get().to_be_bytes()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            fixed_size_binary(32, values.iter().map(|value| value.as_slice()))
+        }
+        AbiKind::Address => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Address(value) => Ok(value.0.as_slice()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            fixed_size_binary(20, values.into_iter())
+        }
+        AbiKind::Bool => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Bool(value) => Ok(Some(*value)),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<BooleanArray>>()?,
+        ),
+        AbiKind::FixedBytes(len) => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::FixedBytes(value) => Ok(value.as_bytes()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            fixed_size_binary(len.get(), values.into_iter())
+        }
+        AbiKind::Function => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Function(value) => {
+                        Ok([value.address.0.as_slice(), value.selector.0.as_slice()].concat())
+                    }
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            fixed_size_binary(24, values.iter().map(Vec::as_slice))
+        }
+        AbiKind::Bytes => Arc::new(BinaryArray::from_iter_values(
+            values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Bytes(value) => Ok(value.as_slice()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+        AbiKind::String => Arc::new(StringArray::from_iter_values(
+            values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::String(value) => Ok(value.as_str()),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )),
+        AbiKind::Tuple(kinds) => {
+            let tuples = values
+                .iter()
+                .map(|value| match value {
+                    AbiValue::Tuple(values) => Ok(values),
+                    _ => Err(mismatch()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let children = kinds
+                .iter()
+                .enumerate()
+                .map(|(i, kind)| {
+                    let values = tuples.iter().map(|tuple| &tuple[i]).collect::<Vec<_>>();
+                    array(kind, &values)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Arc::new(
+                StructArray::try_new(tuple_fields(kinds), children, None)
+                    .context("struct array")?,
+            )
+        }
+        AbiKind::FixedArray(len, element) => {
+            let mut elements = Vec::new();
+            for value in values {
+                match value {
+                    AbiValue::FixedArray(array) => elements.extend(array.as_slice()),
+                    _ => return Err(mismatch()),
+                }
+            }
+            Arc::new(
+                FixedSizeListArray::try_new(
+                    element_field(element),
+                    *len as i32,
+                    array(element, &elements)?,
+                    None,
+                )
+                .context("fixed size list array")?,
+            )
+        }
+        AbiKind::Array(element) => {
+            let mut lengths = Vec::new();
+            let mut elements = Vec::new();
+            for value in values {
+                match value {
+                    AbiValue::Array(array) => {
+                        lengths.push(array.len());
+                        elements.extend(array.as_slice());
+                    }
+                    _ => return Err(mismatch()),
+                }
+            }
+            Arc::new(
+                ListArray::try_new(
+                    element_field(element),
+                    OffsetBuffer::from_lengths(lengths),
+                    array(element, &elements)?,
+                    None,
+                )
+                .context("list array")?,
+            )
+        }
+    })
+}
+
+fn fixed_size_binary<'a>(size: usize, values: impl Iterator<Item = &'a [u8]>) -> ArrayRef {
+    let values = values.flatten().copied().collect::<Vec<_>>();
+    Arc::new(FixedSizeBinaryArray::new(
+        size as i32,
+        Buffer::from_vec(values),
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        arrow_array::Array,
+        database::Log,
+        futures::FutureExt,
+        solabi::{abi::EventDescriptor, ethprim::Address, value::Uint},
+    };
+
+    #[test]
+    fn read_arrow() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration(
+            "event Event(uint256 amount, bool, (address, string)[2] pairs, bytes[] data)",
+        )
+        .unwrap();
+        database::Database::prepare_event(&mut sqlite, "event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let logs = (0..3u8)
+            .map(|i| Log {
+                event: "event".into(),
+                block_number: i.into(),
+                address: Address([i; 20]),
+                fields: vec![
+                    AbiValue::Uint(Uint::new(256, i.into()).unwrap()),
+                    AbiValue::Bool(i % 2 == 1),
+                    AbiValue::FixedArray(
+                        solabi::value::FixedArray::from_values(vec![
+                            AbiValue::Tuple(vec![
+                                AbiValue::Address(Address([i; 20])),
+                                AbiValue::String(i.to_string()),
+                            ]);
+                            2
+                        ])
+                        .unwrap(),
+                    ),
+                    AbiValue::Array(
+                        solabi::value::Array::new(
+                            AbiKind::Bytes,
+                            vec![AbiValue::Bytes(vec![i]); i.into()],
+                        )
+                        .unwrap(),
+                    ),
+                ],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let batch = sqlite.read_arrow("event", 0, 1).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let schema = batch.schema();
+        let names = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "block_number",
+                "log_index",
+                "transaction_index",
+                "address",
+                "amount",
+                "field_1",
+                "pairs",
+                "data"
+            ]
+        );
+        assert_eq!(schema.field(4).data_type(), &DataType::FixedSizeBinary(32));
+        assert_eq!(schema.field(5).data_type(), &DataType::Boolean);
+        assert_eq!(
+            schema.field(6).data_type(),
+            &DataType::FixedSizeList(
+                element_field(&AbiKind::Tuple(vec![AbiKind::Address, AbiKind::String])),
+                2
+            )
+        );
+        assert_eq!(
+            schema.field(7).data_type(),
+            &DataType::List(element_field(&AbiKind::Bytes))
+        );
+
+        let data = batch
+            .column(7)
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        assert_eq!(data.value_length(0), 0);
+        assert_eq!(data.value_length(1), 1);
+        assert_eq!(data.values().len(), 1);
+    }
+
+    #[test]
+    fn incremental_export() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        database::Database::prepare_event(&mut sqlite, "event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let index = |sqlite: &mut Sqlite, blocks: std::ops::Range<u64>| {
+            let logs = blocks
+                .clone()
+                .map(|block_number| Log {
+                    event: "event".into(),
+                    block_number,
+                    fields: vec![AbiValue::Uint(Uint::new(256, block_number.into()).unwrap())],
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
+            let indexed = [database::EventBlock {
+                event: "event",
+                block: database::Block {
+                    indexed: blocks.end - 1,
+                    finalized: 0,
+                },
+            }];
+            sqlite.update_iter(&indexed, &logs, &[], &[]).unwrap();
+        };
+        let export = |sqlite: &Sqlite, from: &mut u64| {
+            let to = sqlite
+                .inner
+                .event_block(&sqlite.connection, "event")
+                .unwrap()
+                .indexed;
+            let batch = sqlite.read_arrow("event", *from, to).unwrap();
+            *from = to + 1;
+            let blocks = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<UInt64Array>()
+                .unwrap();
+            blocks.values().to_vec()
+        };
+
+        let mut cursor = 0;
+        index(&mut sqlite, 0..3);
+        assert_eq!(export(&sqlite, &mut cursor), [0, 1, 2]);
+        index(&mut sqlite, 3..5);
+        assert_eq!(export(&sqlite, &mut cursor), [3, 4]);
+        assert_eq!(export(&sqlite, &mut cursor), [0u64; 0]);
+    }
+
+    #[test]
+    fn export_arrow() {
+        let mut sqlite = Sqlite::new_for_test();
+        let event = EventDescriptor::parse_declaration("event Event(uint256, bytes[])").unwrap();
+        database::Database::prepare_event(&mut sqlite, "event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        // Several logs per block so that pages end in the middle of blocks.
+        let logs = (0..50u64)
+            .map(|i| Log {
+                event: "event".into(),
+                block_number: i / 3,
+                log_index: i % 3,
+                fields: vec![
+                    AbiValue::Uint(Uint::new(256, i.into()).unwrap()),
+                    AbiValue::Array(
+                        solabi::value::Array::new(
+                            AbiKind::Bytes,
+                            vec![AbiValue::Bytes(vec![i as u8]); 2],
+                        )
+                        .unwrap(),
+                    ),
+                ],
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        sqlite.update_iter(&[], &logs, &[], &[]).unwrap();
+
+        let mut sizes = Vec::new();
+        let mut keys = Vec::new();
+        let exported = sqlite
+            .export_arrow("event", 1, 100, 7, |batch| {
+                sizes.push(batch.num_rows());
+                let column = |i: usize| {
+                    batch
+                        .column(i)
+                        .as_any()
+                        .downcast_ref::<UInt64Array>()
+                        .unwrap()
+                        .values()
+                        .to_vec()
+                };
+                keys.extend(column(0).into_iter().zip(column(1)));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(exported, 47);
+        assert_eq!(sizes, [7, 7, 7, 7, 7, 7, 5]);
+        let expected = logs[3..]
+            .iter()
+            .map(|log| (log.block_number, log.log_index))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, expected);
+
+        // Errors of `write` stop the export.
+        let mut pages = 0;
+        let result = sqlite.export_arrow("event", 0, 100, 10, |_| {
+            pages += 1;
+            Err(anyhow!("disk full"))
+        });
+        assert!(result.is_err());
+        assert_eq!(pages, 1);
+        assert!(sqlite.export_arrow("event", 0, 100, 0, |_| Ok(())).is_err());
+    }
+}
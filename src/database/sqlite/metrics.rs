@@ -0,0 +1,150 @@
+//! Measuring how long storing logs takes, see `Options::metrics`.
+
+use std::{collections::HashMap, fmt::Debug, sync::Mutex, time::Duration};
+
+/// Receives measurements of `Sqlite`. Called while the database is being
+/// written, so implementations should be cheap.
+pub trait Metrics: Debug + Send + Sync {
+    /// How long inserting the rows of one log of the event took. The commit
+    /// isn't included because it is shared by all logs of an update.
+    fn record_insert_duration(&self, event: &str, duration: Duration);
+}
+
+/// Upper bound of the first bucket of `HistogramMetrics`. Every further bucket
+/// doubles it.
+const FIRST_BUCKET: Duration = Duration::from_micros(1);
+/// Buckets up to about 36 minutes, the last one also holds longer durations.
+const BUCKETS: usize = 32;
+
+/// `Metrics` keeping a histogram of insert durations per event with
+/// exponentially growing buckets, so that percentiles are only exact up to a
+/// factor of 2.
+#[derive(Debug, Default)]
+pub struct HistogramMetrics {
+    histograms: Mutex<HashMap<String, [u64; BUCKETS]>>,
+}
+
+impl HistogramMetrics {
+    /// Number of recorded durations of the event.
+    pub fn count(&self, event: &str) -> u64 {
+        self.histograms
+            .lock()
+            .unwrap()
+            .get(event)
+            .map(|histogram| histogram.iter().sum())
+            .unwrap_or_default()
+    }
+
+    /// The upper bound of the bucket containing the `percentile` (0 to 100)
+    /// of the event's durations, or `None` if none were recorded.
+    pub fn percentile(&self, event: &str, percentile: f64) -> Option<Duration> {
+        let histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.get(event)?;
+        let count = histogram.iter().sum::<u64>();
+        let rank = ((percentile.clamp(0., 100.) / 100. * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = histogram.iter().position(|&n| {
+            seen += n;
+            seen >= rank
+        })?;
+        Some(bucket_bound(bucket))
+    }
+
+    pub fn p50(&self, event: &str) -> Option<Duration> {
+        self.percentile(event, 50.)
+    }
+
+    pub fn p95(&self, event: &str) -> Option<Duration> {
+        self.percentile(event, 95.)
+    }
+}
+
+impl Metrics for HistogramMetrics {
+    fn record_insert_duration(&self, event: &str, duration: Duration) {
+        let bucket = (0..BUCKETS)
+            .find(|&bucket| duration <= bucket_bound(bucket))
+            .unwrap_or(BUCKETS - 1);
+        let mut histograms = self.histograms.lock().unwrap();
+        match histograms.get_mut(event) {
+            Some(histogram) => histogram[bucket] += 1,
+            None => {
+                let mut histogram = [0; BUCKETS];
+                histogram[bucket] = 1;
+                histograms.insert(event.to_string(), histogram);
+            }
+        }
+    }
+}
+
+fn bucket_bound(bucket: usize) -> Duration {
+    FIRST_BUCKET * (1 << bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::database::{Database, Log, Sqlite, SqliteOptions},
+        futures::FutureExt,
+        solabi::{abi::EventDescriptor, value::Value as AbiValue},
+        std::sync::Arc,
+    };
+
+    #[test]
+    fn histogram() {
+        let metrics = HistogramMetrics::default();
+        assert_eq!(metrics.p50("event"), None);
+        for micros in [3, 3, 3, 3, 3, 3, 3, 3, 3, 100] {
+            metrics.record_insert_duration("event", Duration::from_micros(micros));
+        }
+        metrics.record_insert_duration("other", Duration::from_secs(1));
+        assert_eq!(metrics.count("event"), 10);
+        assert_eq!(metrics.p50("event"), Some(Duration::from_micros(4)));
+        assert_eq!(
+            metrics.percentile("event", 90.),
+            Some(Duration::from_micros(4))
+        );
+        assert_eq!(metrics.p95("event"), Some(Duration::from_micros(128)));
+        assert_eq!(
+            metrics.percentile("event", 0.),
+            Some(Duration::from_micros(4))
+        );
+        assert_eq!(metrics.p50("other"), Some(Duration::from_micros(1 << 20)));
+
+        metrics.record_insert_duration("event", Duration::from_secs(1 << 20));
+        assert_eq!(
+            metrics.percentile("event", 100.),
+            Some(bucket_bound(BUCKETS - 1))
+        );
+    }
+
+    #[test]
+    fn sqlite_records_inserts() {
+        let metrics = Arc::new(HistogramMetrics::default());
+        let mut sqlite = Sqlite::in_memory(SqliteOptions {
+            metrics: Some(metrics.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        let event = EventDescriptor::parse_declaration("event Event(bool)").unwrap();
+        for name in ["a", "b"] {
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+        let log = |event: &'static str, block_number| Log {
+            event: event.into(),
+            block_number,
+            fields: vec![AbiValue::Bool(true)],
+            ..Default::default()
+        };
+        sqlite
+            .update_iter(&[], &[log("a", 1), log("a", 2), log("b", 2)], &[], &[])
+            .unwrap();
+        assert_eq!(metrics.count("a"), 2);
+        assert_eq!(metrics.count("b"), 1);
+        assert!(metrics.p95("a").is_some());
+    }
+}
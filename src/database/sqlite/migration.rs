@@ -0,0 +1,220 @@
+//! Migrating databases created by older versions of arak to the current
+//! schema version.
+
+use {
+    super::{partitions, SqliteInner, GET_EVENT_NAMING, TABLE_EXISTS},
+    crate::database::event_to_tables::{
+        event_to_tables, legacy_event_to_tables, stored_event, Tables,
+    },
+    anyhow::{anyhow, Context, Result},
+    rusqlite::{Connection, OptionalExtension, Transaction},
+    solabi::abi::EventDescriptor,
+    std::collections::HashSet,
+};
+
+/// Version of the database layout that this version of arak creates. It is
+/// stored in `PRAGMA user_version` so that older databases can be migrated and
+/// databases of newer versions are refused instead of being corrupted.
+pub(super) const SCHEMA_VERSION: u32 = 3;
+
+/// Changes an existing database from one schema version to the next. The
+/// migration from version `i` to `i + 1` is at index `i` of `MIGRATIONS`.
+pub(super) type Migration = fn(&SqliteInner, &Transaction) -> Result<()>;
+
+pub(super) const MIGRATIONS: &[Migration] = &[
+    // Databases from before the schema was versioned already have the layout
+    // of version 1.
+    |_, _| Ok(()),
+    |inner, transaction| inner.rename_path_columns(transaction),
+    |inner, transaction| inner.add_integer_encoding(transaction),
+];
+
+const _: () = assert!(MIGRATIONS.len() == SCHEMA_VERSION as usize);
+
+pub(super) fn set_schema_version(connection: &Connection, version: u32) -> Result<()> {
+    connection
+        .pragma_update(None, "user_version", version)
+        .context("set user_version")
+}
+
+impl SqliteInner {
+    /// Brings an existing database up to the schema version of `migrations` by
+    /// applying the missing migrations in order, each in its own transaction.
+    pub(super) fn migrate(&self, connection: &Connection, migrations: &[Migration]) -> Result<()> {
+        let version: u32 = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .context("query user_version")?;
+        let latest = migrations.len() as u32;
+        if version > latest {
+            return Err(anyhow!(
+                "database created by a newer arak (schema version {version}, this version \
+                 supports up to {latest})"
+            ));
+        }
+        for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+            tracing::info!(from, "migrating database schema");
+            let transaction = connection.unchecked_transaction().context("transaction")?;
+            migration(self, &transaction)
+                .with_context(|| format!("migration from schema version {from}"))?;
+            set_schema_version(&transaction, from as u32 + 1)?;
+            transaction.commit().context("commit")?;
+        }
+        Ok(())
+    }
+
+    /// Migration to schema version 2, which names the columns of tuple
+    /// components and fixed array elements after their path instead of only
+    /// their leaf, see `event_to_tables`. The tables are found through the
+    /// stored event signatures. Databases without them are migrated when their
+    /// events are prepared.
+    fn rename_path_columns(&self, con: &Connection) -> Result<()> {
+        let signatures: bool = con
+            .query_row(
+                TABLE_EXISTS,
+                (self.sql("{prefix}_event_signature"),),
+                |row| row.get(0),
+            )
+            .context("query table_exists")?;
+        if !signatures {
+            return Ok(());
+        }
+        let events = con
+            .prepare(&self.sql("SELECT event, signature FROM {prefix}_event_signature;"))
+            .context("prepare event_signatures")?
+            .query_map((), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("query event_signatures")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read event_signatures")?;
+        let sanitizer = self.sanitizer();
+        for (name, signature) in events {
+            let naming = con
+                .query_row(&self.sql(GET_EVENT_NAMING), (&name,), |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()
+                .context("query get_event_naming")?;
+            if naming.is_some_and(|naming| naming != sanitizer.id()) {
+                tracing::warn!(%name, "not renaming the columns of an event with another name sanitizer");
+                continue;
+            }
+            let event = EventDescriptor::parse_declaration(&signature)
+                .with_context(|| format!("parse signature of event {name}"))?;
+            self.rename_legacy_columns(con, &name, &stored_event(&event))?;
+        }
+        Ok(())
+    }
+
+    /// Renames the columns of the event's tables from the leaf names of
+    /// `legacy_event_to_tables` to the path names of `event_to_tables`. Tables
+    /// whose columns don't all have the legacy names are left alone.
+    pub(super) fn rename_legacy_columns(
+        &self,
+        con: &Connection,
+        name: &str,
+        event: &EventDescriptor,
+    ) -> Result<()> {
+        let sanitizer = self.sanitizer();
+        let tables = |tables: Result<Tables<'_>>| -> Result<Vec<(String, Vec<String>)>> {
+            let mut tables = tables?;
+            for table in std::iter::once(&mut tables.primary).chain(&mut tables.dynamic_arrays) {
+                table.name.insert_str(0, &self.options.table_prefix);
+            }
+            Ok(std::iter::once(tables.primary.clone())
+                .chain(self.physical_arrays(&tables))
+                .map(|table| {
+                    let columns = table
+                        .columns
+                        .iter()
+                        .flat_map(|column| self.column_names(column))
+                        .collect();
+                    (table.name, columns)
+                })
+                .collect())
+        };
+        let old = tables(legacy_event_to_tables(name, event, sanitizer))?;
+        let new = tables(event_to_tables(name, event, sanitizer))?;
+        for ((table, old), (_, new)) in old.iter().zip(&new) {
+            let renames = old
+                .iter()
+                .zip(new)
+                .filter(|(old, new)| old != new)
+                .collect::<Vec<_>>();
+            if renames.is_empty() {
+                continue;
+            }
+            let exists: bool = con
+                .query_row(TABLE_EXISTS, (table,), |row| row.get(0))
+                .context("query table_exists")?;
+            let storage = exists
+                .then(|| table.clone())
+                .into_iter()
+                .chain(
+                    partitions(con, table)?
+                        .into_iter()
+                        .map(|partition| format!("{table}_p{partition}")),
+                )
+                .collect::<Vec<_>>();
+            let mut renamed = false;
+            for storage in &storage {
+                let columns = con
+                    .prepare("SELECT name FROM pragma_table_info(?1);")
+                    .context("prepare table columns")?
+                    .query_map((storage,), |row| row.get::<_, String>(0))
+                    .context("query table columns")?
+                    .collect::<Result<HashSet<_>, _>>()
+                    .context("read table columns")?;
+                if !renames.iter().all(|(old, _)| columns.contains(*old)) {
+                    continue;
+                }
+                for (old, new) in &renames {
+                    con.execute(
+                        &format!("ALTER TABLE {storage} RENAME COLUMN {old} TO {new};"),
+                        (),
+                    )
+                    .context("rename column")?;
+                }
+                renamed = true;
+            }
+            if !renamed {
+                continue;
+            }
+            tracing::info!(%name, %table, "renaming columns to their path");
+            for (old, new) in &renames {
+                con.execute(
+                    &self.sql(
+                        "UPDATE {prefix}_column_metadata SET column_name = ?3 WHERE \
+                         table_name = ?1 AND column_name = ?2;",
+                    ),
+                    (table, old, new),
+                )
+                .context("rename column metadata")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Migration to schema version 3, which stores the integer encoding of
+    /// each event next to its name sanitizer.
+    fn add_integer_encoding(&self, con: &Connection) -> Result<()> {
+        let columns = con
+            .prepare("SELECT name FROM pragma_table_info(?1);")
+            .context("prepare table columns")?
+            .query_map((self.sql("{prefix}_event_naming"),), |row| {
+                row.get::<_, String>(0)
+            })
+            .context("query table columns")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read table columns")?;
+        // Databases without the table get it with the column.
+        if !columns.is_empty() && !columns.iter().any(|column| column == "integer_encoding") {
+            con.execute(
+                &self.sql("ALTER TABLE {prefix}_event_naming ADD COLUMN integer_encoding TEXT;"),
+                (),
+            )
+            .context("add integer_encoding column")?;
+        }
+        Ok(())
+    }
+}
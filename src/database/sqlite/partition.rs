@@ -0,0 +1,155 @@
+//! Splitting event tables into partitions by block number, see
+//! `Options::partition_size`.
+
+use {
+    super::{sql_string, PreparedEvent, SqliteInner, TABLE_EXISTS},
+    anyhow::{Context, Result},
+    rusqlite::Connection,
+};
+
+/// Lists the tables of a table's partitions, see `Options::partition_size`.
+const GET_PARTITIONS: &str =
+    "SELECT name FROM sqlite_schema WHERE type = 'table' AND name GLOB ?1 || '_p[0-9]*';";
+
+/// The partitions of `table` in ascending order.
+pub(super) fn partitions(con: &Connection, table: &str) -> Result<Vec<u64>> {
+    let mut partitions = con
+        .prepare_cached(GET_PARTITIONS)
+        .context("prepare_cached get_partitions")?
+        .query_map((table,), |row| row.get::<_, String>(0))
+        .context("query get_partitions")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("read get_partitions")?
+        .into_iter()
+        .filter_map(|name| name[table.len() + 2..].parse().ok())
+        .collect::<Vec<u64>>();
+    partitions.sort_unstable();
+    Ok(partitions)
+}
+
+/// Replaces the view of a partitioned table with one over all partitions.
+pub(super) fn create_view(con: &Connection, table: &str) -> Result<()> {
+    let select = partitions(con, table)?
+        .iter()
+        .map(|partition| format!("SELECT * FROM {table}_p{partition}"))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    con.execute_batch(&format!(
+        "DROP VIEW IF EXISTS {table}; CREATE VIEW {table} AS {select};"
+    ))
+    .context("create view")
+}
+
+/// The statement creating `storage` with `definition`, see
+/// `PreparedEvent::table_definitions`.
+pub(super) fn create_table_sql(storage: &str, definition: &str) -> String {
+    format!("CREATE TABLE IF NOT EXISTS {storage} {definition}")
+}
+
+/// Where the rows of an event table are stored: the table itself, one of its
+/// partitions or, for the view of a union member, the union table restricted
+/// to the member's rows.
+#[derive(Debug)]
+pub(super) struct Storage {
+    pub(super) table: String,
+    /// The union member whose rows are stored in `table`.
+    pub(super) source_event: Option<String>,
+}
+
+impl Storage {
+    pub(super) fn plain(table: String) -> Self {
+        Self {
+            table,
+            source_event: None,
+        }
+    }
+
+    /// The statement deleting the stored rows matching `condition`.
+    pub(super) fn delete(&self, condition: &str) -> String {
+        match &self.source_event {
+            Some(event) => format!(
+                "DELETE FROM {} WHERE source_event = {} AND ({condition});",
+                self.table,
+                sql_string(event)
+            ),
+            None => format!("DELETE FROM {} WHERE {condition};", self.table),
+        }
+    }
+
+    /// The statement deleting all stored rows.
+    pub(super) fn clear(&self) -> String {
+        match &self.source_event {
+            Some(event) => format!(
+                "DELETE FROM {} WHERE source_event = {};",
+                self.table,
+                sql_string(event)
+            ),
+            None => format!("DELETE FROM {};", self.table),
+        }
+    }
+}
+
+impl SqliteInner {
+    /// The table that stands for `table` when checking and creating it, which
+    /// is partition 0 with `Options::partition_size`. Partition 0 is always
+    /// created so that existing tables can be checked.
+    pub(super) fn storage_table(&self, table: &str) -> String {
+        match self.options.partition_size {
+            Some(_) => format!("{table}_p0"),
+            None => table.to_string(),
+        }
+    }
+
+    /// Where the rows of `table` are stored: every partition of `table` that
+    /// can contain blocks from `from` on, or `table` itself if tables aren't
+    /// partitioned. For the view of a union member the member's rows of the
+    /// union table.
+    pub(super) fn storages(
+        &self,
+        con: &Connection,
+        table: &str,
+        from: u64,
+    ) -> Result<Vec<Storage>> {
+        if let Some((union_table, event)) = self.unions.get(table) {
+            return Ok(vec![Storage {
+                table: union_table.clone(),
+                source_event: Some(event.clone()),
+            }]);
+        }
+        let Some(size) = self.options.partition_size else {
+            return Ok(vec![Storage::plain(table.to_string())]);
+        };
+        Ok(partitions(con, table)?
+            .into_iter()
+            .filter(|&partition| partition >= from / size)
+            .map(|partition| Storage::plain(format!("{table}_p{partition}")))
+            .collect())
+    }
+
+    /// Creates the tables of partition `partition` of an event unless they
+    /// exist and adds them to the views.
+    pub(super) fn create_partition(
+        &self,
+        con: &Connection,
+        event: &PreparedEvent,
+        partition: u64,
+    ) -> Result<()> {
+        let exists: bool = con
+            .prepare_cached(TABLE_EXISTS)
+            .context("prepare_cached table_exists")?
+            .query_row((format!("{}_p{partition}", event.table),), |row| row.get(0))
+            .context("query table_exists")?;
+        if exists {
+            return Ok(());
+        }
+        for (table, definition) in std::iter::once(&event.table)
+            .chain(&event.array_tables)
+            .zip(&event.table_definitions)
+        {
+            let sql = create_table_sql(&format!("{table}_p{partition}"), definition);
+            con.execute(&sql, ()).context("execute create_partition")?;
+            create_view(con, table)?;
+        }
+        Ok(())
+    }
+}
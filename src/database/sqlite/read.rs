@@ -0,0 +1,786 @@
+//! Reading logs back from the database.
+
+use {
+    super::{
+        array_depths, array_index_columns, decode_kind, ArrayRow, PreparedEvent, Sqlite,
+        SqliteInner, FIXED_COLUMNS_COUNT,
+    },
+    crate::database::{event_to_tables::event_to_tables, DatabaseError, Log},
+    anyhow::{anyhow, Context, Result},
+    rusqlite::{
+        types::{Value as SqlValue, ValueRef as SqlValueRef},
+        Connection,
+    },
+    solabi::{
+        ethprim::Address,
+        value::{Value as AbiValue, ValueKind as AbiKind},
+    },
+    std::{cell::Cell, collections::HashMap, fmt::Write, string::FromUtf8Error},
+};
+
+/// Filter of `Sqlite::query`. All conditions are optional and combined.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueryFilter {
+    /// Inclusive lower bound of the block number.
+    pub from_block: Option<u64>,
+    /// Inclusive upper bound of the block number.
+    pub to_block: Option<u64>,
+    /// Only logs emitted by this contract.
+    pub address: Option<Address>,
+    pub order: Order,
+    /// Maximum number of rows.
+    pub limit: Option<u64>,
+    /// Only logs after this `(block_number, log_index)` in the order, for
+    /// paging with `limit` by passing the key of the last log of the
+    /// previous page. Unlike an offset this doesn't scan the skipped rows.
+    pub after: Option<(u64, u64)>,
+}
+
+/// Order of rows by block number and log index.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A row returned by `Sqlite::query` with the names of its columns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Row {
+    pub columns: Vec<(String, SqlValue)>,
+}
+
+impl Row {
+    pub fn get(&self, column: &str) -> Option<&SqlValue> {
+        self.columns
+            .iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, value)| value)
+    }
+}
+
+impl QueryFilter {
+    /// The SELECT statement for `table` and its parameters. Values are always
+    /// bound as parameters and never part of the SQL. With `rowid_key` the
+    /// range and order use the packed rowid key, see `Options::packed_key`.
+    pub(super) fn sql(&self, table: &str, rowid_key: bool) -> Result<(String, Vec<SqlValue>)> {
+        let integer = |value: u64| -> Result<SqlValue> {
+            Ok(SqlValue::Integer(
+                value.try_into().context("integer out of bounds")?,
+            ))
+        };
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        // Returns the number of the parameter.
+        let mut condition = |conditions: &mut Vec<String>, condition: &str, value: SqlValue| {
+            params.push(value);
+            conditions.push(format!("{condition} ?{}", params.len()));
+            params.len()
+        };
+        // With the rowid key the bounds of the rowid let SQLite search it
+        // while the conditions on the columns keep the result exact. Block
+        // numbers are clamped so that the shifts can't overflow.
+        if let Some(from) = self.from_block {
+            let from = condition(&mut conditions, "block_number >=", integer(from)?);
+            if rowid_key {
+                conditions.push(format!("rowid >= (min(?{from}, 2147483647) << 32)"));
+            }
+        }
+        if let Some(to) = self.to_block {
+            let to = condition(&mut conditions, "block_number <=", integer(to)?);
+            if rowid_key {
+                conditions.push(format!(
+                    "rowid <= ((min(?{to}, 2147483647) << 32) | 4294967295)"
+                ));
+            }
+        }
+        if let Some(address) = self.address {
+            condition(
+                &mut conditions,
+                "address =",
+                SqlValue::Blob(address.0.to_vec()),
+            );
+        }
+        if let Some((block_number, log_index)) = self.after {
+            let op = match self.order {
+                Order::Asc => ">",
+                Order::Desc => "<",
+            };
+            params.extend([integer(block_number)?, integer(log_index)?]);
+            let (block_number, log_index) = (params.len() - 1, params.len());
+            conditions.push(format!(
+                "(block_number, log_index) {op} (?{block_number}, ?{log_index})"
+            ));
+            if rowid_key {
+                // A clamped block number is past every stored log.
+                conditions.push(format!(
+                    "rowid {op}= ((min(?{block_number}, 2147483647) << 32) | CASE WHEN \
+                     ?{block_number} > 2147483647 THEN 4294967295 ELSE min(?{log_index}, \
+                     4294967295) END)"
+                ));
+            }
+        }
+
+        let mut sql = format!("SELECT * FROM {table}");
+        if !conditions.is_empty() {
+            write!(&mut sql, " WHERE {}", conditions.join(" AND ")).unwrap();
+        }
+        let order = match self.order {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        };
+        match rowid_key {
+            true => write!(&mut sql, " ORDER BY rowid {order}").unwrap(),
+            false => write!(
+                &mut sql,
+                " ORDER BY block_number {order}, log_index {order}"
+            )
+            .unwrap(),
+        }
+        if let Some(limit) = self.limit {
+            params.push(integer(limit)?);
+            write!(&mut sql, " LIMIT ?{}", params.len()).unwrap();
+        }
+        sql.push(';');
+        Ok((sql, params))
+    }
+}
+
+pub(super) const READ_LOGS_FILTER: &str = "block_number BETWEEN ?1 AND ?2";
+
+pub(super) const READ_ONE_FILTER: &str = "block_number = ?1 AND log_index = ?2";
+
+/// `READ_LOGS_FILTER` and `READ_ONE_FILTER` for primary tables keyed by the
+/// rowid, which search the rowid instead of scanning the table. The block
+/// numbers are clamped so that the shifts can't overflow and the conditions on
+/// the columns keep the result exact.
+const PACKED_READ_LOGS_FILTER: &str = "rowid BETWEEN (min(?1, 2147483647) << 32) AND \
+                                       ((min(?2, 2147483647) << 32) | 4294967295) AND \
+                                       block_number BETWEEN ?1 AND ?2";
+
+const PACKED_READ_ONE_FILTER: &str = "rowid = ((min(?1, 2147483647) << 32) | \
+                                      min(?2, 4294967295)) AND block_number = ?1 AND \
+                                      log_index = ?2";
+
+/// Logs per array table query of `Sqlite::read_page`. Stays below SQLite's
+/// limit of 32766 parameters with every key column.
+const READ_PAGE_CHUNK: usize = 1000;
+
+fn read_rows(
+    con: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Vec<SqlValue>>> {
+    let mut statement = con.prepare_cached(sql).context("prepare_cached read")?;
+    let columns = statement.column_count();
+    // rusqlite panics on text that isn't valid UTF-8, which is read as a blob
+    // instead so that decoding reports it.
+    let value = |value: SqlValueRef| match value {
+        SqlValueRef::Text(text) if std::str::from_utf8(text).is_err() => {
+            SqlValue::Blob(text.to_vec())
+        }
+        value => value.into(),
+    };
+    let rows = statement
+        .query_map(params, |row| {
+            (0..columns).map(|i| Ok(value(row.get_ref(i)?))).collect()
+        })
+        .context("query read")?;
+    rows.collect::<Result<_, _>>().context("read row")
+}
+
+pub(super) fn read_sql(table: &str, filter: &str, order: &str) -> String {
+    format!("SELECT * FROM {table} WHERE {filter} ORDER BY {order};")
+}
+
+impl Sqlite {
+    /// Reads the logs of an event in the inclusive block range back from the
+    /// database, ordered by block number and log index.
+    pub fn read_logs<'a>(&self, name: &'a str, from: u64, to: u64) -> Result<Vec<Log<'a>>> {
+        let from = i64::try_from(from).context("from out of bounds")?;
+        let to = i64::try_from(to).context("to out of bounds")?;
+        self.snapshot(|| {
+            self.inner
+                .read_logs(&self.connection, name, READ_LOGS_FILTER, (from, to))
+        })
+    }
+
+    /// Like `read_logs` but the logs own their event name, so that they can
+    /// be replayed into another `Database` without fetching them from the
+    /// chain again.
+    pub fn replay(&self, name: &str, from: u64, to: u64) -> Result<Vec<Log<'static>>> {
+        Ok(self
+            .read_logs(name, from, to)?
+            .into_iter()
+            .map(Log::into_owned)
+            .collect())
+    }
+
+    /// Like `read_logs` but returns only the fields, each paired with its
+    /// sanitized field name in the order of the event's inputs. Unnamed
+    /// fields are called `field_{index}`.
+    pub fn read_events_named(
+        &self,
+        name: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Vec<(String, AbiValue)>>> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let mut names: Vec<String> = Vec::new();
+        for (i, input) in event.descriptor.inputs.iter().enumerate() {
+            let field = match input.field.name.as_str() {
+                "" => format!("field_{i}"),
+                field => self.inner.sanitizer().sanitize(field),
+            };
+            if names.contains(&field) {
+                return Err(anyhow!("event {name} has several fields named {field}"));
+            }
+            names.push(field);
+        }
+        Ok(self
+            .read_logs(name, from, to)?
+            .into_iter()
+            .map(|log| names.iter().cloned().zip(log.fields).collect())
+            .collect())
+    }
+
+    /// The decoded fields of the log of an event at a block and log index, in
+    /// the order of the event's inputs like `Log::fields`. Errors if there is
+    /// no such log.
+    pub fn read_event(
+        &self,
+        name: &str,
+        block_number: u64,
+        log_index: u64,
+    ) -> Result<Vec<AbiValue>> {
+        self.snapshot(|| {
+            self.inner
+                .read_event(&self.connection, name, block_number, log_index)
+        })
+    }
+
+    /// Reads the log of an event at a block and log index, along with its
+    /// decoded fields. `None` if there is no such log.
+    pub fn read_one<'a>(
+        &self,
+        name: &'a str,
+        block_number: u64,
+        log_index: u64,
+    ) -> Result<Option<(Log<'a>, Vec<AbiValue>)>> {
+        let block_number = i64::try_from(block_number).context("block_number out of bounds")?;
+        let log_index = i64::try_from(log_index).context("log_index out of bounds")?;
+        let mut logs = self.snapshot(|| {
+            self.inner.read_logs(
+                &self.connection,
+                name,
+                READ_ONE_FILTER,
+                (block_number, log_index),
+            )
+        })?;
+        if logs.len() > 1 {
+            return Err(anyhow!(
+                "{} logs at block {block_number} index {log_index}, use read_logs",
+                logs.len()
+            ));
+        }
+        Ok(logs.pop().map(|log| {
+            let fields = log.fields.clone();
+            (log, fields)
+        }))
+    }
+
+    /// Reads and decodes the logs of an event that `filter` selects, for
+    /// example a page of them with `QueryFilter::limit`. The array rows of the
+    /// page are read with a single query per array table.
+    pub fn read_page<'a>(&self, name: &'a str, filter: &QueryFilter) -> Result<Vec<Log<'a>>> {
+        self.snapshot(|| self.inner.read_page(&self.connection, name, filter))
+    }
+
+    /// Reads the logs of an event whose string field `column` contains
+    /// `substring`, ordered by block number and log index. `%` and `_` in
+    /// `substring` match only themselves. Like SQL's `LIKE` the search ignores
+    /// the case of ASCII letters unless `Options::case_sensitive_like` is set.
+    ///
+    /// No index can serve a substring search, so every search scans the whole
+    /// primary table of the event.
+    pub fn search_text<'a>(
+        &self,
+        name: &'a str,
+        column: &str,
+        substring: &str,
+    ) -> Result<Vec<Log<'a>>> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let tables = event_to_tables(name, &event.descriptor, self.inner.sanitizer())
+            .context("event_to_tables")?;
+        let is_string = tables.primary.columns.iter().any(|column_| {
+            matches!(column_.kind, AbiKind::String)
+                && self.inner.column_names(column_).iter().any(|c| c == column)
+        });
+        if !is_string {
+            return Err(anyhow!("event {name} has no string column {column:?}"));
+        }
+        let mut pattern = String::from("%");
+        for c in substring.chars() {
+            if matches!(c, '%' | '_' | '\\') {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        pattern.push('%');
+        // Strings of tables created before they were stored as text are blobs,
+        // which `LIKE` doesn't match as text.
+        let sql = format!(
+            "SELECT * FROM {} WHERE CAST({column} AS TEXT) LIKE ?1 ESCAPE '\\' ORDER BY {};",
+            event.table,
+            self.inner.primary_order()
+        );
+        self.snapshot(|| {
+            self.inner
+                .read_selected(&self.connection, name, &sql, &[SqlValue::Text(pattern)])
+        })
+    }
+
+    /// Reads raw rows of the event's primary table without decoding them into
+    /// ABI values.
+    pub fn query(&self, name: &str, filter: QueryFilter) -> Result<Vec<Row>> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        let (sql, params) = filter.sql(&event.table, self.inner.rowid_key())?;
+        self.snapshot(|| {
+            self.inner
+                .explain(&self.connection, &sql, rusqlite::params_from_iter(&params))?;
+            let mut statement = self
+                .connection
+                .prepare_cached(&sql)
+                .context("prepare_cached query")?;
+            let names = statement
+                .column_names()
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>();
+            let rows = statement
+                .query_map(rusqlite::params_from_iter(&params), |row| {
+                    let columns = names
+                        .iter()
+                        .enumerate()
+                        .map(|(i, name)| Ok((name.clone(), row.get(i)?)))
+                        .collect::<rusqlite::Result<_>>()?;
+                    Ok(Row { columns })
+                })
+                .context("query")?;
+            rows.collect::<Result<_, _>>().context("read row")
+        })
+    }
+
+    /// Reads only the specified columns of the event's primary table for logs
+    /// in the inclusive block range, ordered by block number and log index.
+    /// Unlike reading whole events this never touches the array tables.
+    ///
+    /// `columns` can be event field columns of the primary table or the fixed
+    /// columns like `block_number`.
+    pub fn read_fields(
+        &self,
+        name: &str,
+        columns: &[&str],
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<Vec<SqlValue>>> {
+        let event = self.inner.events.get(name).context("unknown event")?;
+        if columns.is_empty() {
+            return Err(anyhow!("no columns"));
+        }
+        let fixed = ["block_number", "log_index", "transaction_index", "address"];
+        for column in columns {
+            let known = fixed.contains(column)
+                || (self.inner.options.chain_id && *column == "chain_id")
+                || event.columns.iter().any(|c| c == column);
+            if !known {
+                return Err(anyhow!("event {name} has no column {column:?}"));
+            }
+        }
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {} ORDER BY {};",
+            columns.join(", "),
+            event.table,
+            self.inner.primary_filter(READ_LOGS_FILTER),
+            self.inner.primary_order()
+        );
+        let from = i64::try_from(from).context("from out of bounds")?;
+        let to = i64::try_from(to).context("to out of bounds")?;
+        self.snapshot(|| {
+            self.inner.explain(&self.connection, &sql, (from, to))?;
+            let mut statement = self
+                .connection
+                .prepare_cached(&sql)
+                .context("prepare_cached read_fields")?;
+            let rows = statement
+                .query_map((from, to), |row| {
+                    (0..columns.len()).map(|i| row.get(i)).collect()
+                })
+                .context("query read_fields")?;
+            rows.collect::<Result<_, _>>().context("read row")
+        })
+    }
+}
+
+impl SqliteInner {
+    /// Logs the query plan of a read query if `debug_query_plan` is set and
+    /// returns its lines.
+    pub(super) fn explain(
+        &self,
+        con: &Connection,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<String>> {
+        if !self.options.debug_query_plan {
+            return Ok(Vec::new());
+        }
+        let mut statement = con
+            .prepare(&format!("EXPLAIN QUERY PLAN {sql}"))
+            .context("prepare explain")?;
+        let plan = statement
+            .query_map(params, |row| row.get::<_, String>(3))
+            .context("query explain")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read explain row")?;
+        for line in &plan {
+            tracing::debug!("query plan of {sql}: {line}");
+        }
+        Ok(plan)
+    }
+
+    /// Reads logs of an event and decodes their fields. `filter` is an SQL
+    /// condition on the fixed columns that is applied to all of the event's
+    /// tables.
+    fn read_logs<'a>(
+        &self,
+        con: &Connection,
+        name: &'a str,
+        filter: &str,
+        params: impl rusqlite::Params + Copy,
+    ) -> Result<Vec<Log<'a>>> {
+        let event = self.events.get(name).context("unknown event")?;
+        let query = |table: &str, filter: &str, order: &str| {
+            let sql = read_sql(table, filter, order);
+            self.explain(con, &sql, params)?;
+            read_rows(con, &sql, params)
+        };
+        let arrays = event
+            .array_sources
+            .iter()
+            .zip(self.array_orders(event))
+            .map(|(table, order)| query(table, filter, &order))
+            .collect::<Result<_>>()?;
+        let primary = query(
+            &event.table,
+            self.primary_filter(filter),
+            &self.primary_order(),
+        )?;
+        self.decode_logs(name, event, primary, arrays)
+    }
+
+    /// Reads the logs of an event that `filter` selects from the primary
+    /// table. The array rows of these logs are read with one query per array
+    /// table for every `READ_PAGE_CHUNK` logs instead of one per log.
+    fn read_page<'a>(
+        &self,
+        con: &Connection,
+        name: &'a str,
+        filter: &QueryFilter,
+    ) -> Result<Vec<Log<'a>>> {
+        let event = self.events.get(name).context("unknown event")?;
+        let (sql, params) = filter.sql(&event.table, self.rowid_key())?;
+        self.read_selected(con, name, &sql, &params)
+    }
+
+    /// Reads and decodes the logs of an event whose primary table rows `sql`
+    /// selects, see `read_page`.
+    fn read_selected<'a>(
+        &self,
+        con: &Connection,
+        name: &'a str,
+        sql: &str,
+        params: &[SqlValue],
+    ) -> Result<Vec<Log<'a>>> {
+        let event = self.events.get(name).context("unknown event")?;
+        self.explain(con, sql, rusqlite::params_from_iter(params))?;
+        let primary = read_rows(con, sql, rusqlite::params_from_iter(params))?;
+
+        let key = self.key_positions();
+        let key_columns = self
+            .options
+            .chain_id
+            .then_some("chain_id")
+            .into_iter()
+            .chain(["block_number", "log_index"])
+            .chain(
+                self.options
+                    .transaction_index_key
+                    .then_some("transaction_index"),
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        let orders = self.array_orders(event);
+        let mut arrays = vec![Vec::new(); event.array_sources.len()];
+        for rows in primary.chunks(READ_PAGE_CHUNK) {
+            let params = rows
+                .iter()
+                .flat_map(|row| key.iter().map(|&i| &row[i]))
+                .collect::<Vec<_>>();
+            let values = (0..rows.len())
+                .map(|row| {
+                    let values = (0..key.len())
+                        .map(|i| format!("?{}", row * key.len() + i + 1))
+                        .collect::<Vec<_>>();
+                    format!("({})", values.join(", "))
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let filter = format!("({key_columns}) IN (VALUES {values})");
+            for ((table, order), rows) in event.array_sources.iter().zip(&orders).zip(&mut arrays) {
+                let sql = read_sql(table, &filter, order);
+                self.explain(con, &sql, rusqlite::params_from_iter(&params))?;
+                rows.extend(read_rows(con, &sql, rusqlite::params_from_iter(&params))?);
+            }
+        }
+        self.decode_logs(name, event, primary, arrays)
+    }
+
+    /// Per array source of the event the order of its rows for decoding.
+    pub(super) fn array_orders(&self, event: &PreparedEvent) -> Vec<String> {
+        let order = self.key_order();
+        array_depths(&event.array_parents)
+            .into_iter()
+            .map(|depth| {
+                std::iter::once(order.clone())
+                    .chain(array_index_columns(depth))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect()
+    }
+
+    /// Positions of the primary key columns, which identify a log in all
+    /// tables.
+    fn key_positions(&self) -> Vec<usize> {
+        let chain_id = self.options.chain_id as usize;
+        (0..chain_id)
+            .chain([chain_id, chain_id + 1])
+            .chain(self.options.transaction_index_key.then_some(chain_id + 2))
+            .collect()
+    }
+
+    /// Decodes rows of the primary table into logs. `arrays` are the rows of
+    /// each array table for these logs, ordered by array index.
+    fn decode_logs<'a>(
+        &self,
+        name: &'a str,
+        event: &PreparedEvent,
+        primary: Vec<Vec<SqlValue>>,
+        arrays: Vec<Vec<Vec<SqlValue>>>,
+    ) -> Result<Vec<Log<'a>>> {
+        let chain_id = self.options.chain_id as usize;
+        let fixed_columns = chain_id + FIXED_COLUMNS_COUNT;
+        let key = self.key_positions();
+        let integer = |value: &SqlValue| -> Result<u64> {
+            match value {
+                SqlValue::Integer(value) => (*value).try_into().context("negative integer"),
+                _ => Err(anyhow!("expected integer")),
+            }
+        };
+
+        // Per array the rows by the key of the log followed by the indices
+        // of the enclosing arrays' elements. Nested arrays come after their
+        // parents so their rows are grouped first and then moved into the
+        // rows of their parents.
+        let depths = array_depths(&event.array_parents);
+        // Per log key the nullable columns that are NULL in any of its rows.
+        let mut nulls: HashMap<Vec<u64>, Vec<String>> = HashMap::new();
+        fn add_nulls(
+            nulls: &mut HashMap<Vec<u64>, Vec<String>>,
+            nullable: &[Option<String>],
+            key: &[u64],
+            values: &[SqlValue],
+        ) {
+            let columns = values
+                .iter()
+                .zip(nullable)
+                .filter(|(value, _)| **value == SqlValue::Null)
+                .filter_map(|(_, column)| column.clone());
+            let nulls = nulls.entry(key.to_vec()).or_default();
+            for column in columns {
+                if !nulls.contains(&column) {
+                    nulls.push(column);
+                }
+            }
+        }
+        let mut grouped: Vec<HashMap<Vec<u64>, Vec<ArrayRow>>> = vec![HashMap::new(); arrays.len()];
+        for (array, table) in arrays.into_iter().enumerate().rev() {
+            let children = (0..event.array_parents.len())
+                .filter(|&child| event.array_parents[child] == Some(array))
+                .collect::<Vec<_>>();
+            let depth = depths[array];
+            for mut row in table {
+                let mut path = key
+                    .iter()
+                    .map(|&i| integer(&row[i]))
+                    .chain((fixed_columns..fixed_columns + depth).map(|i| integer(&row[i])))
+                    .collect::<Result<Vec<_>>>()?;
+                row.drain(..fixed_columns + depth);
+                add_nulls(
+                    &mut nulls,
+                    &event.nullable[array + 1],
+                    &path[..key.len()],
+                    &row,
+                );
+                let arrays = children
+                    .iter()
+                    .map(|&child| grouped[child].remove(&path).unwrap_or_default())
+                    .collect();
+                path.pop();
+                grouped[array].entry(path).or_default().push(ArrayRow {
+                    values: row,
+                    arrays,
+                });
+            }
+        }
+        let mut arrays = (0..grouped.len())
+            .filter(|&array| event.array_parents[array].is_none())
+            .map(|array| (array, std::mem::take(&mut grouped[array])))
+            .collect::<Vec<_>>();
+
+        primary
+            .into_iter()
+            .map(|row| {
+                let key = key
+                    .iter()
+                    .map(|&i| integer(&row[i]))
+                    .collect::<Result<Vec<_>>>()?;
+                add_nulls(&mut nulls, &event.nullable[0], &key, &row[fixed_columns..]);
+                let mut nulls = nulls.remove(&key).unwrap_or_default();
+                nulls.sort();
+                let address = match Sqlite::decode_value(
+                    &AbiKind::Address,
+                    (&row[chain_id + 3]).into(),
+                    event.integer_encoding,
+                )? {
+                    AbiValue::Address(address) => address,
+                    _ => unreachable!(),
+                };
+                let log = Log {
+                    event: name.into(),
+                    block_number: integer(&row[chain_id])?,
+                    log_index: integer(&row[chain_id + 1])?,
+                    transaction_index: integer(&row[chain_id + 2])?,
+                    address,
+                    fields: Vec::new(),
+                    chain_id: match self.options.chain_id {
+                        true => Some(integer(&row[0])?),
+                        false => None,
+                    },
+                    data: Vec::new(),
+                    topics: Vec::new(),
+                    nulls,
+                };
+                // The column or array table read last, which a string that
+                // isn't valid UTF-8 is in.
+                let last = Cell::new(None);
+                let mut columns =
+                    row.into_iter()
+                        .skip(fixed_columns)
+                        .enumerate()
+                        .map(|(i, value)| {
+                            last.set(event.columns.get(i));
+                            value
+                        });
+                let mut tables = arrays.iter_mut().map(|(i, rows)| {
+                    // Coalesced arrays share one table.
+                    last.set(event.array_tables.get(*i).or(event.array_tables.first()));
+                    rows.remove(&key).unwrap_or_default()
+                });
+                let fields = event
+                    .descriptor
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        decode_kind(
+                            &input.field.kind,
+                            &self.options,
+                            event.integer_encoding,
+                            &mut columns,
+                            &mut tables,
+                        )
+                    })
+                    .collect::<Result<_>>()
+                    .map_err(
+                        |err| match (err.root_cause().is::<FromUtf8Error>(), last.get()) {
+                            (true, Some(column)) => DatabaseError::InvalidUtf8 {
+                                event: name.to_string(),
+                                block_number: log.block_number,
+                                log_index: log.log_index,
+                                column: column.clone(),
+                            }
+                            .into(),
+                            _ => err,
+                        },
+                    )?;
+                let (mut data, mut topics) = (Vec::new(), Vec::new());
+                if self.options.raw_logs {
+                    let mut blob = || match columns.next() {
+                        Some(SqlValue::Blob(blob)) => Ok(Some(blob)),
+                        Some(SqlValue::Null) => Ok(None),
+                        _ => Err(anyhow!("expected raw log blob")),
+                    };
+                    data = blob()?.context("missing raw data")?;
+                    for _ in 0..4 {
+                        if let Some(topic) = blob()? {
+                            topics.push(topic.try_into().ok().context("topic isn't 32 bytes")?);
+                        }
+                    }
+                }
+                if columns.next().is_some() {
+                    return Err(anyhow!("unexpected columns"));
+                }
+                Ok(Log {
+                    fields,
+                    data,
+                    topics,
+                    ..log
+                })
+            })
+            .collect()
+    }
+
+    /// The decoded fields of the log of an event at a block and log index,
+    /// reassembled from the primary and array tables.
+    fn read_event(
+        &self,
+        con: &Connection,
+        name: &str,
+        block_number: u64,
+        log_index: u64,
+    ) -> Result<Vec<AbiValue>> {
+        let params = (
+            i64::try_from(block_number).context("block_number out of bounds")?,
+            i64::try_from(log_index).context("log_index out of bounds")?,
+        );
+        let mut logs = self.read_logs(con, name, READ_ONE_FILTER, params)?;
+        match logs.len() {
+            0 => Err(anyhow!(
+                "no log of event {name} at block {block_number} index {log_index}"
+            )),
+            1 => Ok(logs.pop().unwrap().fields),
+            n => Err(anyhow!(
+                "{n} logs at block {block_number} index {log_index}, use read_logs"
+            )),
+        }
+    }
+
+    /// `filter` for the primary table, see `PACKED_READ_LOGS_FILTER`.
+    pub(super) fn primary_filter<'f>(&self, filter: &'f str) -> &'f str {
+        match filter {
+            READ_LOGS_FILTER if self.rowid_key() => PACKED_READ_LOGS_FILTER,
+            READ_ONE_FILTER if self.rowid_key() => PACKED_READ_ONE_FILTER,
+            filter => filter,
+        }
+    }
+}
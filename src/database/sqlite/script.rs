@@ -0,0 +1,212 @@
+//! Writing the SQL statements of the SQLite backend to a file instead of
+//! applying them to a database, for deployments where changes are reviewed or
+//! applied by hand.
+
+use {
+    super::{Options, Sqlite},
+    crate::database::{self, Database},
+    anyhow::{Context, Result},
+    futures::{future::BoxFuture, FutureExt},
+    rusqlite::Connection,
+    solabi::abi::EventDescriptor,
+    std::{cell::RefCell, io::Write},
+};
+
+/// A `Database` that writes the statements that `Sqlite` would execute to
+/// `W`, one per line, with parameters inlined as SQL literals (blobs as
+/// `x'..'`). Running the script with `sqlite3` replays the changes.
+///
+/// The statements are executed on a private in-memory database as well so
+/// that errors like unprepared events are reported like by `Sqlite`, and
+/// statements of failed calls are not written. Reads aren't written either.
+///
+/// With `IntegerEncoding::DecimalText` the tables use the `ARAK_NUMERIC`
+/// collation, which only exists on connections opened by arak.
+pub struct SqlScript<W> {
+    sqlite: Sqlite,
+    out: W,
+}
+
+impl<W: Write> SqlScript<W> {
+    /// Writes the statements creating arak's own tables right away.
+    pub fn new(out: W, options: Options) -> Result<Self> {
+        let mut connection = Connection::open_in_memory().context("open_in_memory")?;
+        connection.trace(Some(trace));
+        let (sqlite, statements) = capture(|| Sqlite::with_options(connection, options))?;
+        let mut this = Self { sqlite, out };
+        this.write(statements)?;
+        Ok(this)
+    }
+
+    /// Returns the output.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn record<T>(&mut self, f: impl FnOnce(&mut Sqlite) -> Result<T>) -> Result<T> {
+        let (result, statements) = capture(|| f(&mut self.sqlite))?;
+        self.write(statements)?;
+        Ok(result)
+    }
+
+    fn write(&mut self, statements: Vec<String>) -> Result<()> {
+        for statement in statements {
+            let statement = statement.trim_end_matches(';');
+            writeln!(self.out, "{statement};").context("write statement")?;
+        }
+        self.out.flush().context("flush")
+    }
+}
+
+thread_local! {
+    /// Statements traced while `capture` runs.
+    static STATEMENTS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Connection trace callback. Only takes a function pointer so the statements
+/// are collected in a thread local.
+fn trace(statement: &str) {
+    let statement = statement.trim();
+    let upper = statement.to_ascii_uppercase();
+    let read = upper.starts_with("SELECT")
+        || upper.starts_with("EXPLAIN")
+        || upper.starts_with("--")
+        || (upper.starts_with("PRAGMA") && !statement.contains('='));
+    if read {
+        return;
+    }
+    STATEMENTS.with(|statements| {
+        if let Some(statements) = statements.borrow_mut().as_mut() {
+            statements.push(statement.to_string());
+        }
+    });
+}
+
+/// Runs `f` and returns the statements it executed if it succeeded.
+fn capture<T>(f: impl FnOnce() -> Result<T>) -> Result<(T, Vec<String>)> {
+    STATEMENTS.with(|statements| *statements.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let statements = STATEMENTS
+        .with(|statements| statements.borrow_mut().take())
+        .unwrap_or_default();
+    Ok((result?, statements))
+}
+
+impl<W: Write + Send> Database for SqlScript<W> {
+    fn prepare_event<'a>(
+        &'a mut self,
+        name: &'a str,
+        event: &'a EventDescriptor,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.record(|sqlite| {
+                sqlite
+                    .prepare_event(name, event)
+                    .now_or_never()
+                    .context("prepare_event didn't complete")?
+            })
+        }
+        .boxed()
+    }
+
+    fn event_block<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, Result<database::Block>> {
+        self.sqlite.event_block(name)
+    }
+
+    fn update<'a>(
+        &'a mut self,
+        blocks: &'a [database::EventBlock],
+        logs: &'a [database::Log],
+        block_times: &'a [database::BlockTime],
+        transactions: &'a [database::Transaction],
+    ) -> BoxFuture<'a, Result<database::UpdateSummary>> {
+        async move {
+            self.record(|sqlite| sqlite.update_iter(blocks, logs, block_times, transactions))
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a mut self, uncles: &'a [database::Uncle]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.record(|sqlite| {
+                sqlite
+                    .remove(uncles)
+                    .now_or_never()
+                    .context("remove didn't complete")?
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        database::Log,
+        solabi::{
+            ethprim::Address,
+            value::{Uint, Value as AbiValue},
+        },
+    };
+
+    #[test]
+    fn script() {
+        let mut script = SqlScript::new(Vec::new(), Default::default()).unwrap();
+        let event =
+            EventDescriptor::parse_declaration("event Event(uint256, string, bytes)").unwrap();
+        script
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let log = Log {
+            event: "event".into(),
+            block_number: 3,
+            address: Address([0xab; 20]),
+            fields: vec![
+                AbiValue::Uint(Uint::new(256, 1000u32.into()).unwrap()),
+                AbiValue::String("it's".to_string()),
+                AbiValue::Bytes(vec![0, 1, 0xff]),
+            ],
+            ..Default::default()
+        };
+        script
+            .update(&[], std::slice::from_ref(&log), &[], &[])
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        // Failed calls write nothing.
+        let before = script.out.len();
+        assert!(script
+            .update(&[], &[Log::default()], &[], &[])
+            .now_or_never()
+            .unwrap()
+            .is_err());
+        assert_eq!(script.out.len(), before);
+
+        let sql = String::from_utf8(script.into_inner()).unwrap();
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS event ("), "{sql}");
+        assert!(
+            sql.lines().any(|line| line.starts_with("INSERT")
+                && line.contains(&format!("x'{}'", "ab".repeat(20)))
+                && line.contains("'it''s'")),
+            "{sql}"
+        );
+        assert!(!sql.contains("SELECT"), "{sql}");
+
+        // The script replays into an equivalent database.
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(&sql).unwrap();
+        let mut sqlite = Sqlite::new(connection).unwrap();
+        sqlite
+            .prepare_event("event", &event)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let read = sqlite.read_logs("event", 0, 10).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].address, log.address);
+        assert_eq!(read[0].fields, log.fields);
+    }
+}
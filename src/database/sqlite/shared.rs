@@ -0,0 +1,145 @@
+//! Sharing one SQLite database between several tasks or threads.
+
+use {
+    super::Sqlite,
+    crate::database::{self, Database},
+    anyhow::{anyhow, Context, Result},
+    futures::{future::BoxFuture, FutureExt},
+    solabi::abi::EventDescriptor,
+    std::sync::{Arc, Mutex, MutexGuard},
+};
+
+/// A cloneable `Database` that forwards to a `Sqlite` behind a mutex.
+///
+/// `rusqlite::Connection` isn't `Sync`, so clones don't run concurrently:
+/// every call locks the mutex for its whole duration and calls of other
+/// clones wait for it. A call still applies in full or not at all. Blocking
+/// is fine because `Sqlite` never awaits while holding the lock.
+#[derive(Clone)]
+pub struct SharedSqlite(Arc<Mutex<Sqlite>>);
+
+impl SharedSqlite {
+    pub fn new(sqlite: Sqlite) -> Self {
+        Self(Arc::new(Mutex::new(sqlite)))
+    }
+
+    /// Locks the database for methods that `Database` doesn't cover, like
+    /// reads. Other clones wait until the guard is dropped.
+    pub fn lock(&self) -> Result<MutexGuard<'_, Sqlite>> {
+        self.0
+            .lock()
+            .map_err(|_| anyhow!("a thread panicked while using the database"))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut Sqlite) -> Option<Result<T>>) -> Result<T> {
+        let mut sqlite = self.lock()?;
+        f(&mut sqlite).context("sqlite future didn't complete")?
+    }
+}
+
+impl Database for SharedSqlite {
+    fn prepare_event<'a>(
+        &'a mut self,
+        name: &'a str,
+        event: &'a EventDescriptor,
+    ) -> BoxFuture<'a, Result<()>> {
+        async move { self.with(|sqlite| sqlite.prepare_event(name, event).now_or_never()) }.boxed()
+    }
+
+    fn event_block<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, Result<database::Block>> {
+        async move { self.with(|sqlite| sqlite.event_block(name).now_or_never()) }.boxed()
+    }
+
+    fn contains_event<'a>(&'a mut self, name: &'a str) -> BoxFuture<'a, bool> {
+        async move {
+            self.lock()
+                .map(|sqlite| sqlite.contains_event(name))
+                .unwrap_or(false)
+        }
+        .boxed()
+    }
+
+    fn update<'a>(
+        &'a mut self,
+        blocks: &'a [database::EventBlock],
+        logs: &'a [database::Log],
+        block_times: &'a [database::BlockTime],
+        transactions: &'a [database::Transaction],
+    ) -> BoxFuture<'a, Result<database::UpdateSummary>> {
+        async move {
+            self.with(|sqlite| {
+                Database::update(sqlite, blocks, logs, block_times, transactions).now_or_never()
+            })
+        }
+        .boxed()
+    }
+
+    fn remove<'a>(&'a mut self, uncles: &'a [database::Uncle]) -> BoxFuture<'a, Result<()>> {
+        async move { self.with(|sqlite| sqlite.remove(uncles).now_or_never()) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        database::{EventBlock, Log},
+        solabi::value::{Uint, Value as AbiValue},
+    };
+
+    #[test]
+    fn shared() {
+        let mut shared = SharedSqlite::new(Sqlite::new_for_test());
+        let event = EventDescriptor::parse_declaration("event Event(uint256)").unwrap();
+        for name in ["a", "b"] {
+            shared
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        }
+
+        let threads = ["a", "b"].map(|name| {
+            let mut shared = shared.clone();
+            std::thread::spawn(move || {
+                for block_number in 1..=50 {
+                    let log = Log {
+                        event: name.into(),
+                        block_number,
+                        fields: vec![AbiValue::Uint(Uint::new(256, block_number.into()).unwrap())],
+                        ..Default::default()
+                    };
+                    let block = EventBlock {
+                        event: name,
+                        block: database::Block {
+                            indexed: block_number,
+                            finalized: 0,
+                        },
+                    };
+                    shared
+                        .update(&[block], &[log], &[], &[])
+                        .now_or_never()
+                        .unwrap()
+                        .unwrap();
+                }
+            })
+        });
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        for name in ["a", "b"] {
+            let block = shared.event_block(name).now_or_never().unwrap().unwrap();
+            assert_eq!(block.indexed, 50);
+            assert_eq!(
+                shared
+                    .lock()
+                    .unwrap()
+                    .read_logs(name, 0, 100)
+                    .unwrap()
+                    .len(),
+                50
+            );
+        }
+    }
+}
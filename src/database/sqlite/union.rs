@@ -0,0 +1,136 @@
+//! Storing several events in one table, see `Sqlite::prepare_union_event`.
+
+use {
+    super::{
+        sql_string, sql_type_name, IntegerEncoding, Sqlite, SqliteInner, FIXED_COLUMNS_COUNT,
+        RAW_COLUMN_NAMES,
+    },
+    crate::database::event_to_tables::Table,
+    anyhow::{anyhow, Context, Result},
+    rusqlite::Connection,
+    solabi::abi::EventDescriptor,
+};
+
+impl Sqlite {
+    /// Prepares several events to be stored in the one table `table` instead
+    /// of a table each, with a `source_event` column holding the name of the
+    /// event of each row. This keeps the number of tables down when many
+    /// events have the same fields, like the `Transfer` events of many tokens.
+    ///
+    /// The members are otherwise used like events prepared with
+    /// `prepare_event`: `update` stores the logs of a member into the union
+    /// table and the member's name is a view of its rows, so reads, queries
+    /// and `remove` only see the member's own logs. The primary key starts
+    /// with `source_event`.
+    ///
+    /// Errors if the members' fields don't map to the same columns, or if a
+    /// member has dynamic arrays. Partitions, packed keys and event addresses
+    /// aren't supported either.
+    pub fn prepare_union_event(
+        &mut self,
+        table: &str,
+        members: &[(&str, &EventDescriptor)],
+    ) -> Result<()> {
+        if members.is_empty() {
+            return Err(anyhow!("union table {table} has no events"));
+        }
+        let transaction = self.connection.savepoint().context("savepoint")?;
+        let mut prepared = Vec::new();
+        let result = members
+            .iter()
+            .try_for_each(|(name, event)| {
+                let new = !self.inner.events.contains_key(*name);
+                self.inner
+                    .prepare_event(&transaction, name, event, &Default::default(), Some(table))
+                    .with_context(|| format!("prepare event {name} of union table {table}"))?;
+                if new {
+                    prepared.push(*name);
+                }
+                Ok(())
+            })
+            .and_then(|()| transaction.commit().context("commit"));
+        if result.is_err() {
+            for name in prepared {
+                if let Some(event) = self.inner.events.remove(name) {
+                    self.inner.unions.remove(&event.table);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl SqliteInner {
+    /// Creates the view of the rows of a union member, naming the columns of
+    /// the union table like the member's columns. Fails if the union table's
+    /// columns don't have the member's types.
+    pub(super) fn create_union_view(
+        &self,
+        con: &Connection,
+        name: &str,
+        table: &Table,
+        union_table: &str,
+        integer_encoding: IntegerEncoding,
+    ) -> Result<()> {
+        let stored = con
+            .prepare("SELECT name, type FROM pragma_table_info(?1) ORDER BY cid;")
+            .context("prepare table_info")?
+            .query_map((union_table,), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("query table_info")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read table_info")?;
+        let fixed = self.options.chain_id as usize + FIXED_COLUMNS_COUNT;
+        let columns = table
+            .columns
+            .iter()
+            .flat_map(|column| {
+                let type_ = match self.split_function(column.kind) {
+                    true => "BLOB",
+                    false => sql_type_name(self.sql_type(column.kind, integer_encoding)),
+                };
+                self.column_names(column)
+                    .into_iter()
+                    .map(move |name| (name, type_))
+            })
+            .chain(
+                RAW_COLUMN_NAMES
+                    .iter()
+                    .filter(|_| self.options.raw_logs)
+                    .map(|name| (name.to_string(), "BLOB")),
+            )
+            .collect::<Vec<_>>();
+        if stored.get(fixed).map(|(name, _)| name.as_str()) != Some("source_event")
+            || stored.len() != fixed + 1 + columns.len()
+        {
+            return Err(anyhow!(
+                "table {union_table} doesn't have the columns of event {name}"
+            ));
+        }
+        let mut select = stored[..fixed]
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for ((stored, stored_type), (column, type_)) in stored[fixed + 1..].iter().zip(&columns) {
+            if !stored_type.eq_ignore_ascii_case(type_) {
+                return Err(anyhow!(
+                    "column {stored} of table {union_table} is {stored_type} but column \
+                     {column} of event {name} is {type_}"
+                ));
+            }
+            select.push(format!("{stored} AS {column}"));
+        }
+        con.execute(
+            &format!(
+                "CREATE VIEW {} AS SELECT {} FROM {union_table} WHERE source_event = {};",
+                table.name,
+                select.join(", "),
+                sql_string(name)
+            ),
+            (),
+        )
+        .context("create union view")?;
+        Ok(())
+    }
+}
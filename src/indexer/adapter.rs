@@ -154,6 +154,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn decode_interleaved_indexed_fields() {
+        let indexer = Adapter::for_signature(
+            "event Transfer(address indexed from, uint256 value, address indexed to, bool flag)",
+        );
+
+        let topics = [
+            keccak!(b"Transfer(address,uint256,address,bool)"),
+            digest!("0x0000000000000000000000000101010101010101010101010101010101010101"),
+            digest!("0x0000000000000000000000000202020202020202020202020202020202020202"),
+        ];
+        let data = hex!(
+            "0000000000000000000000000000000000000000000000003a4965bf58a40000
+             0000000000000000000000000000000000000000000000000000000000000001"
+        );
+
+        // Fields are in the order of the signature, not indexed first.
+        assert_eq!(
+            indexer.decode(&topics, &data).unwrap(),
+            [
+                Value::Address(address!("0x0101010101010101010101010101010101010101")),
+                Value::Uint(Uint::new(256, uint!("4_200_000_000_000_000_000")).unwrap()),
+                Value::Address(address!("0x0202020202020202020202020202020202020202")),
+                Value::Bool(true),
+            ]
+        );
+    }
+
     #[test]
     fn decode_erc721_transfer_event() {
         let indexer = Adapter::for_signature(
@@ -5,11 +5,9 @@ mod chain;
 
 use {
     self::{adapter::Adapter, chain::Chain},
-    crate::{
-        config,
-        database::{self, Database},
-    },
+    crate::config,
     anyhow::{Context, Result},
+    arak::database::{self, Database},
     ethrpc::{
         eth,
         types::{Block, BlockSpec, BlockTag, BlockTransactions, Hydrated, LogBlocks},
@@ -171,40 +169,12 @@ where
             // Compute the database updates required:
             // - Update latest indexed blocks for the events that were queried
             // - Add the logs to the DB.
-            let mut blocks = adapters
-                .iter()
-                .copied()
-                .map(|adapter| database::EventBlock {
-                    event: adapter.name(),
-                    block: database::Block {
-                        indexed: to,
-                        finalized: finalized.number.as_u64(),
-                    },
-                })
-                .collect::<Vec<_>>();
+            let blocks = page_blocks(adapters.iter().copied(), to, finalized.number.as_u64());
             let logs = adapters
                 .into_iter()
                 .zip(results)
                 .flat_map(|(adapter, logs)| database_logs(adapter, logs))
                 .collect::<Vec<_>>();
-
-            // Add blocks and transactions.
-            blocks.extend([
-                database::EventBlock {
-                    event: "blocks",
-                    block: database::Block {
-                        indexed: to,
-                        finalized: finalized.number.as_u64(),
-                    },
-                },
-                database::EventBlock {
-                    event: "transactions",
-                    block: database::Block {
-                        indexed: to,
-                        finalized: finalized.number.as_u64(),
-                    },
-                },
-            ]);
             let (block_times, transactions) = database_block_data(block_tx_data);
             self.database
                 .update(&blocks, &logs, &block_times, &transactions)
@@ -337,6 +307,26 @@ where
     }
 }
 
+/// The blocks of the events indexed by an initialization page up to `to`,
+/// followed by the `blocks` and `transactions` pseudo-events. These can move
+/// back when an event was added with an earlier start than the others.
+fn page_blocks<'a>(
+    adapters: impl IntoIterator<Item = &'a Adapter>,
+    to: u64,
+    finalized: u64,
+) -> Vec<database::EventBlock<'a>> {
+    let block = database::Block {
+        indexed: to,
+        finalized,
+    };
+    adapters
+        .into_iter()
+        .map(Adapter::name)
+        .chain(["blocks", "transactions"])
+        .map(|event| database::EventBlock { event, block })
+        .collect()
+}
+
 fn database_block_data(
     block_data: Vec<Option<Block>>,
 ) -> (Vec<database::BlockTime>, Vec<database::Transaction>) {
@@ -396,12 +386,16 @@ fn database_logs(
             };
 
             Some(database::Log {
-                event: adapter.name(),
+                event: adapter.name().into(),
                 block_number: log.block_number.as_u64(),
                 log_index: log.log_index.as_u64(),
                 transaction_index: log.transaction_index.as_u64(),
                 address: log.address,
                 fields,
+                chain_id: None,
+                data: log.data,
+                topics: log.topics.into_iter().map(|topic| topic.0).collect(),
+                nulls: Vec::new(),
             })
         })
 }
@@ -412,7 +406,7 @@ pub fn timestamp_to_systemtime(timestamp: u64) -> SystemTime {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use {super::*, arak::database::Sqlite};
 
     #[test]
     fn date_conversions() {
@@ -425,4 +419,26 @@ mod tests {
             SystemTime::UNIX_EPOCH + Duration::from_secs(1438262788)
         );
     }
+
+    #[tokio::test]
+    async fn event_with_earlier_start() {
+        let mut database = Sqlite::in_memory(Default::default()).unwrap();
+        let late = Adapter::for_signature("event Late(uint256)");
+        let early = Adapter::for_signature("event Early(uint256)");
+        for adapter in [&late, &early] {
+            database
+                .prepare_event(adapter.name(), adapter.signature())
+                .await
+                .unwrap();
+        }
+
+        // A previous run indexed `Late` up to block 100 and `Early` was added
+        // since, starting at block 10, so the next pages end before 100.
+        for blocks in [page_blocks([&late], 100, 90), page_blocks([&early], 19, 90)] {
+            database.update(&blocks, &[], &[], &[]).await.unwrap();
+        }
+        assert_eq!(database.event_block("Early").await.unwrap().indexed, 19);
+        assert_eq!(database.event_block("Late").await.unwrap().indexed, 100);
+        assert_eq!(database.event_block("blocks").await.unwrap().indexed, 19);
+    }
 }
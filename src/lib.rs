@@ -0,0 +1,5 @@
+//! Library part of arak, for embedding the storage backends into other
+//! applications.
+
+pub mod database;
+pub mod presets;
@@ -1,7 +1,6 @@
-use database::Database;
+use arak::database::{self, Database};
 use dotenv::dotenv;
 mod config;
-mod database;
 mod indexer;
 
 use {
@@ -0,0 +1,65 @@
+//! Declarations of commonly indexed events so that they don't have to be
+//! written by hand.
+
+use solabi::abi::EventDescriptor;
+
+/// ERC-20 `Transfer(address indexed from, address indexed to, uint256 value)`.
+pub fn erc20_transfer() -> EventDescriptor {
+    parse("event Transfer(address indexed from, address indexed to, uint256 value)")
+}
+
+/// ERC-20 `Approval(address indexed owner, address indexed spender, uint256 value)`.
+pub fn erc20_approval() -> EventDescriptor {
+    parse("event Approval(address indexed owner, address indexed spender, uint256 value)")
+}
+
+/// ERC-721 `Transfer(address indexed from, address indexed to, uint256 indexed tokenId)`.
+///
+/// Has the same signature as the ERC-20 event except for the indexed token id
+/// so the two can't be told apart by topic alone.
+pub fn erc721_transfer() -> EventDescriptor {
+    parse("event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)")
+}
+
+fn parse(declaration: &str) -> EventDescriptor {
+    EventDescriptor::parse_declaration(declaration).expect("valid preset declaration")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::database::{Database, Sqlite},
+        futures::FutureExt,
+    };
+
+    #[test]
+    fn prepare() {
+        for (name, event, columns) in [
+            (
+                "erc20_transfer",
+                erc20_transfer(),
+                ["from_0", "to_1", "value_2"],
+            ),
+            (
+                "erc20_approval",
+                erc20_approval(),
+                ["owner_0", "spender_1", "value_2"],
+            ),
+            (
+                "erc721_transfer",
+                erc721_transfer(),
+                ["from_0", "to_1", "tokenId_2"],
+            ),
+        ] {
+            let mut sqlite = Sqlite::new_for_test();
+            sqlite
+                .prepare_event(name, &event)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+            let fields = sqlite.read_fields(name, &columns, 0, 0).unwrap();
+            assert!(fields.is_empty());
+        }
+    }
+}